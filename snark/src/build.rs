@@ -1,6 +1,13 @@
 extern crate gcc;
 
+use std::env;
+
 fn main() {
+    // Without libsnark, there is nothing to build or link.
+    if env::var("CARGO_FEATURE_LIBSNARK").is_err() {
+        return;
+    }
+
     println!("cargo:rustc-link-lib=gmp");
     println!("cargo:rustc-link-lib=gmpxx");
     println!("cargo:rustc-link-lib=sodium");
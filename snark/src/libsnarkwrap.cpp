@@ -89,7 +89,9 @@ void* libsnark_cs_return(
     assert(((*d / 2) * 2) == *d);
     
     // Assume radix2 evaluation domain
-    *omega = std::static_pointer_cast<basic_radix2_domain<curve_Fr>>(qap.domain)->omega;
+    auto domain = std::dynamic_pointer_cast<basic_radix2_domain<curve_Fr>>(qap.domain);
+    assert(domain);
+    *omega = domain->omega;
     *num_inputs = cs.num_inputs();
 
     return new r1cs_constraint_system<curve_Fr>(cs);
@@ -124,6 +126,25 @@ extern "C" void* libsnarkwrap_getcs_dummy(uint64_t *d, uint64_t *vars, uint64_t
     );
 }
 
+extern "C" void* libsnarkwrap_getcs_bytes(
+    const char *data, uint64_t len,
+    uint64_t *d, uint64_t *vars, uint64_t *num_inputs, curve_Fr *omega
+)
+{
+    r1cs_constraint_system<curve_Fr> cs;
+
+    std::stringstream ss(std::string(data, len));
+    ss >> cs;
+
+    return libsnark_cs_return(
+        d,
+        vars,
+        num_inputs,
+        omega,
+        cs
+    );
+}
+
 extern "C" void libsnarkwrap_dropcs(r1cs_constraint_system<curve_Fr> *cs)
 {
     delete cs;
@@ -285,6 +306,37 @@ extern "C" void libsnarkwrap_keypair_write(
     saveToFile("vk", kp->vk);
 }
 
+// Serialization, through a callback that appends to a Rust buffer
+
+typedef void (*libsnarkwrap_write)(void *ctx, const char *data, uint64_t len);
+
+extern "C" void libsnarkwrap_cs_serialize(
+    const r1cs_constraint_system<curve_Fr> *cs,
+    libsnarkwrap_write write,
+    void *ctx
+)
+{
+    std::stringstream ss;
+    ss << *cs;
+    std::string s = ss.str();
+
+    write(ctx, s.data(), s.size());
+}
+
+extern "C" void libsnarkwrap_keypair_serialize(
+    const r1cs_ppzksnark_keypair<curve_pp> *kp,
+    libsnarkwrap_write write,
+    void *ctx
+)
+{
+    std::stringstream ss;
+    ss << kp->vk;
+    ss << kp->pk;
+    std::string s = ss.str();
+
+    write(ctx, s.data(), s.size());
+}
+
 extern "C" bool libsnarkwrap_keypair_eq(
     const r1cs_ppzksnark_keypair<curve_pp> *kp1,
     const r1cs_ppzksnark_keypair<curve_pp> *kp2
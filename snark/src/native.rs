@@ -0,0 +1,430 @@
+//! The constraint system and keypairs of r1cs_ppzksnark in Rust. Keys
+//! are assembled as `libsnarkwrap_construct_keypair` does, and written
+//! as libsnark serializes them with `BINARY_OUTPUT`,
+//! `MONTGOMERY_OUTPUT` and `NO_PT_COMPRESSION` defined, so that they are
+//! interchangeable with those of the `libsnark` feature.
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::{mem, slice};
+
+use bn::*;
+
+use r1cs::R1CS;
+use qap::QAP;
+
+pub struct CS {
+    r1cs: R1CS,
+    qap: QAP,
+    pub d: usize,
+    pub num_vars: usize,
+    pub num_inputs: usize,
+    pub omega: Fr
+}
+
+impl CS {
+    pub fn from_r1cs(mut cs: R1CS) -> Result<Self, String> {
+        cs.swap_ab_if_beneficial();
+
+        let qap = try!(QAP::new(&cs));
+
+        Ok(CS {
+            d: qap.d,
+            num_vars: qap.num_vars(),
+            num_inputs: cs.num_inputs,
+            omega: qap.omega,
+            qap: qap,
+            r1cs: cs
+        })
+    }
+
+    /// Reads the constraint system from `r1cs` in the working directory,
+    /// where libsnark would.
+    pub fn from_file() -> Self {
+        let mut data = vec![];
+        if let Err(e) = File::open("r1cs").and_then(|mut f| f.read_to_end(&mut data)) {
+            panic!("could not read r1cs: {}", e);
+        }
+
+        match R1CS::read(&data).and_then(CS::from_r1cs) {
+            Ok(cs) => cs,
+            Err(e) => panic!("invalid r1cs: {}", e)
+        }
+    }
+
+    pub fn dummy() -> Self {
+        CS::from_r1cs(R1CS::dummy(250, 4)).expect("the dummy circuit should reduce to a QAP")
+    }
+
+    /// The constraint system, after the A/B swap.
+    pub fn r1cs(&self) -> R1CS {
+        self.r1cs.clone()
+    }
+
+    pub fn test_compare_tau(&self, v1: &[G1], v2: &[G2], tau: &Fr) -> bool {
+        assert_eq!(v1.len(), v2.len());
+        assert_eq!(v1.len(), self.d);
+
+        self.qap.lagrange_coeffs(*tau).iter().zip(v1.iter().zip(v2.iter())).all(|(&l, (&g1, &g2))| {
+            G1::one() * l == g1 && G2::one() * l == g2
+        })
+    }
+
+    pub fn test_eval(&self, tau: &Fr, at: &[G1], bt1: &[G1], bt2: &[G2], ct: &[G1]) -> bool {
+        assert_eq!(at.len(), bt1.len());
+        assert_eq!(bt1.len(), bt2.len());
+        assert_eq!(bt2.len(), ct.len());
+        assert_eq!(at.len(), self.num_vars);
+
+        let (a, b, c, _) = self.qap.evaluate_at(*tau);
+
+        (0..self.num_vars).all(|i| {
+            G1::one() * a[i] == at[i] &&
+            G1::one() * b[i] == bt1[i] &&
+            G2::one() * b[i] == bt2[i] &&
+            G1::one() * c[i] == ct[i]
+        })
+    }
+
+    pub fn eval(
+        &self,
+        lt1: &[G1],
+        lt2: &[G2],
+        at: &mut [G1],
+        bt1: &mut [G1],
+        bt2: &mut [G2],
+        ct: &mut [G1]
+    )
+    {
+        assert_eq!(lt1.len(), lt2.len());
+        assert_eq!(at.len(), bt1.len());
+        assert_eq!(bt1.len(), bt2.len());
+        assert_eq!(bt2.len(), ct.len());
+
+        self.qap.eval(lt1, lt2, at, bt1, bt2, ct)
+    }
+}
+
+/// The nonzero elements of a vector of knowledge commitments `(g, h)`,
+/// with their indices.
+struct KnowledgeCommitments<T1, T2> {
+    domain_size: usize,
+    indices: Vec<usize>,
+    values: Vec<(T1, T2)>
+}
+
+impl<T1: Group, T2: Group> KnowledgeCommitments<T1, T2> {
+    /// Leaves out the commitments before `start` as well as the zero ones.
+    fn new(g: &[T1], h: &[T2], start: usize) -> Self {
+        assert_eq!(g.len(), h.len());
+
+        let mut kc = KnowledgeCommitments {
+            domain_size: g.len(),
+            indices: vec![],
+            values: vec![]
+        };
+
+        for (i, (&g, &h)) in g.iter().zip(h.iter()).enumerate() {
+            if i >= start && !(g.is_zero() && h.is_zero()) {
+                kc.indices.push(i);
+                kc.values.push((g, h));
+            }
+        }
+
+        kc
+    }
+}
+
+struct ProvingKey {
+    a_query: KnowledgeCommitments<G1, G1>,
+    b_query: KnowledgeCommitments<G2, G1>,
+    c_query: KnowledgeCommitments<G1, G1>,
+    h_query: Vec<G1>,
+    k_query: Vec<G1>
+}
+
+struct VerifyingKey {
+    alpha_a_g2: G2,
+    alpha_b_g1: G1,
+    alpha_c_g2: G2,
+    gamma_g2: G2,
+    gamma_beta_g1: G1,
+    gamma_beta_g2: G2,
+    rc_z_g2: G2,
+    ic_base: G1,
+    ic: Vec<G1>
+}
+
+pub struct Keypair {
+    pk: ProvingKey,
+    vk: VerifyingKey
+}
+
+impl PartialEq for Keypair {
+    fn eq(&self, other: &Keypair) -> bool {
+        self.to_bytes() == other.to_bytes()
+    }
+}
+
+impl Keypair {
+    /// The verifying key and then the proving key, as libsnark
+    /// serializes them.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = vec![];
+        self.vk.write(&mut out);
+        self.pk.write(&mut out);
+
+        out
+    }
+
+    pub fn write_to_disk(&self) {
+        for &(path, ref data) in &[("pk", self.pk.to_bytes()), ("vk", self.vk.to_bytes())] {
+            if let Err(e) = File::create(path).and_then(|mut f| f.write_all(data)) {
+                panic!("could not write {}: {}", path, e);
+            }
+        }
+    }
+
+    pub fn from(
+        cs: &CS,
+        pk_a: &[G1],
+        pk_a_prime: &[G1],
+        pk_b: &[G2],
+        pk_b_prime: &[G1],
+        pk_c: &[G1],
+        pk_c_prime: &[G1],
+        pk_k: &[G1],
+        pk_h: &[G1],
+        vk_a: &G2,
+        vk_b: &G1,
+        vk_c: &G2,
+        vk_gamma: &G2,
+        vk_beta_gamma_1: &G1,
+        vk_beta_gamma_2: &G2,
+        vk_z: &G2
+    ) -> Keypair
+    {
+        assert_eq!(pk_a.len(), pk_a_prime.len());
+        assert_eq!(pk_a.len(), pk_b.len());
+        assert_eq!(pk_a.len(), pk_b_prime.len());
+        assert_eq!(pk_a.len(), pk_c.len());
+        assert_eq!(pk_a.len(), pk_c_prime.len());
+        assert!(pk_a.len() > cs.num_inputs + 1);
+
+        Keypair {
+            pk: ProvingKey {
+                // The inputs' part of the A query is in the verifying key.
+                a_query: KnowledgeCommitments::new(pk_a, pk_a_prime, cs.num_inputs + 1),
+                b_query: KnowledgeCommitments::new(pk_b, pk_b_prime, 0),
+                c_query: KnowledgeCommitments::new(pk_c, pk_c_prime, 0),
+                h_query: pk_h.to_vec(),
+                k_query: pk_k.to_vec()
+            },
+            vk: VerifyingKey {
+                alpha_a_g2: *vk_a,
+                alpha_b_g1: *vk_b,
+                alpha_c_g2: *vk_c,
+                gamma_g2: *vk_gamma,
+                gamma_beta_g1: *vk_beta_gamma_1,
+                gamma_beta_g2: *vk_beta_gamma_2,
+                rc_z_g2: *vk_z,
+                ic_base: pk_a[0],
+                ic: pk_a[1..cs.num_inputs + 1].to_vec()
+            }
+        }
+    }
+
+    /// Generates the keypair directly from the secrets, as libsnark's
+    /// `r1cs_ppzksnark_generator` does.
+    pub fn generate(
+        cs: &CS,
+        tau: &Fr,
+        alpha_a: &Fr,
+        alpha_b: &Fr,
+        alpha_c: &Fr,
+        rho_a: &Fr,
+        rho_b: &Fr,
+        beta: &Fr,
+        gamma: &Fr
+    ) -> Keypair {
+        let (mut at, mut bt, mut ct, zt) = cs.qap.evaluate_at(*tau);
+        let rho_c = *rho_a * *rho_b;
+
+        let mut kt: Vec<Fr> = (0..cs.num_vars).map(|i| {
+            *beta * (*rho_a * at[i] + *rho_b * bt[i] + rho_c * ct[i])
+        }).collect();
+        kt.push(*beta * *rho_a * zt);
+        kt.push(*beta * *rho_b * zt);
+        kt.push(*beta * rho_c * zt);
+
+        at.push(zt);
+        bt.push(zt);
+        ct.push(zt);
+
+        let mut ht = vec![];
+        let mut ti = Fr::one();
+        for _ in 0..cs.d + 1 {
+            ht.push(ti);
+            ti = ti * *tau;
+        }
+
+        let g1 = |v: &[Fr], c: Fr| -> Vec<G1> { v.iter().map(|&x| G1::one() * (c * x)).collect() };
+        let g2 = |v: &[Fr], c: Fr| -> Vec<G2> { v.iter().map(|&x| G2::one() * (c * x)).collect() };
+
+        Keypair::from(
+            cs,
+            &g1(&at, *rho_a),
+            &g1(&at, *rho_a * *alpha_a),
+            &g2(&bt, *rho_b),
+            &g1(&bt, *rho_b * *alpha_b),
+            &g1(&ct, rho_c),
+            &g1(&ct, rho_c * *alpha_c),
+            &g1(&kt, Fr::one()),
+            &g1(&ht, Fr::one()),
+            &(G2::one() * *alpha_a),
+            &(G1::one() * *alpha_b),
+            &(G2::one() * *alpha_c),
+            &(G2::one() * *gamma),
+            &(G1::one() * (*gamma * *beta)),
+            &(G2::one() * (*gamma * *beta)),
+            &(G2::one() * (rho_c * zt))
+        )
+    }
+}
+
+impl ProvingKey {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut out = vec![];
+        self.write(&mut out);
+
+        out
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        self.a_query.write(out);
+        self.b_query.write(out);
+        self.c_query.write(out);
+        self.h_query.write(out);
+        self.k_query.write(out);
+    }
+}
+
+impl VerifyingKey {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut out = vec![];
+        self.write(&mut out);
+
+        out
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        self.alpha_a_g2.write(out);
+        self.alpha_b_g1.write(out);
+        self.alpha_c_g2.write(out);
+        self.gamma_g2.write(out);
+        self.gamma_beta_g1.write(out);
+        self.gamma_beta_g2.write(out);
+        self.rc_z_g2.write(out);
+
+        // An accumulation vector, whose rest is a sparse vector with
+        // every index present.
+        self.ic_base.write(out);
+        write_size(out, self.ic.len());
+        write_size(out, self.ic.len());
+        for i in 0..self.ic.len() {
+            write_size(out, i);
+        }
+        self.ic.write(out);
+    }
+}
+
+/// Writing in libsnark's serialization, where sizes are decimal and
+/// followed by a newline, and everything else is binary without
+/// separators.
+trait Serialize {
+    fn write(&self, out: &mut Vec<u8>);
+}
+
+fn write_size(out: &mut Vec<u8>, n: usize) {
+    write!(out, "{}\n", n).unwrap();
+}
+
+impl<T: Serialize> Serialize for Vec<T> {
+    fn write(&self, out: &mut Vec<u8>) {
+        write_size(out, self.len());
+        for t in self {
+            t.write(out);
+        }
+    }
+}
+
+impl<T1: Serialize, T2: Serialize> Serialize for KnowledgeCommitments<T1, T2> {
+    fn write(&self, out: &mut Vec<u8>) {
+        write_size(out, self.domain_size);
+        write_size(out, self.indices.len());
+        for &i in &self.indices {
+            write_size(out, i);
+        }
+        write_size(out, self.values.len());
+        for &(ref g, ref h) in &self.values {
+            g.write(out);
+            h.write(out);
+        }
+    }
+}
+
+/// One in `Fq`, in Montgomery form.
+const FQ_ONE: [u64; 4] = [
+    0xd35d438dc58f0d9d,
+    0x0a78eb28f5c70b3d,
+    0x666ea36f7879462c,
+    0x0e0a77c19a07df2f
+];
+
+impl Serialize for G1 {
+    fn write(&self, out: &mut Vec<u8>) {
+        write_affine(out, *self, &FQ_ONE)
+    }
+}
+
+impl Serialize for G2 {
+    fn write(&self, out: &mut Vec<u8>) {
+        let mut one = FQ_ONE.to_vec();
+        one.extend_from_slice(&[0; 4]);
+
+        write_affine(out, *self, &one)
+    }
+}
+
+/// Writes whether `g` is zero and its affine coordinates, where
+/// libsnark puts the point at infinity at `(0, one)`.
+fn write_affine<G: Group>(out: &mut Vec<u8>, mut g: G, one: &[u64]) {
+    let n = one.len();
+
+    // bn lays out a point as libsnark does, as its Jacobian coordinates
+    // in Montgomery form, which the FFI relies on too.
+    assert_eq!(mem::size_of::<G>(), 3 * n * 8);
+
+    if g.is_zero() {
+        write_zero_flag(out, true);
+        write_limbs(out, &vec![0; n]);
+        write_limbs(out, one);
+    } else {
+        g.normalize();
+        let limbs = unsafe { slice::from_raw_parts(&g as *const G as *const u64, 3 * n) };
+
+        write_zero_flag(out, false);
+        write_limbs(out, &limbs[0..n]);
+        write_limbs(out, &limbs[n..2 * n]);
+    }
+}
+
+fn write_zero_flag(out: &mut Vec<u8>, is_zero: bool) {
+    out.push(if is_zero { b'1' } else { b'0' });
+}
+
+fn write_limbs(out: &mut Vec<u8>, limbs: &[u64]) {
+    for limb in limbs {
+        out.extend((0..8).map(|i| (limb >> (8 * i)) as u8));
+    }
+}
@@ -0,0 +1,253 @@
+//! The reduction of a rank-1 constraint system to a quadratic
+//! arithmetic program, as libsnark's `r1cs_to_qap_instance_map`
+//! performs it, restricted to the radix-2 evaluation domains the rest
+//! of the ceremony assumes.
+
+use bn::*;
+
+use r1cs::R1CS;
+
+/// The largest power of two dividing `r - 1`.
+const FR_TWO_ADICITY: usize = 28;
+
+/// A primitive `2^28`th root of unity in `Fr`, as libsnark picks it.
+const FR_ROOT_OF_UNITY: &'static str = "19103219067921713944291392827692070036145651957329286315305642004821462161904";
+
+pub struct QAP {
+    /// The size of the evaluation domain.
+    pub d: usize,
+    /// The generator of the evaluation domain.
+    pub omega: Fr,
+    /// For each variable, its coefficients in the lagrange basis of the
+    /// domain, as the index of the basis polynomial and the coefficient.
+    a: Vec<Vec<(usize, Fr)>>,
+    b: Vec<Vec<(usize, Fr)>>,
+    c: Vec<Vec<(usize, Fr)>>
+}
+
+impl QAP {
+    pub fn new(cs: &R1CS) -> Result<QAP, String> {
+        let num_vars = cs.num_variables() + 1;
+        let d = try!(domain_size(cs.constraints.len() + cs.num_inputs + 1));
+
+        let mut qap = QAP {
+            d: d,
+            omega: root_of_unity(d),
+            a: vec![vec![]; num_vars],
+            b: vec![vec![]; num_vars],
+            c: vec![vec![]; num_vars]
+        };
+
+        // The constraints `input_i * 0 = 0`, for the soundness of the
+        // inputs, come after the others.
+        for i in 0..cs.num_inputs + 1 {
+            qap.a[i].push((cs.constraints.len() + i, Fr::one()));
+        }
+
+        for (j, constraint) in cs.constraints.iter().enumerate() {
+            for &(i, coeff) in &constraint.a {
+                try!(check_variable(i, num_vars));
+                qap.a[i].push((j, coeff));
+            }
+            for &(i, coeff) in &constraint.b {
+                try!(check_variable(i, num_vars));
+                qap.b[i].push((j, coeff));
+            }
+            for &(i, coeff) in &constraint.c {
+                try!(check_variable(i, num_vars));
+                qap.c[i].push((j, coeff));
+            }
+        }
+
+        Ok(qap)
+    }
+
+    /// The number of variables, including the constant one.
+    pub fn num_vars(&self) -> usize {
+        self.a.len()
+    }
+
+    /// Adds `A_i`, `B_i` and `C_i` of each variable to `at`, `bt1`,
+    /// `bt2` and `ct`, from the lagrange basis in `lc1` and `lc2`.
+    pub fn eval(
+        &self,
+        lc1: &[G1],
+        lc2: &[G2],
+        at: &mut [G1],
+        bt1: &mut [G1],
+        bt2: &mut [G2],
+        ct: &mut [G1]
+    )
+    {
+        assert_eq!(lc1.len(), self.d);
+        assert_eq!(lc2.len(), self.d);
+        assert_eq!(at.len(), self.num_vars());
+        assert_eq!(bt1.len(), self.num_vars());
+        assert_eq!(bt2.len(), self.num_vars());
+        assert_eq!(ct.len(), self.num_vars());
+
+        for (i, terms) in self.a.iter().enumerate() {
+            for &(j, coeff) in terms {
+                at[i] = at[i] + lc1[j] * coeff;
+            }
+        }
+
+        for (i, terms) in self.b.iter().enumerate() {
+            for &(j, coeff) in terms {
+                bt1[i] = bt1[i] + lc1[j] * coeff;
+                bt2[i] = bt2[i] + lc2[j] * coeff;
+            }
+        }
+
+        for (i, terms) in self.c.iter().enumerate() {
+            for &(j, coeff) in terms {
+                ct[i] = ct[i] + lc1[j] * coeff;
+            }
+        }
+    }
+
+    /// The lagrange basis polynomials of the domain, evaluated at `t`.
+    pub fn lagrange_coeffs(&self, t: Fr) -> Vec<Fr> {
+        let m = self.d;
+        let mut u = vec![Fr::zero(); m];
+
+        let tm = t.pow(fr(m));
+        if tm == Fr::one() {
+            // t is in the domain, where only its own polynomial is one.
+            let mut r = Fr::one();
+            for i in 0..m {
+                if r == t {
+                    u[i] = Fr::one();
+                    break;
+                }
+                r = r * self.omega;
+            }
+
+            return u;
+        }
+
+        // L_i(t) = Z(t) omega^i / (m (t - omega^i)), where Z(t) = t^m - 1
+        let mut l = (tm - Fr::one()) * fr(m).inverse().unwrap();
+        let mut r = Fr::one();
+        for i in 0..m {
+            u[i] = l * (t - r).inverse().unwrap();
+            l = l * self.omega;
+            r = r * self.omega;
+        }
+
+        u
+    }
+
+    /// `A_i(t)`, `B_i(t)` and `C_i(t)` for each variable, and `Z(t)`.
+    pub fn evaluate_at(&self, t: Fr) -> (Vec<Fr>, Vec<Fr>, Vec<Fr>, Fr) {
+        let u = self.lagrange_coeffs(t);
+        let evaluate = |polys: &[Vec<(usize, Fr)>]| -> Vec<Fr> {
+            polys.iter().map(|terms| {
+                terms.iter().fold(Fr::zero(), |acc, &(j, coeff)| acc + coeff * u[j])
+            }).collect()
+        };
+
+        (evaluate(&self.a), evaluate(&self.b), evaluate(&self.c), t.pow(fr(self.d)) - Fr::one())
+    }
+}
+
+fn check_variable(i: usize, num_vars: usize) -> Result<(), String> {
+    if i < num_vars {
+        Ok(())
+    } else {
+        Err(format!("variable {} out of range", i))
+    }
+}
+
+fn fr(n: usize) -> Fr {
+    Fr::from_str(&format!("{}", n)).unwrap()
+}
+
+/// libsnark's `log2`: the smallest `k` with `2^k >= n`.
+fn log2(n: usize) -> usize {
+    n.next_power_of_two().trailing_zeros() as usize
+}
+
+/// The size of the domain libsnark evaluates the QAP over, for
+/// `min_size` constraints, if it is a basic radix-2 domain. This takes
+/// the branches of libsnark's `get_evaluation_domain` in its order.
+fn domain_size(min_size: usize) -> Result<usize, String> {
+    let log_min_size = log2(min_size);
+
+    if min_size <= 1 || log_min_size > FR_TWO_ADICITY + 1 {
+        return Err(format!("libsnark has no evaluation domain for {} constraints", min_size));
+    }
+
+    if min_size == 1 << log_min_size {
+        if log_min_size == FR_TWO_ADICITY + 1 {
+            return Err(format!("libsnark would use an extended radix-2 domain for {} constraints", min_size));
+        }
+
+        Ok(min_size)
+    } else {
+        let big = 1 << (log_min_size - 1);
+        let small = min_size - big;
+        let rounded_small = 1 << log2(small);
+
+        if big != rounded_small {
+            return Err(format!("libsnark would use a step radix-2 domain for {} constraints", min_size));
+        }
+        if log2(big + rounded_small) >= FR_TWO_ADICITY + 1 {
+            return Err(format!("libsnark would use an extended radix-2 domain for {} constraints", min_size));
+        }
+
+        Ok(big + rounded_small)
+    }
+}
+
+/// The generator of the radix-2 domain of size `d`.
+fn root_of_unity(d: usize) -> Fr {
+    let mut omega = Fr::from_str(FR_ROOT_OF_UNITY).unwrap();
+
+    let mut size = 1 << FR_TWO_ADICITY;
+    while size > d {
+        omega = omega * omega;
+        size /= 2;
+    }
+
+    omega
+}
+
+#[test]
+fn domains() {
+    assert_eq!(domain_size(255), Ok(256));
+    assert_eq!(domain_size(256), Ok(256));
+    assert_eq!(domain_size(97), Ok(128));
+    assert!(domain_size(96).is_err());
+    assert!(domain_size(150).is_err());
+    assert!(domain_size(1).is_err());
+    assert_eq!(domain_size(1 << FR_TWO_ADICITY), Ok(1 << FR_TWO_ADICITY));
+    assert!(domain_size((1 << FR_TWO_ADICITY) + 1).is_err());
+    assert!(domain_size(1 << (FR_TWO_ADICITY + 1)).is_err());
+
+    let omega = root_of_unity(256);
+    assert!(omega.pow(fr(256)) == Fr::one());
+    assert!(omega.pow(fr(128)) != Fr::one());
+}
+
+#[test]
+fn lagrange_coeffs_interpolate() {
+    let qap = QAP::new(&R1CS::dummy(10, 4)).unwrap();
+    let t = Fr::from_str("1234567").unwrap();
+
+    // The lagrange basis sums to one, and interpolates x to x.
+    let u = qap.lagrange_coeffs(t);
+    assert!(u.iter().fold(Fr::zero(), |acc, &l| acc + l) == Fr::one());
+
+    let mut r = Fr::one();
+    let mut x = Fr::zero();
+    for l in &u {
+        x = x + *l * r;
+        r = r * qap.omega;
+    }
+    assert!(x == t);
+
+    // Within the domain
+    let u = qap.lagrange_coeffs(qap.omega);
+    assert!(u[1] == Fr::one() && u[0] == Fr::zero());
+}
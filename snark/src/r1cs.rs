@@ -0,0 +1,257 @@
+//! Rank-1 constraint systems, as libsnark represents and serializes
+//! them.
+
+use std::mem;
+
+use bn::*;
+
+/// The terms of a linear combination, as the index of each variable
+/// and its coefficient. Variable 0 is the constant one.
+pub type LinearCombination = Vec<(usize, Fr)>;
+
+/// The constraint `<a, x> * <b, x> = <c, x>`.
+#[derive(Clone, PartialEq)]
+pub struct Constraint {
+    pub a: LinearCombination,
+    pub b: LinearCombination,
+    pub c: LinearCombination
+}
+
+#[derive(Clone, PartialEq)]
+pub struct R1CS {
+    pub num_inputs: usize,
+    pub num_aux: usize,
+    pub constraints: Vec<Constraint>
+}
+
+/// The modulus of `Fr`, in little-endian limbs.
+const FR_MODULUS: [u64; 4] = [
+    0x43e1f593f0000001,
+    0x2833e84879b97091,
+    0xb85045b68181585d,
+    0x30644e72e131a029
+];
+
+impl R1CS {
+    /// The number of variables, besides the constant one.
+    pub fn num_variables(&self) -> usize {
+        self.num_inputs + self.num_aux
+    }
+
+    /// Reads a constraint system as libsnark writes it, with
+    /// `BINARY_OUTPUT` and `MONTGOMERY_OUTPUT` defined.
+    pub fn read(data: &[u8]) -> Result<R1CS, String> {
+        let mut r = Reader { data: data, pos: 0 };
+
+        let num_inputs = try!(r.number());
+        let num_aux = try!(r.number());
+        let num_constraints = try!(r.number());
+        try!(r.newline());
+
+        let mut cs = R1CS {
+            num_inputs: num_inputs,
+            num_aux: num_aux,
+            constraints: vec![]
+        };
+
+        for _ in 0..num_constraints {
+            let a = try!(r.linear_combination(cs.num_variables()));
+            let b = try!(r.linear_combination(cs.num_variables()));
+            let c = try!(r.linear_combination(cs.num_variables()));
+
+            cs.constraints.push(Constraint { a: a, b: b, c: c });
+        }
+
+        Ok(cs)
+    }
+
+    /// Writes the constraint system as libsnark does, so that `read`
+    /// returns it unchanged.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = format!("{}\n{}\n{}\n", self.num_inputs, self.num_aux, self.constraints.len()).into_bytes();
+
+        for c in &self.constraints {
+            for lc in &[&c.a, &c.b, &c.c] {
+                data.extend(format!("{}\n", lc.len()).bytes());
+                for &(index, coeff) in lc.iter() {
+                    data.extend(format!("{}\n", index).bytes());
+                    let limbs: [u64; 4] = unsafe { mem::transmute(coeff) };
+                    for limb in limbs.iter() {
+                        data.extend((0..8).map(|j| (limb >> (8 * j)) as u8));
+                    }
+                }
+            }
+        }
+
+        data
+    }
+
+    /// The constraint system of libsnark's
+    /// `generate_r1cs_example_with_field_input`, which is the same every
+    /// time; only its satisfying assignment is random.
+    pub fn dummy(num_constraints: usize, num_inputs: usize) -> R1CS {
+        assert!(num_inputs <= num_constraints + 2);
+
+        let one = Fr::one();
+        let mut cs = R1CS {
+            num_inputs: num_inputs,
+            num_aux: 2 + num_constraints - num_inputs,
+            constraints: vec![]
+        };
+
+        for i in 0..num_constraints - 1 {
+            let (a, b) = if i % 2 == 1 {
+                // a * b = c
+                (vec![(i + 1, one)], vec![(i + 2, one)])
+            } else {
+                // a + b = c
+                (vec![(i + 1, one), (i + 2, one)], vec![(0, one)])
+            };
+
+            cs.constraints.push(Constraint {
+                a: a,
+                b: b,
+                c: vec![(i + 3, one)]
+            });
+        }
+
+        // The square of the sum of the variables
+        let n = cs.num_variables();
+        cs.constraints.push(Constraint {
+            a: (1..n).map(|i| (i, one)).collect(),
+            b: (1..n).map(|i| (i, one)).collect(),
+            c: vec![(n, one)]
+        });
+
+        cs
+    }
+
+    /// Swaps A and B in every constraint if B involves more variables,
+    /// as libsnark's `swap_AB_if_beneficial` does, since the B query is
+    /// the more expensive one for the prover.
+    pub fn swap_ab_if_beneficial(&mut self) {
+        let n = self.num_variables() + 1;
+        let mut touched_by_a = vec![false; n];
+        let mut touched_by_b = vec![false; n];
+
+        for c in &self.constraints {
+            for &(i, _) in &c.a {
+                touched_by_a[i] = true;
+            }
+            for &(i, _) in &c.b {
+                touched_by_b[i] = true;
+            }
+        }
+
+        let count = |touched: &[bool]| touched.iter().filter(|&&t| t).count();
+
+        if count(&touched_by_b) > count(&touched_by_a) {
+            for c in &mut self.constraints {
+                mem::swap(&mut c.a, &mut c.b);
+            }
+        }
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize
+}
+
+impl<'a> Reader<'a> {
+    /// A decimal number, after any whitespace, as `operator>>` reads it.
+    fn number(&mut self) -> Result<usize, String> {
+        while self.pos < self.data.len() && (self.data[self.pos] as char).is_whitespace() {
+            self.pos += 1;
+        }
+
+        let start = self.pos;
+        let mut n: usize = 0;
+        while self.pos < self.data.len() && (self.data[self.pos] as char).is_digit(10) {
+            let digit = (self.data[self.pos] - b'0') as usize;
+            n = try!(n.checked_mul(10).and_then(|n| n.checked_add(digit))
+                      .ok_or_else(|| format!("number too large at byte {}", start)));
+            self.pos += 1;
+        }
+
+        if self.pos == start {
+            return Err(format!("expected a number at byte {}", start));
+        }
+
+        Ok(n)
+    }
+
+    fn newline(&mut self) -> Result<(), String> {
+        if self.data.get(self.pos) != Some(&b'\n') {
+            return Err(format!("expected a newline at byte {}", self.pos));
+        }
+        self.pos += 1;
+
+        Ok(())
+    }
+
+    /// A field element, as the raw limbs of its Montgomery form.
+    fn field(&mut self) -> Result<Fr, String> {
+        if self.data.len() - self.pos < 32 {
+            return Err(format!("expected a field element at byte {}", self.pos));
+        }
+
+        let mut limbs = [0u64; 4];
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            for j in 0..8 {
+                *limb |= (self.data[self.pos + i * 8 + j] as u64) << (8 * j);
+            }
+        }
+
+        // Compare from the most significant limb down.
+        if limbs.iter().rev().cmp(FR_MODULUS.iter().rev()) != ::std::cmp::Ordering::Less {
+            return Err(format!("field element out of range at byte {}", self.pos));
+        }
+        self.pos += 32;
+
+        // bn stores `Fr` in Montgomery form in the same limbs as
+        // libsnark, which the FFI relies on too.
+        assert_eq!(mem::size_of::<Fr>(), mem::size_of::<[u64; 4]>());
+        Ok(unsafe { mem::transmute::<[u64; 4], Fr>(limbs) })
+    }
+
+    fn linear_combination(&mut self, num_variables: usize) -> Result<LinearCombination, String> {
+        let terms = try!(self.number());
+        try!(self.newline());
+
+        let mut lc = vec![];
+        for _ in 0..terms {
+            let index = try!(self.number());
+            try!(self.newline());
+            if index > num_variables {
+                return Err(format!("variable {} out of range at byte {}", index, self.pos));
+            }
+
+            let coeff = try!(self.field());
+
+            lc.push((index, coeff));
+        }
+
+        Ok(lc)
+    }
+}
+
+#[test]
+fn read_dummy() {
+    let cs = R1CS::dummy(10, 4);
+
+    let mut data = cs.to_bytes();
+    assert!(data.starts_with(b"4\n8\n10\n"));
+
+    assert!(R1CS::read(&data).unwrap() == cs);
+    assert!(R1CS::read(&data[0..data.len() - 1]).is_err());
+
+    // A coefficient equal to the modulus
+    let last = data.len() - 32;
+    for (i, limb) in FR_MODULUS.iter().enumerate() {
+        for j in 0..8 {
+            data[last + i * 8 + j] = (limb >> (8 * j)) as u8;
+        }
+    }
+    assert!(R1CS::read(&data).is_err());
+}
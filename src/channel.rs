@@ -0,0 +1,504 @@
+//! An authenticated, encrypted channel between the coordinator and the
+//! players' network clients.
+//!
+//! The handshake has the message pattern of Noise's `XK`: the network
+//! client pins the coordinator's static key in advance, and proves
+//! possession of its own long-term key during the handshake. The
+//! coordinator identifies players by that key, so a session can only be
+//! resumed by the player who started it. The handshake uses X25519,
+//! ChaCha20-Poly1305 and BLAKE2s, after which `SecureStream` carries the
+//! protocol messages in encrypted, length-prefixed frames.
+//!
+//! It is not a Noise protocol, and does not interoperate with Noise
+//! implementations: keys are derived with keyed BLAKE2s rather than
+//! HKDF, and ChaCha20-Poly1305 is the original construction with a
+//! 64-bit nonce rather than the IETF one.
+
+use std::cmp;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+
+use rand::{OsRng, Rng};
+use blake2_rfc::blake2s::{Blake2s, blake2s};
+use byteorder::{BigEndian, LittleEndian, ByteOrder};
+use crypto::curve25519::{curve25519, curve25519_base};
+use crypto::chacha20poly1305::ChaCha20Poly1305;
+use crypto::aead::{AeadEncryptor, AeadDecryptor};
+use rustc_serialize::hex::{ToHex, FromHex};
+
+const PROTOCOL_NAME: &'static [u8] = b"zcash-mpc XK X25519 ChaCha20Poly1305-64 keyed-BLAKE2s";
+
+const KEY_LEN: usize = 32;
+const TAG_LEN: usize = 16;
+
+/// The largest amount of plaintext sent in a single frame.
+const MAX_FRAME: usize = 1 << 16;
+
+/// The public half of a static key, which identifies its owner.
+pub type PeerKey = [u8; KEY_LEN];
+
+/// A long-term X25519 keypair.
+pub struct StaticKey {
+    secret: [u8; KEY_LEN],
+    public: PeerKey
+}
+
+impl StaticKey {
+    pub fn generate() -> Result<StaticKey, String> {
+        let mut rng = try!(OsRng::new().map_err(|e| format!("could not open the OS random number generator: {}", e)));
+        let mut secret = [0; KEY_LEN];
+        rng.fill_bytes(&mut secret);
+
+        Ok(StaticKey::from_secret(secret))
+    }
+
+    fn from_secret(secret: [u8; KEY_LEN]) -> StaticKey {
+        StaticKey {
+            public: curve25519_base(&secret),
+            secret: secret
+        }
+    }
+
+    pub fn public(&self) -> &PeerKey {
+        &self.public
+    }
+
+    /// Loads the key stored at `path`, or generates a new one and stores
+    /// it there if the file does not exist yet.
+    pub fn load_or_generate(path: &str) -> Result<StaticKey, String> {
+        match File::open(path) {
+            Ok(mut f) => {
+                let mut contents = String::new();
+                try!(f.read_to_string(&mut contents).map_err(|e| format!("could not read {}: {}", path, e)));
+
+                let secret = try!(parse_key(contents.trim()).map_err(|e| format!("{}: {}", path, e)));
+
+                Ok(StaticKey::from_secret(secret))
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                let key = try!(StaticKey::generate());
+
+                let mut options = OpenOptions::new();
+                options.write(true).create_new(true);
+                #[cfg(unix)]
+                {
+                    use std::os::unix::fs::OpenOptionsExt;
+                    options.mode(0o600);
+                }
+
+                let mut f = try!(options.open(path).map_err(|e| format!("could not create {}: {}", path, e)));
+                try!(writeln!(f, "{}", key.secret.to_hex()).map_err(|e| format!("could not write {}: {}", path, e)));
+
+                Ok(key)
+            },
+            Err(e) => Err(format!("could not open {}: {}", path, e))
+        }
+    }
+}
+
+/// Parses a hex encoded key.
+pub fn parse_key(hex: &str) -> Result<[u8; KEY_LEN], String> {
+    let bytes = try!(hex.from_hex().map_err(|_| "key is not valid hex".to_string()));
+
+    if bytes.len() != KEY_LEN {
+        return Err(format!("key must be {} bytes long", KEY_LEN));
+    }
+
+    let mut key = [0; KEY_LEN];
+    key.copy_from_slice(&bytes);
+
+    Ok(key)
+}
+
+fn dh(secret: &[u8; KEY_LEN], public: &[u8; KEY_LEN]) -> Result<[u8; KEY_LEN], String> {
+    let shared = curve25519(secret, public);
+
+    // A low order point from the other side would make the shared
+    // secret predictable.
+    if shared.iter().all(|b| *b == 0) {
+        return Err("invalid public key".into());
+    }
+
+    Ok(shared)
+}
+
+fn to_array(bytes: &[u8]) -> [u8; KEY_LEN] {
+    let mut array = [0; KEY_LEN];
+    array.copy_from_slice(bytes);
+
+    array
+}
+
+/// Derives two keys from the chaining key and new input key material,
+/// in the manner of HKDF but with keyed BLAKE2s in place of HMAC.
+fn kdf(ck: &[u8; KEY_LEN], ikm: &[u8]) -> ([u8; KEY_LEN], [u8; KEY_LEN]) {
+    let temp = blake2s(KEY_LEN, ck, ikm);
+    let out1 = blake2s(KEY_LEN, temp.as_bytes(), &[1]);
+
+    let mut input = out1.as_bytes().to_vec();
+    input.push(2);
+    let out2 = blake2s(KEY_LEN, temp.as_bytes(), &input);
+
+    (to_array(out1.as_bytes()), to_array(out2.as_bytes()))
+}
+
+struct CipherState {
+    k: [u8; KEY_LEN],
+    n: u64
+}
+
+impl CipherState {
+    fn new(k: [u8; KEY_LEN]) -> CipherState {
+        CipherState {
+            k: k,
+            n: 0
+        }
+    }
+
+    fn next_nonce(&mut self) -> [u8; 8] {
+        let mut nonce = [0; 8];
+        LittleEndian::write_u64(&mut nonce, self.n);
+        self.n += 1;
+
+        nonce
+    }
+
+    fn encrypt(&mut self, ad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+        let nonce = self.next_nonce();
+        let mut out = vec![0; plaintext.len() + TAG_LEN];
+
+        {
+            let (ciphertext, tag) = out.split_at_mut(plaintext.len());
+            ChaCha20Poly1305::new(&self.k, &nonce, ad).encrypt(plaintext, ciphertext, tag);
+        }
+
+        out
+    }
+
+    fn decrypt(&mut self, ad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, String> {
+        if ciphertext.len() < TAG_LEN {
+            return Err("message is too short".into());
+        }
+
+        let nonce = self.next_nonce();
+        let (ciphertext, tag) = ciphertext.split_at(ciphertext.len() - TAG_LEN);
+        let mut out = vec![0; ciphertext.len()];
+
+        if ChaCha20Poly1305::new(&self.k, &nonce, ad).decrypt(ciphertext, &mut out, tag) {
+            Ok(out)
+        } else {
+            Err("could not authenticate message".into())
+        }
+    }
+}
+
+/// The running hash and chaining key of the handshake.
+struct SymmetricState {
+    ck: [u8; KEY_LEN],
+    h: [u8; KEY_LEN],
+    cipher: Option<CipherState>
+}
+
+impl SymmetricState {
+    fn new() -> SymmetricState {
+        let h = to_array(blake2s(KEY_LEN, &[], PROTOCOL_NAME).as_bytes());
+
+        SymmetricState {
+            ck: h,
+            h: h,
+            cipher: None
+        }
+    }
+
+    fn mix_hash(&mut self, data: &[u8]) {
+        let mut hasher = Blake2s::new(KEY_LEN);
+        hasher.update(&self.h);
+        hasher.update(data);
+
+        self.h = to_array(hasher.finalize().as_bytes());
+    }
+
+    fn mix_key(&mut self, ikm: &[u8]) {
+        let (ck, k) = kdf(&self.ck, ikm);
+
+        self.ck = ck;
+        self.cipher = Some(CipherState::new(k));
+    }
+
+    fn encrypt_and_hash(&mut self, plaintext: &[u8]) -> Vec<u8> {
+        let ciphertext = match self.cipher {
+            Some(ref mut cipher) => cipher.encrypt(&self.h, plaintext),
+            None => plaintext.to_vec()
+        };
+        self.mix_hash(&ciphertext);
+
+        ciphertext
+    }
+
+    fn decrypt_and_hash(&mut self, ciphertext: &[u8]) -> Result<Vec<u8>, String> {
+        let plaintext = match self.cipher {
+            Some(ref mut cipher) => try!(cipher.decrypt(&self.h, ciphertext)),
+            None => ciphertext.to_vec()
+        };
+        self.mix_hash(ciphertext);
+
+        Ok(plaintext)
+    }
+
+    /// The keys for each direction of the transport, the initiator's
+    /// sending key first.
+    fn split(&self) -> (CipherState, CipherState) {
+        let (k1, k2) = kdf(&self.ck, &[]);
+
+        (CipherState::new(k1), CipherState::new(k2))
+    }
+}
+
+fn read_bytes<S: Read>(s: &mut S, len: usize) -> Result<Vec<u8>, String> {
+    let mut buf = vec![0; len];
+    try!(s.read_exact(&mut buf).map_err(|e| format!("handshake failed: {}", e)));
+
+    Ok(buf)
+}
+
+fn write_bytes<S: Write>(s: &mut S, parts: &[&[u8]]) -> Result<(), String> {
+    for part in parts {
+        try!(s.write_all(part).map_err(|e| format!("handshake failed: {}", e)));
+    }
+
+    s.flush().map_err(|e| format!("handshake failed: {}", e))
+}
+
+/// Performs the handshake as the network client, with the coordinator
+/// whose static key is `coordinator`.
+pub fn initiate<S: Read + Write>(mut s: S, key: &StaticKey, coordinator: &PeerKey) -> Result<SecureStream<S>, String> {
+    let mut state = SymmetricState::new();
+    state.mix_hash(coordinator);
+
+    let e = try!(StaticKey::generate());
+
+    // -> e, es
+    state.mix_hash(&e.public);
+    state.mix_key(&try!(dh(&e.secret, coordinator)));
+    let payload = state.encrypt_and_hash(&[]);
+    try!(write_bytes(&mut s, &[&e.public[..], &payload[..]]));
+
+    // <- e, ee
+    let re = to_array(&try!(read_bytes(&mut s, KEY_LEN)));
+    state.mix_hash(&re);
+    state.mix_key(&try!(dh(&e.secret, &re)));
+    try!(state.decrypt_and_hash(&try!(read_bytes(&mut s, TAG_LEN)))
+         .map_err(|_| "the coordinator did not prove possession of the pinned key".to_string()));
+
+    // -> s, se
+    let ciphertext = state.encrypt_and_hash(&key.public);
+    state.mix_key(&try!(dh(&key.secret, &re)));
+    let payload = state.encrypt_and_hash(&[]);
+    try!(write_bytes(&mut s, &[&ciphertext[..], &payload[..]]));
+
+    let (send, recv) = state.split();
+
+    Ok(SecureStream::new(s, send, recv))
+}
+
+/// Performs the handshake as the coordinator, returning the channel and
+/// the static key of the network client on the other end.
+pub fn respond<S: Read + Write>(mut s: S, key: &StaticKey) -> Result<(SecureStream<S>, PeerKey), String> {
+    let mut state = SymmetricState::new();
+    state.mix_hash(&key.public);
+
+    // -> e, es
+    let re = to_array(&try!(read_bytes(&mut s, KEY_LEN)));
+    state.mix_hash(&re);
+    state.mix_key(&try!(dh(&key.secret, &re)));
+    try!(state.decrypt_and_hash(&try!(read_bytes(&mut s, TAG_LEN))));
+
+    let e = try!(StaticKey::generate());
+
+    // <- e, ee
+    state.mix_hash(&e.public);
+    state.mix_key(&try!(dh(&e.secret, &re)));
+    let payload = state.encrypt_and_hash(&[]);
+    try!(write_bytes(&mut s, &[&e.public[..], &payload[..]]));
+
+    // -> s, se
+    let rs = to_array(&try!(state.decrypt_and_hash(&try!(read_bytes(&mut s, KEY_LEN + TAG_LEN)))));
+    state.mix_key(&try!(dh(&e.secret, &rs)));
+    try!(state.decrypt_and_hash(&try!(read_bytes(&mut s, TAG_LEN))));
+
+    let (recv, send) = state.split();
+
+    Ok((SecureStream::new(s, send, recv), rs))
+}
+
+/// A stream whose contents are encrypted and authenticated. Writes are
+/// buffered until the buffer holds a full frame or the stream is flushed.
+pub struct SecureStream<S> {
+    s: S,
+    send: CipherState,
+    recv: CipherState,
+    /// Plaintext waiting to be sent.
+    wbuf: Vec<u8>,
+    /// The frame being received. It is kept across calls to `read` so
+    /// that a timeout in the middle of a frame does not lose it.
+    frame: Vec<u8>,
+    /// Decrypted plaintext, of which `rpos` bytes were read.
+    rbuf: Vec<u8>,
+    rpos: usize
+}
+
+impl<S: Read + Write> SecureStream<S> {
+    fn new(s: S, send: CipherState, recv: CipherState) -> SecureStream<S> {
+        SecureStream {
+            s: s,
+            send: send,
+            recv: recv,
+            wbuf: vec![],
+            frame: vec![],
+            rbuf: vec![],
+            rpos: 0
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.s
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.s
+    }
+
+    fn send_frame(&mut self) -> io::Result<()> {
+        let mut header = [0; 4];
+        BigEndian::write_u32(&mut header, (self.wbuf.len() + TAG_LEN) as u32);
+
+        let ciphertext = self.send.encrypt(&header, &self.wbuf);
+        self.wbuf.clear();
+
+        try!(self.s.write_all(&header));
+        self.s.write_all(&ciphertext)
+    }
+
+    fn recv_frame(&mut self) -> io::Result<()> {
+        loop {
+            let needed = if self.frame.len() < 4 {
+                4
+            } else {
+                let len = BigEndian::read_u32(&self.frame[0..4]) as usize;
+
+                if len < TAG_LEN || len > MAX_FRAME + TAG_LEN {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid frame length"));
+                }
+
+                4 + len
+            };
+
+            if self.frame.len() == needed && needed > 4 {
+                break;
+            }
+
+            let mut buf = vec![0; needed - self.frame.len()];
+            let read = try!(self.s.read(&mut buf));
+            if read == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed"));
+            }
+
+            self.frame.extend_from_slice(&buf[0..read]);
+        }
+
+        let plaintext = try!(self.recv.decrypt(&self.frame[0..4], &self.frame[4..])
+                             .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)));
+
+        self.frame.clear();
+        self.rbuf = plaintext;
+        self.rpos = 0;
+
+        Ok(())
+    }
+}
+
+impl<S: Read + Write> Read for SecureStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        while self.rpos == self.rbuf.len() {
+            try!(self.recv_frame());
+        }
+
+        let n = cmp::min(buf.len(), self.rbuf.len() - self.rpos);
+        buf[0..n].copy_from_slice(&self.rbuf[self.rpos..(self.rpos + n)]);
+        self.rpos += n;
+
+        Ok(n)
+    }
+}
+
+impl<S: Read + Write> Write for SecureStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = cmp::min(buf.len(), MAX_FRAME - self.wbuf.len());
+        self.wbuf.extend_from_slice(&buf[0..n]);
+
+        if self.wbuf.len() == MAX_FRAME {
+            try!(self.send_frame());
+        }
+
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.wbuf.is_empty() {
+            try!(self.send_frame());
+        }
+
+        self.s.flush()
+    }
+}
+
+#[test]
+fn channel_handshake() {
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    let coordinator = StaticKey::generate().unwrap();
+    let player = StaticKey::generate().unwrap();
+    let coordinator_public = *coordinator.public();
+    let player_public = *player.public();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let message: Vec<u8> = (0..(3 * MAX_FRAME + 17)).map(|i| i as u8).collect();
+    let expected = message.clone();
+
+    let server = thread::spawn(move || {
+        // The first client pinned the wrong key.
+        let (stream, _) = listener.accept().unwrap();
+        assert!(respond(stream, &coordinator).is_err());
+
+        let (stream, _) = listener.accept().unwrap();
+        let (mut s, remote) = respond(stream, &coordinator).unwrap();
+        assert!(remote == player_public);
+
+        let mut received = vec![0; expected.len()];
+        s.read_exact(&mut received).unwrap();
+        assert!(received == expected);
+
+        s.write_all(b"ack").unwrap();
+        s.flush().unwrap();
+    });
+
+    let impostor = StaticKey::generate().unwrap();
+    assert!(initiate(TcpStream::connect(addr).unwrap(), &player, impostor.public()).is_err());
+
+    let mut s = initiate(TcpStream::connect(addr).unwrap(), &player, &coordinator_public).unwrap();
+    s.write_all(&message).unwrap();
+    s.flush().unwrap();
+
+    let mut ack = [0; 3];
+    s.read_exact(&mut ack).unwrap();
+    assert_eq!(&ack, b"ack");
+
+    server.join().unwrap();
+}
@@ -0,0 +1,172 @@
+//! The network client's side of a session with the coordinator, over
+//! any transport. It reconnects whenever the connection fails, and
+//! resumes where it left off.
+
+use std::io::{Read, Write};
+use std::thread;
+use std::time::Duration;
+
+use rustc_serialize::{Encodable, Decodable};
+use bincode::SizeLimit::Infinite;
+use bincode::rustc_serialize::{encode_into, decode_from};
+
+use consts::*;
+use channel::{self, StaticKey, PeerKey, SecureStream};
+use transport::{Stream, Connector};
+use wire::{MessageType, Outgoing, Incoming, Progress};
+
+/// Authenticates with the coordinator over `s` and exchanges message
+/// ids, returning the secure channel and the coordinator's message id.
+pub fn handshake(
+    mut s: Box<Stream>,
+    key: &StaticKey,
+    coordinator_key: &PeerKey,
+    msgid: u64,
+    network_timeout: u64
+) -> Result<(SecureStream<Box<Stream>>, u64), String>
+{
+    try!(s.set_timeout(Some(Duration::from_secs(5))).map_err(|e| e.to_string()));
+    try!(s.write_all(&NETWORK_MAGIC).map_err(|e| e.to_string()));
+    try!(s.flush().map_err(|e| e.to_string()));
+
+    let mut buf: [u8; 8] = [0; 8];
+    try!(s.read_exact(&mut buf).map_err(|e| e.to_string()));
+
+    if buf != COORDINATOR_MAGIC {
+        return Err("the server is not a coordinator".into());
+    }
+
+    let mut s = try!(channel::initiate(s, key, coordinator_key));
+
+    try!(encode_into(&msgid, &mut s, Infinite).map_err(|e| e.to_string()));
+    try!(s.flush().map_err(|e| e.to_string()));
+
+    let _ = s.get_ref().set_timeout(Some(Duration::from_secs(network_timeout)));
+
+    let their_msgid = try!(decode_from(&mut s, Infinite).map_err(|e| e.to_string()));
+
+    Ok((s, their_msgid))
+}
+
+pub struct Client {
+    key: StaticKey,
+    coordinator_key: PeerKey,
+    connector: Box<Connector>,
+    s: SecureStream<Box<Stream>>,
+    msgid: u64,
+    network_timeout: u64,
+    max_message_size: u64
+}
+
+impl Client {
+    pub fn connect(
+        connector: Box<Connector>,
+        key: StaticKey,
+        coordinator_key: PeerKey,
+        network_timeout: u64,
+        max_message_size: u64
+    ) -> Result<Client, String>
+    {
+        let s = try!(connector.connect().map_err(|e| format!("could not connect to coordinator: {}", e)));
+        let (s, _) = try!(handshake(s, &key, &coordinator_key, 0, network_timeout)
+                          .map_err(|e| format!("could not handshake with coordinator: {}", e)));
+
+        Ok(Client {
+            key: key,
+            coordinator_key: coordinator_key,
+            connector: connector,
+            s: s,
+            msgid: 0,
+            network_timeout: network_timeout,
+            max_message_size: max_message_size
+        })
+    }
+
+    fn do_with_stream<T, E, F: FnMut(&mut SecureStream<Box<Stream>>, u64) -> Result<T, E>>(&mut self, mut cb: F) -> T
+    {
+        let mut their_msgid = 0;
+
+        loop {
+            let val = cb(&mut self.s, their_msgid);
+
+            match val {
+                Ok(s) => {
+                    return s;
+                },
+                Err(_) => {
+                    let mut failed = false;
+
+                    loop {
+                        match self.connector.connect() {
+                            Ok(s) => {
+                                match handshake(s, &self.key, &self.coordinator_key, self.msgid, self.network_timeout) {
+                                    Ok((s, id)) => {
+                                        self.s = s;
+                                        their_msgid = id;
+                                        if failed {
+                                            println!("Reconnected to coordinator.");
+                                        }
+                                        break;
+                                    },
+                                    Err(e) => {
+                                        println!("Handshake with coordinator failed ({}), trying again...", e);
+                                        thread::sleep(Duration::from_secs(2));
+                                    }
+                                }
+                            },
+                            Err(_) => {
+                                failed = true;
+                                println!("Failed to connect to coordinator, trying again...");
+                                thread::sleep(Duration::from_secs(2));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn read<T: Decodable>(&mut self, ty: MessageType) -> T {
+        let msgid = self.msgid + 1;
+        let mut incoming = Incoming::new(ty, self.max_message_size);
+        let mut progress = Progress::new();
+
+        // A transfer interrupted by a reconnect resumes where it stopped.
+        let msg = self.do_with_stream(|s, _| {
+            try!(incoming.receive(s, msgid, |done, total| {
+                if let Some(percent) = progress.update(done, total) {
+                    println!("Receiving {:?} from the coordinator: {}% of {} bytes", ty, percent, total);
+                }
+            }));
+
+            incoming.decode()
+        });
+
+        self.msgid = msgid;
+
+        msg
+    }
+
+    pub fn write<T: Encodable>(&mut self, ty: MessageType, obj: &T) {
+        self.msgid += 1;
+
+        let msgid = self.msgid;
+        let outgoing = Outgoing::new(ty, obj).expect("messages should never fail to encode");
+        let mut progress = Progress::new();
+
+        self.do_with_stream(|s, theirid| {
+            if theirid >= msgid {
+                // They have the message we're sending already.
+                return Ok(());
+            }
+
+            // Any failure here will trigger a reconnect, after which the
+            // transfer resumes from the last acknowledged chunk.
+            outgoing.send(s, msgid, |done, total| {
+                if let Some(percent) = progress.update(done, total) {
+                    println!("Sending {:?} to the coordinator: {}% of {} bytes", ty, percent, total);
+                }
+            })
+        })
+    }
+}
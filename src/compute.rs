@@ -1,37 +1,56 @@
 #![allow(non_snake_case, dead_code)]
 
-extern crate bn;
 extern crate rand;
-extern crate crossbeam;
-extern crate rustc_serialize;
 extern crate blake2_rfc;
 extern crate bincode;
 extern crate byteorder;
 
-mod protocol;
-use self::protocol::*;
-
-mod dvd;
-use self::dvd::*;
+extern crate mpc;
+#[cfg(test)]
+extern crate snark;
+use mpc::*;
+use mpc::dvd::*;
+use mpc::config::Config;
+use mpc::media;
+use mpc::ui::Ui;
 
 use rand::{SeedableRng, Rng};
 use std::fs::{File};
+use std::io::Read;
+use std::sync::Arc;
 use bincode::SizeLimit::Infinite;
 use bincode::rustc_serialize::{encode_into, decode_from};
 
-pub const THREADS: usize = 8;
-pub const DIRECTORY_PREFIX: &'static str = "/home/compute/";
-pub const ASK_USER_TO_RECORD_HASHES: bool = true;
-
-fn get_entropy() -> [u32; 8] {
+const DEFAULT_THREADS: usize = 8;
+const DEFAULT_DIRECTORY: &'static str = "/home/compute/";
+const ASK_USER_TO_RECORD_HASHES: bool = true;
+
+const USAGE: &'static str = "\
+Usage: compute [options]
+
+Options:
+  --config PATH      read options from a file of `key = value` lines
+  --media SPEC       medium discs are exchanged on: optical:DEVICE, usb:MOUNT,
+                     qr:DIR or dir:DIR (default optical:/dev/sr0)
+  --directory PATH   where discs are staged (default /home/compute/)
+  --threads N        threads used to compute the stages (default 8)
+  --entropy TEXT     additional entropy, instead of asking for it
+  --script PATH      answer prompts with the lines of PATH instead of asking,
+                     and with empty lines once they run out
+  --non-interactive  answer every prompt with an empty line";
+
+fn get_entropy(config: &DiscConfig, entropy: Option<&str>) -> [u32; 8] {
     use blake2_rfc::blake2s::blake2s;
 
     let mut v: Vec<u8> = vec![];
 
     {
-        let input_from_user = prompt(
-            "Please type a random string of text and then press [ENTER] to provide additional entropy."
-        );
+        let input_from_user = match entropy {
+            Some(entropy) => entropy.into(),
+            None => config.prompt(
+                "Please type a random string of text and then press [ENTER] to provide additional entropy."
+            )
+        };
 
         let hash = blake2s(32, &[], input_from_user.as_bytes());
 
@@ -64,18 +83,99 @@ fn get_entropy() -> [u32; 8] {
     seed
 }
 
+/// Parses the command line (without the program name).
+fn parse_options<I: Iterator<Item=String>>(args: I) -> Result<Config, String> {
+    Config::from_args(args, &["non-interactive", "help"], &["media", "directory", "threads", "entropy", "script"])
+}
+
 fn main() {
-    prompt("Press [ENTER] when you're ready to perform diagnostics of the DVD drive.");
-    perform_diagnostics();
-    prompt("Diagnostics complete. Press [ENTER] when you're ready to begin the ceremony.");
+    let options = match parse_options(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            println!("{}\n\n{}", e, USAGE);
+            std::process::exit(1);
+        }
+    };
+
+    if options.flag("help") {
+        println!("{}", USAGE);
+        return;
+    }
+
+    let media = match media::open(options.get("media").unwrap_or(media::DEFAULT_MEDIA)) {
+        Ok(media) => media,
+        Err(e) => {
+            println!("--media: {}\n\n{}", e, USAGE);
+            std::process::exit(1);
+        }
+    };
+
+    let input = match options.get("script") {
+        Some(path) => {
+            let mut contents = String::new();
+            if let Err(e) = File::open(path).and_then(|mut f| f.read_to_string(&mut contents)) {
+                println!("--script: could not read {}: {}", path, e);
+                std::process::exit(1);
+            }
+
+            Input::script(&contents)
+        },
+        None if options.flag("non-interactive") => Input::script(""),
+        None => Input::Terminal
+    };
+
+    // Unattended runs are usually logged, so the screen isn't redrawn.
+    let ui = match input {
+        Input::Terminal => Ui::new(true),
+        Input::Script(_) => Ui::new(false)
+    };
+
+    match options.get_or("threads", DEFAULT_THREADS) {
+        Ok(threads) => set_threads(threads),
+        Err(e) => {
+            println!("{}\n\n{}", e, USAGE);
+            std::process::exit(1);
+        }
+    }
+
+    let config = DiscConfig {
+        directory_prefix: DiscConfig::directory_prefix(options.get("directory").unwrap_or(DEFAULT_DIRECTORY)),
+        ask_user_to_record_hashes: ASK_USER_TO_RECORD_HASHES,
+        media: media,
+        input: Arc::new(input),
+        ui: Arc::new(ui)
+    };
+
+    config.prompt("Press [ENTER] when you're ready to perform diagnostics of the DVD drive.");
+    perform_diagnostics(&config);
+    config.prompt("Diagnostics complete. Press [ENTER] when you're ready to begin the ceremony.");
+
+    let seed = get_entropy(&config, options.get("entropy"));
+
+    participate(&config, &seed);
+}
+
+/// What the network machine sends back after one of the player's turns.
+enum Next<T, S> {
+    /// The contents of the next stage.
+    Stage(T, Digest256),
+    /// The contents of the same stage again, because the coordinator
+    /// rejected the contribution the player had built upon.
+    Again(S, Digest256)
+}
 
-    let mut chacha_rng = rand::chacha::ChaChaRng::from_seed(&get_entropy());
+/// Takes the player's part in the ceremony, exchanging discs with the
+/// network machine, and returns their commitment. A turn taken again is
+/// exchanged on discs numbered by the attempt, such as 'A2' and 'B2'.
+fn participate(config: &DiscConfig, seed: &[u32]) -> Digest256 {
+    let mut chacha_rng = rand::chacha::ChaChaRng::from_seed(seed);
 
     let privkey = PrivateKey::new(&mut chacha_rng);
     let pubkey = privkey.pubkey(&mut chacha_rng);
     let comm = pubkey.hash();
 
-    let (hash_of_commitments, mut stage1, prev_msg_hash): (Digest512, Stage1Contents, Digest256) = read_disc(
+    let (hash_of_commitments, mut stage1, mut prev_msg_hash): (Digest512, Stage1Contents, Digest256) = read_disc(
+        config,
         "A",
         &format!("Commitment: {}\n\n\
                   Write this commitment down on paper.\n\n\
@@ -92,60 +192,251 @@ fn main() {
 
     let nizks = pubkey.nizks(&mut chacha_rng, &privkey, &hash_of_commitments);
 
-    reset();
-    println!("Please wait while disc 'B' is computed... This should take 30 minutes to an hour.");
-    stage1.transform(&privkey);
+    let mut attempt = 1;
+    let (mut stage2, mut prev_msg_hash): (Stage2Contents, Digest256) = loop {
+        let disc = retry_disc("B", attempt);
+
+        config.ui.work(&format!("Computing disc '{}'. This should take 30 minutes to an hour.", disc), stage1.size(), |observer| {
+            stage1.transform_observed(&privkey, observer)
+        });
+
+        let next = exchange_discs(
+            config,
+            &disc,
+            &["C", &retry_disc("A", attempt + 1)[..]],
+            |f| {
+                try!(encode_into(&pubkey, f, Infinite));
+                try!(encode_into(&nizks, f, Infinite));
+                try!(encode_into(&stage1, f, Infinite));
+
+                encode_into(&prev_msg_hash, f, Infinite)
+            },
+            |i, f, p| -> Result<Next<Stage2Contents, Stage1Contents>, bincode::rustc_serialize::DecodingError> {
+                if i == 0 {
+                    let stage2 = try!(decode_from(f, Infinite));
+
+                    Ok(Next::Stage(stage2, p.unwrap()))
+                } else {
+                    let _: Digest512 = try!(decode_from(f, Infinite));
+                    let stage1 = try!(decode_from(f, Infinite));
+
+                    Ok(Next::Again(stage1, p.unwrap()))
+                }
+            }
+        );
 
-    let (mut stage2, prev_msg_hash): (Stage2Contents, Digest256) = exchange_disc(
-        "B",
-        "C",
-        |f| {
-            try!(encode_into(&pubkey, f, Infinite));
-            try!(encode_into(&nizks, f, Infinite));
-            try!(encode_into(&stage1, f, Infinite));
+        match next {
+            Next::Stage(stage2, h) => break (stage2, h),
+            Next::Again(again, h) => {
+                stage1 = again;
+                prev_msg_hash = h;
+                attempt += 1;
+            }
+        }
+    };
 
-            encode_into(&prev_msg_hash, f, Infinite)
-        },
-        |f, p| -> Result<(Stage2Contents, Digest256), bincode::rustc_serialize::DecodingError> {
-            let stage2 = try!(decode_from(f, Infinite));
+    drop(stage1);
+
+    let mut attempt = 1;
+    let (mut stage3, mut prev_msg_hash): (Stage3Contents, Digest256) = loop {
+        let disc = retry_disc("D", attempt);
+
+        config.ui.work(&format!("Computing disc '{}'. This should take 45 to 90 minutes.", disc), stage2.size(), |observer| {
+            stage2.transform_observed(&privkey, observer)
+        });
+
+        let next = exchange_discs(
+            config,
+            &disc,
+            &["E", &retry_disc("C", attempt + 1)[..]],
+            |f| {
+                try!(encode_into(&stage2, f, Infinite));
+
+                encode_into(&prev_msg_hash, f, Infinite)
+            },
+            |i, f, p| -> Result<Next<Stage3Contents, Stage2Contents>, bincode::rustc_serialize::DecodingError> {
+                if i == 0 {
+                    Ok(Next::Stage(try!(decode_from(f, Infinite)), p.unwrap()))
+                } else {
+                    Ok(Next::Again(try!(decode_from(f, Infinite)), p.unwrap()))
+                }
+            }
+        );
 
-            Ok((stage2, p.unwrap()))
+        match next {
+            Next::Stage(stage3, h) => break (stage3, h),
+            Next::Again(again, h) => {
+                stage2 = again;
+                prev_msg_hash = h;
+                attempt += 1;
+            }
         }
-    );
+    };
 
-    drop(stage1);
+    drop(stage2);
 
-    reset();
-    println!("Please wait while disc 'D' is computed... This should take 45 to 90 minutes.");
-    stage2.transform(&privkey);
+    // Nothing follows the last disc, unless the turn is to be taken
+    // again, in which case the network machine burns the next attempt's
+    // disc 'E' as in the other stages.
+    let mut attempt = 1;
+    loop {
+        let disc = retry_disc("F", attempt);
+
+        config.ui.work(&format!("Computing disc '{}'. This should take 15-30 minutes.", disc), stage3.size(), |observer| {
+            stage3.transform_observed(&privkey, observer)
+        });
+
+        let again = write_last_disc(
+            config,
+            &disc,
+            &retry_disc("E", attempt + 1),
+            |f| {
+                try!(encode_into(&stage3, f, Infinite));
+
+                encode_into(&prev_msg_hash, f, Infinite)
+            },
+            |f, p| -> Result<(Stage3Contents, Digest256), bincode::rustc_serialize::DecodingError> {
+                Ok((try!(decode_from(f, Infinite)), p.unwrap()))
+            }
+        );
 
-    let (mut stage3, prev_msg_hash): (Stage3Contents, Digest256) = exchange_disc(
-        "D",
-        "E",
-        |f| {
-            try!(encode_into(&stage2, f, Infinite));
+        match again {
+            Some((again, h)) => {
+                stage3 = again;
+                prev_msg_hash = h;
+                attempt += 1;
+            },
+            None => break
+        }
+    }
 
-            encode_into(&prev_msg_hash, f, Infinite)
-        },
-        |f, p| -> Result<(Stage3Contents, Digest256), bincode::rustc_serialize::DecodingError> {
-            let stage3 = try!(decode_from(f, Infinite));
+    comm
+}
 
-            Ok((stage3, p.unwrap()))
+#[test]
+fn scripted_participant() {
+    use std::{env, fs, thread};
+    use std::io::Write;
+    use std::time::Duration;
+    use snark::CS;
+
+    let dir = env::temp_dir().join(format!("mpc-compute-{}", rand::random::<u64>()));
+    let discs = dir.join("discs");
+    fs::create_dir_all(dir.join("staging")).unwrap();
+    fs::create_dir_all(&discs).unwrap();
+
+    let config = DiscConfig {
+        directory_prefix: format!("{}/", dir.join("staging").display()),
+        ask_user_to_record_hashes: true,
+        media: media::open(&format!("dir:{}", discs.display())).unwrap(),
+        input: Arc::new(Input::script("")),
+        ui: Arc::new(Ui::new(false))
+    };
+
+    let participant = {
+        let config = config.clone();
+        thread::spawn(move || participate(&config, &[1, 2, 3, 4, 5, 6, 7, 8]))
+    };
+
+    // The network machine's part, with the checks the coordinator makes.
+    let write = |name: &str, disc: &[u8]| -> Digest256 {
+        let partial = discs.join(format!("{}.partial", name));
+        File::create(&partial).unwrap().write_all(disc).unwrap();
+        fs::rename(&partial, discs.join(name)).unwrap();
+
+        Digest256::from_reader(&mut &disc[..])
+    };
+    let wait = |name: &str| -> Vec<u8> {
+        let path = discs.join(name);
+        while !path.is_file() {
+            thread::sleep(Duration::from_millis(100));
         }
-    );
 
-    drop(stage2);
+        let mut disc = vec![];
+        File::open(&path).unwrap().read_to_end(&mut disc).unwrap();
+        disc
+    };
+
+    let cs = CS::dummy();
+    let hash_of_commitments = Digest512::from(&vec![0u8; 32]).unwrap();
+    let prev_msg_hash = Digest256::from(&vec![0u8; 32]).unwrap();
+
+    let stage1 = Stage1Contents::new(&cs);
+    let mut disc = vec![];
+    encode_into(&hash_of_commitments, &mut disc, Infinite).unwrap();
+    encode_into(&stage1, &mut disc, Infinite).unwrap();
+    encode_into(&prev_msg_hash, &mut disc, Infinite).unwrap();
+    let sent = write("discA", &disc);
+
+    let disc = wait("discB");
+    let mut f = &disc[..];
+    let pubkey: PublicKey = decode_from(&mut f, Infinite).unwrap();
+    let nizks: PublicKeyNizks = decode_from(&mut f, Infinite).unwrap();
+    let new_stage1: Stage1Contents = decode_from(&mut f, Infinite).unwrap();
+    let ihash: Digest256 = decode_from(&mut f, Infinite).unwrap();
+    assert!(ihash == sent);
+    assert!(nizks.is_valid(&pubkey, &hash_of_commitments));
+    assert!(new_stage1.is_well_formed(&stage1));
+    assert!(new_stage1.verify_transform(&stage1, &pubkey));
+    let prev_msg_hash = Digest256::from_reader(&mut &disc[..]);
+
+    let stage2 = Stage2Contents::new(&cs, &new_stage1);
+    let mut disc = vec![];
+    encode_into(&stage2, &mut disc, Infinite).unwrap();
+    encode_into(&prev_msg_hash, &mut disc, Infinite).unwrap();
+    let sent = write("discC", &disc);
+
+    let disc = wait("discD");
+    let mut f = &disc[..];
+    let new_stage2: Stage2Contents = decode_from(&mut f, Infinite).unwrap();
+    let ihash: Digest256 = decode_from(&mut f, Infinite).unwrap();
+    assert!(ihash == sent);
+    assert!(new_stage2.is_well_formed(&stage2));
+    assert!(new_stage2.verify_transform(&stage2, &pubkey));
+    let prev_msg_hash = Digest256::from_reader(&mut &disc[..]);
+
+    let stage3 = Stage3Contents::new(&cs, &new_stage2);
+    let mut disc = vec![];
+    encode_into(&stage3, &mut disc, Infinite).unwrap();
+    encode_into(&prev_msg_hash, &mut disc, Infinite).unwrap();
+    let sent = write("discE", &disc);
+
+    let disc = wait("discF");
+    let mut f = &disc[..];
+    let new_stage3: Stage3Contents = decode_from(&mut f, Infinite).unwrap();
+    let ihash: Digest256 = decode_from(&mut f, Infinite).unwrap();
+    assert!(ihash == sent);
+    assert!(new_stage3.is_well_formed(&stage3));
+    assert!(new_stage3.verify_transform(&stage3, &pubkey));
+
+    let comm = participant.join().unwrap();
+    assert!(pubkey.hash() == comm);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
 
-    reset();
-    println!("Please wait while disc 'F' is computed... This should take 15-30 minutes.");
-    stage3.transform(&privkey);
+#[test]
+fn command_line() {
+    let parse = |args: &[&str]| parse_options(args.iter().map(|&a| String::from(a)));
 
-    write_disc(
-        "F",
-        |f| {
-            try!(encode_into(&stage3, f, Infinite));
+    let options = parse(&["--non-interactive"]).unwrap();
+    assert!(options.flag("non-interactive"));
+    assert_eq!(options.get("entropy"), None);
 
-            encode_into(&prev_msg_hash, f, Infinite)
-        },
-    );
+    let options = parse(&["--non-interactive", "--entropy", "x"]).unwrap();
+    assert!(options.flag("non-interactive"));
+    assert_eq!(options.get("entropy"), Some("x"));
+
+    let options = parse(&["--media", "dir:/tmp/discs", "--script", "answers"]).unwrap();
+    assert!(!options.flag("non-interactive"));
+    assert_eq!(options.get("media"), Some("dir:/tmp/discs"));
+    assert_eq!(options.get("script"), Some("answers"));
+
+    let options = parse(&["--threads", "4", "--directory", "/tmp/staging"]).unwrap();
+    assert_eq!(options.get_or("threads", DEFAULT_THREADS).unwrap(), 4);
+    assert_eq!(DiscConfig::directory_prefix(options.get("directory").unwrap()), "/tmp/staging/");
+    assert!(parse(&["--threads", "many"]).unwrap().get_or("threads", DEFAULT_THREADS).is_err());
+
+    assert!(parse(&["--entropy"]).is_err());
+    assert!(parse(&["--noninteractive"]).is_err());
 }
@@ -0,0 +1,158 @@
+//! Runtime configuration for the binaries.
+//!
+//! Options are given on the command line as `--key value`, or as `--flag`
+//! for boolean options. `--config PATH` additionally reads options from a
+//! file containing `key = value` lines (`#` starts a comment). Options on
+//! the command line take precedence over those in the file. Anything else
+//! on the command line is a positional argument. Each binary lists the
+//! options it accepts, and any other is an error.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::str::FromStr;
+
+pub struct Config {
+    values: HashMap<String, String>,
+    positional: Vec<String>
+}
+
+impl Config {
+    /// Parses the command line (without the program name). `flags` lists
+    /// the options that do not take a value, and `options` those that
+    /// do, besides `config`.
+    pub fn from_args<I: Iterator<Item=String>>(args: I, flags: &[&str], options: &[&str]) -> Result<Config, String> {
+        let mut values = HashMap::new();
+        let mut positional = vec![];
+        let mut args = args;
+
+        let is_known = |key: &str| key == "config" || flags.contains(&key) || options.contains(&key);
+
+        while let Some(arg) = args.next() {
+            if arg.starts_with("--") {
+                let key = arg[2..].to_string();
+
+                if !is_known(&key[..]) {
+                    return Err(format!("unknown option --{}", key));
+                }
+
+                if flags.contains(&&key[..]) {
+                    values.insert(key, "true".to_string());
+                } else {
+                    match args.next() {
+                        Some(value) => {
+                            values.insert(key, value);
+                        },
+                        None => return Err(format!("missing value for --{}", key))
+                    }
+                }
+            } else {
+                positional.push(arg);
+            }
+        }
+
+        let mut config = Config {
+            values: HashMap::new(),
+            positional: positional
+        };
+
+        if let Some(path) = values.get("config") {
+            let mut contents = String::new();
+            try!(File::open(path).and_then(|mut f| f.read_to_string(&mut contents))
+                 .map_err(|e| format!("could not read config file {}: {}", path, e)));
+
+            try!(config.parse_file(&contents));
+
+            for key in config.values.keys() {
+                if key == "config" || !is_known(&key[..]) {
+                    return Err(format!("config file {}: unknown option {}", path, key));
+                }
+            }
+        }
+
+        // The command line overrides the config file.
+        config.values.extend(values.into_iter());
+
+        Ok(config)
+    }
+
+    fn parse_file(&mut self, contents: &str) -> Result<(), String> {
+        for (i, line) in contents.lines().enumerate() {
+            let line = match line.find('#') {
+                Some(comment) => &line[0..comment],
+                None => line
+            }.trim();
+
+            if line.is_empty() {
+                continue;
+            }
+
+            match line.find('=') {
+                Some(eq) => {
+                    let key = line[0..eq].trim().to_string();
+                    let value = line[eq+1..].trim().to_string();
+
+                    self.values.insert(key, value);
+                },
+                None => return Err(format!("config file line {}: expected `key = value`", i + 1))
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(|v| &v[..])
+    }
+
+    /// Parses the value of `key`, or returns `default` if it was not
+    /// given.
+    pub fn get_or<T: FromStr>(&self, key: &str, default: T) -> Result<T, String> {
+        match self.get(key) {
+            Some(value) => value.parse().map_err(|_| format!("invalid value for {}: {}", key, value)),
+            None => Ok(default)
+        }
+    }
+
+    pub fn flag(&self, key: &str) -> bool {
+        match self.get(key) {
+            Some("true") | Some("yes") | Some("1") => true,
+            _ => false
+        }
+    }
+
+    pub fn positional(&self) -> &[String] {
+        &self.positional
+    }
+}
+
+#[test]
+fn config_parsing() {
+    let args = vec!["inspect", "--players", "6", "--dummy-cs", "extra"];
+    let config = Config::from_args(args.into_iter().map(String::from), &["dummy-cs"], &["players"]).unwrap();
+
+    assert_eq!(config.get_or("players", 1usize).unwrap(), 6);
+    assert_eq!(config.get_or("threads", 8usize).unwrap(), 8);
+    assert!(config.flag("dummy-cs"));
+    assert!(!config.flag("resume"));
+    assert_eq!(config.positional(), &["inspect".to_string(), "extra".to_string()]);
+
+    let args = vec!["--players", "six"];
+    let config = Config::from_args(args.into_iter().map(String::from), &[], &["players"]).unwrap();
+    assert!(config.get_or("players", 1usize).is_err());
+
+    let args = vec!["--players"];
+    assert!(Config::from_args(args.into_iter().map(String::from), &[], &["players"]).is_err());
+
+    // Options the binary doesn't know about
+    let args = vec!["--playres", "6"];
+    assert!(Config::from_args(args.into_iter().map(String::from), &[], &["players"]).is_err());
+    let args = vec!["--resume"];
+    assert!(Config::from_args(args.into_iter().map(String::from), &["dummy-cs"], &["players"]).is_err());
+
+    let mut config = Config::from_args(vec![].into_iter(), &[], &[]).unwrap();
+    config.parse_file("# ceremony rehearsal\nplayers = 3\nlisten = 127.0.0.1:65530 # local only\n").unwrap();
+    assert_eq!(config.get("listen"), Some("127.0.0.1:65530"));
+    assert_eq!(config.get_or("players", 1usize).unwrap(), 3);
+    assert!(config.parse_file("players 3").is_err());
+}
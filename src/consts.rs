@@ -1,6 +1,7 @@
-pub const NETWORK_MAGIC: [u8; 8] = [0x83, 0xd4, 0x02, 0x2e, 0x07, 0x54, 0x65, 0xd8];
+pub const NETWORK_MAGIC: [u8; 8] = [0x83, 0xd4, 0x02, 0x2e, 0x07, 0x54, 0x65, 0xda];
 pub const COORDINATOR_MAGIC: [u8; 8] = [0xe1, 0xd5, 0xd7, 0x35, 0x60, 0x51, 0xc5, 0x94];
 
-pub const NETWORK_ACK: [u8; 4] = [0xcc, 0xd0, 0x33, 0x8d];
-pub const USE_DUMMY_CS: bool = false;
-pub const NETWORK_TIMEOUT: u64 = 60;
+pub const TRANSCRIPT_MAGIC: [u8; 8] = [0x9b, 0x6d, 0x3e, 0xa2, 0x15, 0xf0, 0x4c, 0x71];
+
+pub const DEFAULT_NETWORK_TIMEOUT: u64 = 60;
+pub const DEFAULT_MAX_MESSAGE_SIZE: u64 = 1 << 30;
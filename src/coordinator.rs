@@ -1,13 +1,8 @@
 #![allow(non_snake_case, dead_code)]
 
-extern crate bn;
-extern crate rand;
 extern crate snark;
-extern crate crossbeam;
 extern crate rustc_serialize;
-extern crate blake2_rfc;
 extern crate bincode;
-extern crate byteorder;
 
 #[macro_use]
 extern crate log;
@@ -16,106 +11,1168 @@ extern crate time;
 extern crate ansi_term;
 
 #[macro_use]
-mod protocol;
-use self::protocol::*;
+extern crate mpc;
 
-mod consts;
-use self::consts::*;
+#[cfg(test)]
+extern crate rand;
+use mpc::*;
+use mpc::consts::*;
+use mpc::config::Config;
+use mpc::transcript::*;
+use mpc::verify::{replay, Progress};
+use mpc::protocol::powersoftau::Accumulator;
+use mpc::channel::{respond, StaticKey, PeerKey, SecureStream};
+use mpc::wire::{self, MessageType, Outgoing, Incoming};
+use mpc::transport::{self, Stream};
 
 use snark::*;
-use std::net::{TcpListener, TcpStream};
-use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::io::{self, Read, Write, Seek, SeekFrom, BufReader, BufWriter};
+use std::fs::{self, File, OpenOptions};
+use std::path::PathBuf;
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 use std::sync::mpsc::{channel, Sender, Receiver};
-use std::thread;
+use std::thread::{self, JoinHandle};
 use rustc_serialize::{Decodable, Encodable};
 use rustc_serialize::hex::ToHex;
-use bincode::SizeLimit::Infinite;
-use bincode::rustc_serialize::{encode_into, decode_from};
-use std::time::Duration;
+use bincode::SizeLimit::{Infinite, Bounded};
+use bincode::rustc_serialize::{encode, decode, encode_into, decode_from};
+use std::time::{Duration, Instant};
+
+const DEFAULT_LISTEN_ADDR: &'static str = "0.0.0.0:65530";
+const DEFAULT_PLAYERS: usize = 1;
+const DEFAULT_THREADS: usize = 128;
+const DEFAULT_TRANSCRIPT_PATH: &'static str = "transcript";
+const DEFAULT_KEY_PATH: &'static str = "coordinator.key";
+const DEFAULT_PLAYER_TIMEOUT: u64 = 12 * 60 * 60;
+
+const USAGE: &'static str = "\
+Usage: coordinator [options]
+
+Options:
+  --config PATH      read options from a file of `key = value` lines
+  --players N        number of players in the ceremony (default 1)
+  --listen ADDR      address to accept players on (default 0.0.0.0:65530);
+                     `unix:PATH` listens on a Unix socket and `dir:PATH`
+                     exchanges files through a shared directory
+  --transcript PATH  where to write the transcript (default transcript); an
+                     unfinished transcript is replayed and the ceremony continued
+  --key PATH         the coordinator's long-term key, generated if missing
+                     (default coordinator.key); players pin its public key
+  --accumulator PATH start stage 1 from the powers of a powers of tau
+                     accumulator rather than afresh
+  --threads N        threads used to construct the stages (default 128)
+  --timeout SECS     network read/write timeout (default 60)
+  --player-timeout SECS
+                     how long to wait for each message to or from a player,
+                     including while they are offline, before rejecting
+                     them (default 43200, which is 12 hours)
+  --max-message-size N
+                     largest message accepted from a player, in bytes
+                     (default 1073741824)
+  --status ADDR      serve a read-only status page for observers over HTTP
+                     (disabled by default)
+  --offline DIR      exchange challenge and response files with the players
+                     through DIR instead of accepting connections
+  --dummy-cs         use a small dummy constraint system, for rehearsals";
+
+#[derive(Clone)]
+struct CoordinatorConfig {
+    listen_addr: String,
+    players: usize,
+    transcript_path: String,
+    key_path: String,
+    accumulator_path: Option<String>,
+    threads: usize,
+    network_timeout: u64,
+    player_timeout: u64,
+    max_message_size: u64,
+    status_addr: Option<String>,
+    offline_dir: Option<String>,
+    use_dummy_cs: bool
+}
+
+impl CoordinatorConfig {
+    fn from(config: &Config) -> Result<CoordinatorConfig, String> {
+        let players = try!(config.get_or("players", DEFAULT_PLAYERS));
+        if players == 0 {
+            return Err("there must be at least one player".into());
+        }
+
+        Ok(CoordinatorConfig {
+            listen_addr: config.get("listen").unwrap_or(DEFAULT_LISTEN_ADDR).into(),
+            players: players,
+            transcript_path: config.get("transcript").unwrap_or(DEFAULT_TRANSCRIPT_PATH).into(),
+            key_path: config.get("key").unwrap_or(DEFAULT_KEY_PATH).into(),
+            accumulator_path: config.get("accumulator").map(|path| path.into()),
+            threads: try!(config.get_or("threads", DEFAULT_THREADS)),
+            network_timeout: try!(config.get_or("timeout", DEFAULT_NETWORK_TIMEOUT)),
+            player_timeout: try!(config.get_or("player-timeout", DEFAULT_PLAYER_TIMEOUT)),
+            max_message_size: try!(config.get_or("max-message-size", DEFAULT_MAX_MESSAGE_SIZE)),
+            status_addr: config.get("status").map(|addr| addr.into()),
+            offline_dir: config.get("offline").map(|dir| dir.into()),
+            use_dummy_cs: config.flag("dummy-cs")
+        })
+    }
+}
+
+/// Number of messages exchanged with a player during each of their
+/// turns: the commitment, then stages 1, 2 and 3, each ending with
+/// whether the player must take it again.
+const MESSAGES_PER_TURN: [u64; 4] = [1, 8, 5, 5];
+
+/// Where `run` picks up the ceremony. This is the very beginning for a
+/// new ceremony, or is rebuilt from the transcript after a restart.
+struct Resume {
+    peers: Vec<PeerKey>,
+    commitments: Vec<Digest256>,
+    /// Number of players who have completed each of the three stages.
+    done: [usize; 3],
+    /// Players whose contribution was rejected.
+    dropped: Vec<usize>,
+    /// The (player, stage) of each turn taken again because the player
+    /// had built upon a rejected contribution.
+    retries: Vec<(usize, usize)>,
+    pubkeys: Vec<Option<PublicKey>>,
+    last_message_hash: Option<Digest256>,
+    stage1: Option<Stage1Contents>,
+    stage2: Option<Stage2Contents>,
+    stage3: Option<Stage3Contents>,
+    /// The contribution to the stage in progress that had been
+    /// acknowledged, but not yet verified, when the coordinator stopped.
+    pending1: Option<SavedPending<Stage1Contents>>,
+    pending2: Option<SavedPending<Stage2Contents>>,
+    pending3: Option<SavedPending<Stage3Contents>>
+}
+
+impl Resume {
+    fn new() -> Resume {
+        Resume {
+            peers: vec![],
+            commitments: vec![],
+            done: [0; 3],
+            dropped: vec![],
+            retries: vec![],
+            pubkeys: vec![],
+            last_message_hash: None,
+            stage1: None,
+            stage2: None,
+            stage3: None,
+            pending1: None,
+            pending2: None,
+            pending3: None
+        }
+    }
+
+    /// The stage and player of the pending contribution, if any.
+    fn pending_turn(&self) -> Option<(usize, usize)> {
+        if let Some(ref saved) = self.pending1 {
+            Some((1, saved.player))
+        } else if let Some(ref saved) = self.pending2 {
+            Some((2, saved.player))
+        } else if let Some(ref saved) = self.pending3 {
+            Some((3, saved.player))
+        } else {
+            None
+        }
+    }
+
+    /// The number of messages exchanged with player `i` so far, which
+    /// their network client expects us to know when it reconnects.
+    fn msgid(&self, i: usize) -> u64 {
+        let mut msgid = MESSAGES_PER_TURN[0];
+
+        for (stage, done) in self.done.iter().enumerate() {
+            if i < *done {
+                msgid += MESSAGES_PER_TURN[stage + 1];
+            }
+        }
+
+        for &(player, stage) in &self.retries {
+            if player == i {
+                msgid += MESSAGES_PER_TURN[stage];
+            }
+        }
+
+        // The player may not have been told that their turn is over
+        // before the coordinator stopped, so they are told again.
+        if let Some((stage, player)) = self.pending_turn() {
+            if player == i {
+                msgid += MESSAGES_PER_TURN[stage] - 1;
+            }
+        }
+
+        msgid
+    }
+}
+
+/// A message from a player that can never be accepted.
+struct Invalid {
+    reason: String,
+    /// The message as it was received, unless it was refused before
+    /// then.
+    data: Option<Vec<u8>>
+}
+
+impl Invalid {
+    /// The reason for rejecting the player, and the message as evidence
+    /// if there is one, recorded as `record`.
+    fn into_evidence(self, record: RecordType) -> (String, Evidence) {
+        let evidence = match self.data {
+            Some(data) => vec![(record, data)],
+            None => vec![]
+        };
+
+        (self.reason, evidence)
+    }
+}
+
+/// A message received from a player, as evidence of their contribution.
+fn as_evidence<T: Encodable>(record: RecordType, obj: &T) -> (RecordType, Vec<u8>) {
+    (record, encode(obj, Infinite).expect("messages should never fail to encode"))
+}
+
+/// A contribution whose `verify_transform` runs in the background while
+/// the next player works upon it. It is only written to the transcript
+/// once it has been verified.
+struct Pending<T> {
+    player: usize,
+    peerid: PeerKey,
+    /// The player's key, for contributions to stage 1.
+    key: Option<(PublicKey, PublicKeyNizks)>,
+    contents: Arc<T>,
+    ihash: Digest256,
+    last_message_hash: Digest256,
+    verified: JoinHandle<bool>
+}
+
+/// A `Pending` contribution as it is kept on disk until it is resolved,
+/// so that the coordinator can pick it up again after a restart. The
+/// player is told that their turn is over once it has been saved.
+#[derive(RustcDecodable)]
+struct SavedPending<T> {
+    player: usize,
+    key: Option<(PublicKey, PublicKeyNizks)>,
+    contents: T,
+    ihash: Digest256,
+    last_message_hash: Digest256
+}
+
+/// Runs `verify`, the check of a contribution to `stage`, in the
+/// background, logging its progress through each vector.
+fn verify_in_background<F>(stage: usize, peerid: PeerKey, verify: F) -> JoinHandle<bool>
+    where F: FnOnce(&Observer) -> bool + Send + 'static
+{
+    thread::spawn(move || {
+        let meter = Meter::new();
+
+        verify(&|name: &str, done: usize, total: usize| {
+            if let Some((percent, rate)) = meter.update(name, done, total) {
+                info!("Verifying stage{} from peerid={}: {} {}% of {} elements ({:.0} elements/s)",
+                      stage, peerid.to_hex(), name, percent, total, rate);
+            }
+        })
+    })
+}
+
+/// Reported to `run` as new players join.
+enum Event {
+    Connected(PeerKey),
+    Committed(PeerKey, Digest256)
+}
+
+/// Where a player's session is at.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum SessionState {
+    /// Connected, but has not sent their commitment yet.
+    Committing,
+    /// Waiting for their turn.
+    Waiting,
+    /// Working on a stage.
+    Turn(usize),
+    Finished,
+    Rejected
+}
+
+struct Session {
+    state: SessionState,
+    online: bool
+}
+
+/// A turn still to come, and an estimate of how long until it begins.
+struct QueueEntry {
+    player: usize,
+    peerid: PeerKey,
+    stage: usize,
+    online: bool,
+    /// Unknown until a turn of the stage has been timed.
+    eta: Option<Duration>
+}
+
+/// The hashes of the discs exchanged during a turn: the stage as it
+/// was sent to the player, and their contribution, whose hash is the
+/// previous message hash of the next turn.
+struct DiscHashes {
+    stage: usize,
+    player: usize,
+    sent: Digest256,
+    received: Digest256
+}
+
+/// A message being sent to or received from a player.
+struct TransferProgress {
+    ty: MessageType,
+    sending: bool,
+    done: u64,
+    total: u64
+}
+
+/// The players' sessions, and the order and duration of their turns.
+/// Each session is tracked independently of the connection, so a player
+/// can go offline while they wait and be re-admitted for their turn.
+struct Schedule {
+    players: Vec<PeerKey>,
+    commitments: Vec<Digest256>,
+    sessions: HashMap<PeerKey, Session>,
+    dropped: Vec<usize>,
+    /// The turn in progress, as (stage, player), and when it began.
+    current: Option<(usize, usize, Instant)>,
+    /// How long the completed turns of each stage took.
+    durations: [Vec<Duration>; 3],
+    /// Discs exchanged since the coordinator started.
+    discs: Vec<DiscHashes>,
+    transfers: HashMap<PeerKey, TransferProgress>,
+    complete: bool
+}
+
+impl Schedule {
+    fn new() -> Schedule {
+        Schedule {
+            players: vec![],
+            commitments: vec![],
+            sessions: HashMap::new(),
+            dropped: vec![],
+            current: None,
+            durations: [vec![], vec![], vec![]],
+            discs: vec![],
+            transfers: HashMap::new(),
+            complete: false
+        }
+    }
+
+    fn set_state(&mut self, peerid: &PeerKey, state: SessionState) {
+        self.sessions.entry(*peerid).or_insert(Session {
+            state: state,
+            online: false
+        }).state = state;
+    }
+
+    fn set_online(&mut self, peerid: &PeerKey, online: bool) {
+        if let Some(session) = self.sessions.get_mut(peerid) {
+            session.online = online;
+        }
+    }
+
+    fn begin_turn(&mut self, stage: usize, player: usize) {
+        let peerid = self.players[player];
+
+        self.set_state(&peerid, SessionState::Turn(stage));
+        self.current = Some((stage, player, Instant::now()));
+    }
+
+    fn end_turn(&mut self) {
+        if let Some((stage, player, began)) = self.current.take() {
+            let peerid = self.players[player];
+
+            self.durations[stage - 1].push(began.elapsed());
+            self.set_state(&peerid, if stage == 3 { SessionState::Finished } else { SessionState::Waiting });
+        }
+    }
+
+    fn reject(&mut self, player: usize) {
+        let peerid = self.players[player];
+
+        self.dropped.push(player);
+        self.set_state(&peerid, SessionState::Rejected);
+    }
+
+    /// The average duration of a turn of `stage`, or failing that, of
+    /// any turn.
+    fn average(&self, stage: usize) -> Option<Duration> {
+        let durations: Vec<Duration> = if self.durations[stage - 1].is_empty() {
+            self.durations.iter().flat_map(|d| d.iter()).cloned().collect()
+        } else {
+            self.durations[stage - 1].clone()
+        };
+
+        if durations.is_empty() {
+            return None;
+        }
+
+        let total = durations.iter().fold(Duration::from_secs(0), |acc, d| acc + *d);
+
+        Some(total / durations.len() as u32)
+    }
+
+    /// The turns from the one in progress to the end of the ceremony.
+    fn queue(&self) -> Vec<QueueEntry> {
+        let (stage, player, began) = match self.current {
+            Some(current) => current,
+            None => return vec![]
+        };
+
+        let mut queue = vec![];
+        // How long until the next turn in the queue begins.
+        let mut eta = Some(Duration::from_secs(0));
+
+        for s in stage..4 {
+            let first = if s == stage { player } else { 0 };
+
+            for (i, peerid) in self.players.iter().enumerate().skip(first) {
+                if self.dropped.contains(&i) {
+                    continue;
+                }
+
+                queue.push(QueueEntry {
+                    player: i,
+                    peerid: *peerid,
+                    stage: s,
+                    online: self.sessions.get(peerid).map(|session| session.online).unwrap_or(false),
+                    eta: eta
+                });
+
+                eta = match (eta, self.average(s)) {
+                    (Some(eta), Some(average)) => {
+                        if s == stage && i == player {
+                            Some(average.checked_sub(began.elapsed()).unwrap_or(Duration::from_secs(0)))
+                        } else {
+                            Some(eta + average)
+                        }
+                    },
+                    _ => None
+                };
+            }
+        }
+
+        queue
+    }
+
+    fn describe(&self, peerid: &PeerKey) -> String {
+        match self.players.iter().position(|p| p == peerid) {
+            Some(i) => format!("player {} (peerid={})", i + 1, peerid.to_hex()),
+            None => format!("new player (peerid={})", peerid.to_hex())
+        }
+    }
+
+    /// The plain text status page for observers.
+    fn render(&self, players: usize) -> String {
+        let mut page = String::new();
+
+        page.push_str("Ceremony status\n\n");
+
+        match self.current {
+            Some((stage, player, began)) => {
+                page.push_str(&format!("Current turn: stage{}, {}, started {} minutes ago\n",
+                                       stage, self.describe(&self.players[player]),
+                                       began.elapsed().as_secs() / 60));
+            },
+            None if self.complete => {
+                page.push_str("The ceremony is complete.\n");
+            },
+            None if self.commitments.len() < players => {
+                page.push_str(&format!("Waiting for commitments: {} of {} received\n",
+                                       self.commitments.len(), players));
+            },
+            None => {
+                page.push_str("Between turns\n");
+            }
+        }
+
+        let queue = self.queue();
+        if queue.len() > 1 {
+            page.push_str("\nQueue:\n");
+            for entry in queue.iter().skip(1) {
+                page.push_str(&format!("  stage{}: {}, {}, starting {}\n",
+                                       entry.stage, self.describe(&entry.peerid),
+                                       if entry.online { "online" } else { "offline" },
+                                       format_eta(entry.eta)));
+            }
+        }
+
+        if !self.transfers.is_empty() {
+            page.push_str("\nTransfers:\n");
+            for (peerid, transfer) in &self.transfers {
+                page.push_str(&format!("  {:?} {} {}: {} of {} bytes\n",
+                                       transfer.ty, if transfer.sending { "to" } else { "from" },
+                                       self.describe(peerid), transfer.done, transfer.total));
+            }
+        }
+
+        page.push_str("\nCommitments:\n");
+        for (i, comm) in self.commitments.iter().enumerate() {
+            page.push_str(&format!("  player {}: {}{}\n", i + 1, comm.to_string(),
+                                   if self.dropped.contains(&i) { " (rejected)" } else { "" }));
+        }
+
+        if !self.discs.is_empty() {
+            page.push_str("\nDisc hashes:\n");
+            for disc in &self.discs {
+                page.push_str(&format!("  stage{}, player {}: sent {}, received {}\n",
+                                       disc.stage, disc.player + 1,
+                                       disc.sent.to_string(), disc.received.to_string()));
+            }
+        }
+
+        page
+    }
+}
+
+/// Serves the status page to anyone who connects to `addr`, whatever
+/// they ask for.
+fn serve_status(addr: &str, schedule: Arc<Mutex<Schedule>>, players: usize) -> Result<(), String> {
+    let listener = try!(TcpListener::bind(addr).map_err(|e| format!("could not listen on {}: {}", addr, e)));
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => continue
+            };
+
+            let _ = stream.set_read_timeout(Some(Duration::from_secs(5)));
+            let _ = stream.set_write_timeout(Some(Duration::from_secs(5)));
+
+            // Read the request, up to the end of its headers.
+            let mut request = vec![];
+            let mut buf = [0; 1024];
+            while !request.ends_with(b"\r\n\r\n") && request.len() < 8192 {
+                match stream.read(&mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => request.extend_from_slice(&buf[0..n])
+                }
+            }
+
+            let page = schedule.lock().unwrap().render(players);
+            let _ = write!(stream, "HTTP/1.0 200 OK\r\n\
+                                    Content-Type: text/plain; charset=utf-8\r\n\
+                                    Content-Length: {}\r\n\
+                                    Connection: close\r\n\r\n{}", page.len(), page);
+        }
+    });
+
+    Ok(())
+}
+
+fn format_eta(eta: Option<Duration>) -> String {
+    match eta {
+        Some(eta) if eta.as_secs() < 60 => "now".into(),
+        Some(eta) => format!("in about {} minutes", eta.as_secs() / 60),
+        None => "at an unknown time".into()
+    }
+}
+
+/// An offline player's turn in progress.
+struct OfflineTurn {
+    stage: usize,
+    /// The challenge while it is being written, under a temporary name.
+    challenge: Option<BufWriter<File>>,
+    /// The response, once it has been found, read up to the next message.
+    response: Option<BufReader<File>>
+}
+
+/// Exchanges messages with players through files rather than over the
+/// network, for players who cannot connect to the coordinator at all.
+/// Each player has a directory, `playerN`, holding their commitment as
+/// text in `commitment`. In each stage, the messages to the player are
+/// written to `stageN.challenge` and their replies are read from
+/// `stageN.response`, encoded exactly like the discs the compute machine
+/// reads and writes, so a challenge can be burned as the disc the
+/// network machine would have produced and the disc burned in return
+/// copied back as the response. A player who must take their turn again
+/// finds their response moved to `stageN.superseded` and a new challenge
+/// in place of the old.
+struct Offline {
+    dir: PathBuf,
+    turns: Mutex<HashMap<PeerKey, OfflineTurn>>
+}
+
+impl Offline {
+    fn new(dir: &str, players: usize) -> Result<Offline, String> {
+        let dir = PathBuf::from(dir);
+
+        for player in 0..players {
+            let path = dir.join(format!("player{}", player + 1));
+            try!(fs::create_dir_all(&path).map_err(|e| format!("could not create {}: {}", path.display(), e)));
+        }
+
+        Ok(Offline {
+            dir: dir,
+            turns: Mutex::new(HashMap::new())
+        })
+    }
+
+    /// Offline players have no keys, so each is identified by the index
+    /// of their directory instead.
+    fn peerid(player: usize) -> PeerKey {
+        let mut peerid = [0; 32];
+
+        for i in 0..8 {
+            peerid[31 - i] = ((player as u64) >> (i * 8)) as u8;
+        }
+
+        peerid
+    }
+
+    fn path(&self, peerid: &PeerKey, name: &str) -> PathBuf {
+        let player = peerid[24..].iter().fold(0, |n, &b| (n << 8) | b as usize);
+
+        self.dir.join(format!("player{}", player + 1)).join(name)
+    }
+
+    /// Where the player's reply in `stage` is expected, stage 0 being
+    /// their commitment.
+    fn response_path(&self, peerid: &PeerKey, stage: usize) -> PathBuf {
+        if stage == 0 {
+            self.path(peerid, "commitment")
+        } else {
+            self.path(peerid, &format!("stage{}.response", stage))
+        }
+    }
+
+    /// The player's turn in `stage`, begun afresh if their last turn was
+    /// in another stage.
+    fn turn<'a>(turns: &'a mut HashMap<PeerKey, OfflineTurn>, peerid: &PeerKey, stage: usize) -> &'a mut OfflineTurn {
+        let turn = turns.entry(*peerid).or_insert(OfflineTurn {
+            stage: stage,
+            challenge: None,
+            response: None
+        });
+
+        if turn.stage != stage {
+            *turn = OfflineTurn {
+                stage: stage,
+                challenge: None,
+                response: None
+            };
+        }
+
+        turn
+    }
+
+    /// Appends a message to the player's challenge for `stage`.
+    fn write<T: Encodable>(&self, peerid: &PeerKey, stage: usize, obj: &T) -> Result<(), String> {
+        let mut turns = self.turns.lock().unwrap();
+        let turn = Offline::turn(&mut turns, peerid, stage);
+
+        if turn.challenge.is_none() {
+            let path = self.path(peerid, &format!("stage{}.challenge.partial", stage));
+            let f = try!(File::create(&path).map_err(|e| format!("could not create {}: {}", path.display(), e)));
+
+            turn.challenge = Some(BufWriter::new(f));
+        }
+
+        encode_into(obj, turn.challenge.as_mut().unwrap(), Infinite).map_err(|e| e.to_string())
+    }
 
-const LISTEN_ADDR: &'static str = "0.0.0.0:65530";
-const PLAYERS: usize = 1;
-pub const THREADS: usize = 128;
+    /// Moves a finished challenge into place, so that the player never
+    /// picks up one that is incomplete.
+    fn publish(&self, peerid: &PeerKey, turn: &mut OfflineTurn) -> Result<(), String> {
+        if let Some(mut challenge) = turn.challenge.take() {
+            try!(challenge.flush().map_err(|e| e.to_string()));
+
+            let path = self.path(peerid, &format!("stage{}.challenge", turn.stage));
+            try!(fs::rename(self.path(peerid, &format!("stage{}.challenge.partial", turn.stage)), &path)
+                 .map_err(|e| format!("could not write {}: {}", path.display(), e)));
+
+            info!("Challenge for stage{} written to {}", turn.stage, path.display());
+        }
+
+        Ok(())
+    }
+
+    /// Sets the player's response in `stage` aside, as
+    /// `stageN.superseded`, so that they can respond to a new challenge.
+    fn retry(&self, peerid: &PeerKey, stage: usize) -> Result<(), String> {
+        let mut turns = self.turns.lock().unwrap();
+        turns.remove(peerid);
+
+        let path = self.response_path(peerid, stage);
+        fs::rename(&path, self.path(peerid, &format!("stage{}.superseded", stage)))
+            .map_err(|e| format!("could not move {}: {}", path.display(), e))
+    }
+
+    /// Reads the next message from the player's response in `stage`, or
+    /// returns `None` if there is no response yet. The challenge is
+    /// published first, since the player can only respond to it once
+    /// every message has been written.
+    fn read<T: Decodable>(&self, peerid: &PeerKey, stage: usize, limit: u64) -> Result<Option<T>, String> {
+        let mut turns = self.turns.lock().unwrap();
+        let turn = Offline::turn(&mut turns, peerid, stage);
+
+        try!(self.publish(peerid, turn));
+
+        let path = self.response_path(peerid, stage);
+
+        if stage == 0 {
+            // The commitment is typed in by hand from the compute
+            // machine's screen, so it is kept as text.
+            let mut contents = String::new();
+            match File::open(&path) {
+                Ok(mut f) => {
+                    try!(f.read_to_string(&mut contents).map_err(|e| e.to_string()));
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+                Err(e) => return Err(format!("could not open {}: {}", path.display(), e))
+            }
+
+            let comm = try!(Digest256::from_string(contents.trim()).ok_or("invalid commitment".to_string()));
+            let encoded = try!(encode(&comm, Infinite).map_err(|e| e.to_string()));
+
+            return decode(&encoded).map(Some).map_err(|e| e.to_string());
+        }
+
+        if turn.response.is_none() {
+            match File::open(&path) {
+                Ok(f) => {
+                    turn.response = Some(BufReader::new(f));
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+                Err(e) => return Err(format!("could not open {}: {}", path.display(), e))
+            }
+        }
+
+        let response = turn.response.as_mut().unwrap();
+        let offset = try!(response.seek(SeekFrom::Current(0)).map_err(|e| e.to_string()));
+
+        match decode_from(&mut *response, Bounded(limit)) {
+            Ok(msg) => Ok(Some(msg)),
+            Err(e) => {
+                // The response may not have been copied in full yet, so
+                // the message is read again on the next attempt.
+                let _ = response.seek(SeekFrom::Start(offset));
+
+                Err(format!("invalid response in {}: {}", path.display(), e))
+            }
+        }
+    }
+}
 
 #[derive(Clone)]
 struct ConnectionHandler {
-    peers: Arc<Mutex<HashMap<[u8; 8], Option<(TcpStream, u8, u8)>>>>,
-    notifier: Sender<[u8; 8]>
+    peers: Arc<Mutex<HashMap<PeerKey, Option<(SecureStream<Box<Stream>>, u64, u64)>>>>,
+    /// Players from before a restart who have not reconnected yet, and
+    /// the message id to resume each of them at.
+    recovered: Arc<Mutex<HashMap<PeerKey, u64>>>,
+    notifier: Sender<Event>,
+    schedule: Arc<Mutex<Schedule>>,
+    /// Set if players take part through files rather than connections.
+    offline: Option<Arc<Offline>>,
+    config: CoordinatorConfig
 }
 
 impl ConnectionHandler {
-    fn new() -> ConnectionHandler {
+    /// Starts the ceremony in the background once the transcript has
+    /// been replayed. The ceremony is over when the returned thread
+    /// exits.
+    fn new(config: CoordinatorConfig) -> Result<(ConnectionHandler, JoinHandle<()>), String> {
         let (tx, rx) = channel();
+        let (ready_tx, ready_rx) = channel();
+
+        let offline = match config.offline_dir {
+            Some(ref dir) => Some(Arc::new(try!(Offline::new(dir, config.players)))),
+            None => None
+        };
 
         let handler = ConnectionHandler {
             peers: Arc::new(Mutex::new(HashMap::new())),
-            notifier: tx
+            recovered: Arc::new(Mutex::new(HashMap::new())),
+            notifier: tx,
+            schedule: Arc::new(Mutex::new(Schedule::new())),
+            offline: offline,
+            config: config
         };
 
-        {
+        let ceremony = {
             let handler = handler.clone();
             thread::spawn(move || {
-                handler.run(rx);
-            });
+                handler.run(rx, ready_tx);
+            })
+        };
+
+        // Players reconnecting after a restart must not be mistaken for
+        // new players, so wait until the transcript has been replayed.
+        match ready_rx.recv() {
+            Ok(Ok(())) => Ok((handler, ceremony)),
+            Ok(Err(e)) => Err(e),
+            Err(_) => Err("coordinator thread exited unexpectedly".into())
+        }
+    }
+
+    fn peers_path(&self) -> String {
+        format!("{}.peers", self.config.transcript_path)
+    }
+
+    /// The transcript does not identify the players' network clients, so
+    /// their keys are kept alongside it in order to recognize them when
+    /// they reconnect after a restart.
+    fn save_peers(&self, peers: &[PeerKey]) -> Result<(), String> {
+        let path = self.peers_path();
+        let tmp_path = format!("{}.tmp", path);
+
+        {
+            let mut f = try!(File::create(&tmp_path).map_err(|e| e.to_string()));
+            try!(encode_into(&peers.to_vec(), &mut f, Infinite).map_err(|e| e.to_string()));
+            try!(f.sync_all().map_err(|e| e.to_string()));
+        }
+
+        fs::rename(&tmp_path, &path).map_err(|e| e.to_string())
+    }
+
+    fn load_peers(&self) -> Result<Vec<PeerKey>, String> {
+        let path = self.peers_path();
+        let mut f = try!(File::open(&path).map_err(|e| format!("could not open {}: {}", path, e)));
+
+        decode_from(&mut f, Infinite).map_err(|e| format!("could not read {}: {}", path, e))
+    }
+
+    fn pending_path(&self) -> String {
+        format!("{}.pending", self.config.transcript_path)
+    }
+
+    /// Keeps a contribution to `stage` alongside the transcript until it
+    /// has been verified, in the layout of `SavedPending`, preceded by
+    /// the stage.
+    fn save_pending<T: Encodable>(&self, stage: usize, pending: &Pending<T>) -> Result<(), String> {
+        let path = self.pending_path();
+        let tmp_path = format!("{}.tmp", path);
+
+        {
+            let mut f = BufWriter::new(try!(File::create(&tmp_path).map_err(|e| e.to_string())));
+            try!(encode_into(&stage, &mut f, Infinite).map_err(|e| e.to_string()));
+            try!(encode_into(&pending.player, &mut f, Infinite).map_err(|e| e.to_string()));
+            try!(encode_into(&pending.key, &mut f, Infinite).map_err(|e| e.to_string()));
+            try!(encode_into(&*pending.contents, &mut f, Infinite).map_err(|e| e.to_string()));
+            try!(encode_into(&pending.ihash, &mut f, Infinite).map_err(|e| e.to_string()));
+            try!(encode_into(&pending.last_message_hash, &mut f, Infinite).map_err(|e| e.to_string()));
+            let f = try!(f.into_inner().map_err(|e| e.to_string()));
+            try!(f.sync_all().map_err(|e| e.to_string()));
         }
 
-        handler
+        fs::rename(&tmp_path, &path).map_err(|e| e.to_string())
+    }
+
+    /// Forgets the saved contribution once it is in the transcript.
+    fn discard_pending(&self) {
+        let _ = fs::remove_file(self.pending_path());
     }
 
-    fn do_with_stream<T, E, F: FnMut(&mut TcpStream, &mut u8, &u8) -> Result<T, E>>(&self, peerid: &[u8; 8], mut cb: F) -> T
+    /// Reads back the contribution that was pending when the coordinator
+    /// stopped, unless it had been resolved by then.
+    fn load_pending(&self, resume: &mut Resume) -> Result<(), String> {
+        let path = self.pending_path();
+        let mut f = match File::open(&path) {
+            Ok(f) => BufReader::new(f),
+            Err(_) => return Ok(())
+        };
+
+        let stage: usize = try!(decode_from(&mut f, Infinite).map_err(|e| format!("could not read {}: {}", path, e)));
+        let player = match stage {
+            1 => {
+                let saved: SavedPending<Stage1Contents> = try!(decode_from(&mut f, Infinite)
+                                                               .map_err(|e| format!("could not read {}: {}", path, e)));
+                let player = saved.player;
+                resume.pending1 = Some(saved);
+
+                player
+            },
+            2 => {
+                let saved: SavedPending<Stage2Contents> = try!(decode_from(&mut f, Infinite)
+                                                               .map_err(|e| format!("could not read {}: {}", path, e)));
+                let player = saved.player;
+                resume.pending2 = Some(saved);
+
+                player
+            },
+            3 => {
+                let saved: SavedPending<Stage3Contents> = try!(decode_from(&mut f, Infinite)
+                                                               .map_err(|e| format!("could not read {}: {}", path, e)));
+                let player = saved.player;
+                resume.pending3 = Some(saved);
+
+                player
+            },
+            _ => return Err(format!("{} holds a contribution to stage {}", path, stage))
+        };
+
+        // The contribution is still pending if it belongs to the next
+        // player to take a turn; if it was resolved, it is in the
+        // transcript already.
+        let players = self.config.players;
+        let next = resume.done[0..(stage - 1)].iter().all(|&done| done == players) &&
+                   resume.done[stage - 1] <= player && player < resume.peers.len() &&
+                   (resume.done[stage - 1]..player).all(|i| resume.dropped.contains(&i)) &&
+                   !resume.dropped.contains(&player);
+
+        if next {
+            info!("Recovered the contribution of player {} to stage{}, which had not been verified yet", player + 1, stage);
+        } else {
+            resume.pending1 = None;
+            resume.pending2 = None;
+            resume.pending3 = None;
+            self.discard_pending();
+        }
+
+        Ok(())
+    }
+
+    /// Picks up a contribution that was pending when the coordinator
+    /// stopped, verifying it in the background again. Its player may not
+    /// have been told that their turn was over, so they are told again.
+    fn restore_pending<T: Send + Sync + 'static>(
+        &self,
+        stage: usize,
+        peerid: &PeerKey,
+        saved: SavedPending<T>,
+        accepted: &Arc<T>,
+        pubkey: PublicKey,
+        verify: fn(&T, &T, &PublicKey, &Observer) -> bool
+    ) -> Pending<T>
+    {
+        info!("Verifying transformation of stage{} from peerid={} in the background", stage, peerid.to_hex());
+
+        let contents = Arc::new(saved.contents);
+        let verified = {
+            let (contents, accepted) = (contents.clone(), accepted.clone());
+            verify_in_background(stage, *peerid, move |observer| {
+                verify(&contents, &accepted, &pubkey, observer)
+            })
+        };
+
+        self.conclude_turn(peerid, stage, false);
+
+        Pending {
+            player: saved.player,
+            peerid: *peerid,
+            key: saved.key,
+            contents: contents,
+            ihash: saved.ihash,
+            last_message_hash: saved.last_message_hash,
+            verified: verified
+        }
+    }
+
+    /// Creates the transcript, or if one was left behind by an earlier
+    /// run, replays it to find out where the ceremony stopped. Anything
+    /// after the last complete contribution is cut off so that the
+    /// transcript can be continued.
+    fn open_transcript(&self, cs: &CS, accumulator: Option<&Accumulator>) -> Result<(TranscriptWriter<File>, Resume), String> {
+        let path = &self.config.transcript_path;
+
+        if fs::metadata(path).map(|m| m.len() == 0).unwrap_or(true) {
+            info!("Creating transcript file...");
+            let f = try!(File::create(path).map_err(|e| format!("could not create {}: {}", path, e)));
+            let transcript = try!(TranscriptWriter::new(f, &Header::new(cs_hash(cs), self.config.players)));
+            try!(self.save_peers(&[]));
+            self.discard_pending();
+
+            return Ok((transcript, Resume::new()));
+        }
+
+        info!("Replaying the existing transcript to recover the ceremony...");
+        let mut f = try!(OpenOptions::new().read(true).write(true).open(path)
+                         .map_err(|e| format!("could not open {}: {}", path, e)));
+
+        if try!(TranscriptReader::open(&mut f)).header().players != self.config.players {
+            return Err(format!("{} is the transcript of a ceremony with a different number of players", path));
+        }
+        try!(f.seek(SeekFrom::Start(0)).map_err(|e| e.to_string()));
+
+        let mut resume = Resume::new();
+        let offset = match try!(replay(&mut f, cs, accumulator.map(|acc| acc.powers()))) {
+            Progress::Complete => {
+                return Err(format!("the ceremony in {} is already complete", path));
+            },
+            Progress::Commitments(commitments, offset) => {
+                resume.commitments = commitments;
+
+                offset
+            },
+            Progress::Started(checkpoint) => {
+                let offset = checkpoint.offset();
+                let stage = checkpoint.stage();
+
+                for done in resume.done[0..(stage - 1)].iter_mut() {
+                    *done = self.config.players;
+                }
+                resume.done[stage - 1] = checkpoint.next_player();
+                resume.dropped = checkpoint.report().players.iter()
+                                           .filter_map(|p| p.rejected.as_ref().map(|r| r.player))
+                                           .collect();
+                resume.retries = checkpoint.report().players.iter()
+                                           .filter_map(|p| p.rejected.as_ref())
+                                           .filter_map(|r| r.retried.map(|player| (player, r.stage)))
+                                           .collect();
+                resume.pubkeys = checkpoint.pubkeys().to_vec();
+                resume.commitments = checkpoint.commitments().to_vec();
+                resume.last_message_hash = Some(*checkpoint.last_message_hash());
+
+                let (stage1, stage2, stage3) = checkpoint.into_stages();
+                resume.stage1 = Some(stage1);
+                resume.stage2 = stage2;
+                resume.stage3 = stage3;
+
+                offset
+            }
+        };
+
+        let mut peers = try!(self.load_peers());
+        if peers.len() < resume.commitments.len() {
+            return Err(format!("{} does not list every player in the transcript", self.peers_path()));
+        }
+        // A player may have been accepted without their commitment
+        // reaching the transcript; they will have to start over.
+        peers.truncate(resume.commitments.len());
+        try!(self.save_peers(&peers));
+        resume.peers = peers;
+
+        try!(self.load_pending(&mut resume));
+
+        let len = try!(f.metadata().map_err(|e| e.to_string())).len();
+        if len > offset {
+            warn!("Discarding the last {} bytes of the transcript, which do not complete a contribution", len - offset);
+        }
+        try!(f.set_len(offset).map_err(|e| e.to_string()));
+        try!(f.seek(SeekFrom::Start(offset)).map_err(|e| e.to_string()));
+
+        info!("Recovered {} commitments; players done with stages 1, 2 and 3: {}, {}, {}; rejected: {}",
+              resume.commitments.len(), resume.done[0], resume.done[1], resume.done[2], resume.dropped.len());
+
+        Ok((TranscriptWriter::append(f), resume))
+    }
+
+    /// Runs `cb` upon the player's stream until it succeeds, waiting for
+    /// the player to reconnect if the connection fails. Gives up if this
+    /// takes longer than `player_timeout` seconds.
+    fn do_with_stream<T, F: FnMut(&mut SecureStream<Box<Stream>>, &mut u64, &u64) -> Result<T, String>>(&self, peerid: &PeerKey, mut cb: F) -> Result<T, String>
     {
         let waittime = Duration::from_secs(10);
+        let deadline = Instant::now() + Duration::from_secs(self.config.player_timeout);
 
         loop {
-            // The stream is always there, because we put it back
-            // even if it fails.
-            let (mut stream, mut our_msgid, their_msgid): (TcpStream, u8, u8) = {
+            if Instant::now() >= deadline {
+                self.schedule.lock().unwrap().set_online(peerid, false);
+
+                return Err(format!("did not respond within {} seconds", self.config.player_timeout));
+            }
+
+            // The stream is always there once the peer has connected,
+            // because we put it back even if it fails. Peers from before
+            // a restart may not have reconnected yet.
+            let stream = {
                 let mut peers = self.peers.lock().unwrap();
-                peers.get_mut(peerid).unwrap().take()
-            }.unwrap();
+                peers.get_mut(peerid).and_then(|p| p.take())
+            };
+            let (mut stream, mut our_msgid, their_msgid): (SecureStream<Box<Stream>>, u64, u64) = match stream {
+                Some(stream) => stream,
+                None => {
+                    thread::sleep(waittime);
+                    continue;
+                }
+            };
 
             let val = cb(&mut stream, &mut our_msgid, &their_msgid);
 
             {
-                // Put it back
+                // Put it back, unless the player was dropped meanwhile.
                 let mut peers = self.peers.lock().unwrap();
-                *peers.get_mut(peerid).unwrap() = Some((stream, our_msgid, their_msgid));
+                if let Some(slot) = peers.get_mut(peerid) {
+                    *slot = Some((stream, our_msgid, their_msgid));
+                }
             }
 
             match val {
-                Err(_) => {
+                Err(e) => {
+                    warn!("Communication with peerid={} failed: {}", peerid.to_hex(), e);
+                    self.schedule.lock().unwrap().set_online(peerid, false);
                     thread::sleep(waittime);
                 },
                 Ok(v) => {
-                    return v
+                    return Ok(v)
                 }
             }
         }
     }
 
-    fn read<T: Decodable>(&self, peerid: &[u8; 8]) -> T
+    /// Receives the next message from the player, retrying if the
+    /// connection fails. Fails if the player sent a message that can
+    /// never be accepted: one that does not decode, or is larger than
+    /// `max_message_size`, or if they do not send it in time.
+    fn read<T: Decodable>(&self, peerid: &PeerKey, ty: MessageType) -> Result<T, Invalid>
     {
-        self.do_with_stream(peerid, |s, ourid, _| {
-            match decode_from(s, Infinite) {
-                Ok(v) => {
-                    let _ = s.write_all(&NETWORK_ACK);
-                    let _ = s.flush();
+        if let Some(ref offline) = self.offline {
+            return self.read_offline(offline, peerid, ty).map_err(|e| Invalid {
+                reason: e,
+                data: None
+            });
+        }
 
-                    *ourid += 1;
+        let mut incoming = Incoming::new(ty, self.config.max_message_size);
+        let mut progress = wire::Progress::new();
 
-                    Ok(v)
-                },
-                Err(e) => {
-                    Err(e)
+        let received = self.do_with_stream(peerid, |s, ourid, _| {
+            let received = incoming.receive(s, *ourid + 1, |done, total| {
+                if let Some(percent) = progress.update(done, total) {
+                    info!("Receiving {:?} from peerid={}: {}% of {} bytes", ty, peerid.to_hex(), percent, total);
                 }
+                self.track_transfer(peerid, ty, false, done, total);
+            });
+
+            if let Err(e) = received {
+                return match incoming.refused() {
+                    Some(reason) => Ok(Err(Invalid {
+                        reason: format!("sent an invalid message: {}", reason),
+                        data: None
+                    })),
+                    None => Err(e)
+                };
             }
-        })
+
+            // The message has been received in full and acknowledged,
+            // so the player has moved on, and if it does not decode,
+            // asking for it again will not help.
+            *ourid += 1;
+
+            Ok(incoming.decode().map_err(|e| Invalid {
+                reason: format!("sent an invalid message: {}", e),
+                data: Some(incoming.data().to_vec())
+            }))
+        });
+
+        match received {
+            Ok(received) => received,
+            Err(e) => Err(Invalid {
+                reason: e,
+                data: None
+            })
+        }
     }
 
-    fn write<T: Encodable>(&self, peerid: &[u8; 8], obj: &T)
+    /// Sends a message to the player, retrying if the connection fails.
+    /// Fails if the player does not receive it in time.
+    fn write<T: Encodable>(&self, peerid: &PeerKey, ty: MessageType, obj: &T) -> Result<(), String>
     {
+        if let Some(ref offline) = self.offline {
+            let stage = self.stage_of(peerid);
+            if let Err(e) = offline.write(peerid, stage, obj) {
+                panic!("could not write {:?} to the challenge of peerid={}: {}", ty, peerid.to_hex(), e);
+            }
+
+            return Ok(());
+        }
+
+        let outgoing = Outgoing::new(ty, obj).expect("messages should never fail to encode");
+        let mut progress = wire::Progress::new();
         let mut incremented = false;
 
         self.do_with_stream(peerid, move |s, ourid, theirid| {
@@ -129,197 +1186,880 @@ impl ConnectionHandler {
                 return Ok(())
             }
 
-            if encode_into(obj, s, Infinite).is_err() {
-                return Err("couldn't send data".to_string());
+            // Resumes from the last acknowledged chunk if an earlier
+            // attempt was interrupted.
+            outgoing.send(s, *ourid, |done, total| {
+                if let Some(percent) = progress.update(done, total) {
+                    info!("Sending {:?} to peerid={}: {}% of {} bytes", ty, peerid.to_hex(), percent, total);
+                }
+                self.track_transfer(peerid, ty, true, done, total);
+            })
+        })
+    }
+
+    /// Waits for an offline player's response, and reads the next message
+    /// from it. Gives up if the response does not arrive within
+    /// `player_timeout` seconds.
+    fn read_offline<T: Decodable>(&self, offline: &Offline, peerid: &PeerKey, ty: MessageType) -> Result<T, String>
+    {
+        let stage = self.stage_of(peerid);
+        let mut waiting = false;
+        let deadline = Instant::now() + Duration::from_secs(self.config.player_timeout);
+
+        loop {
+            if Instant::now() >= deadline {
+                return Err(format!("did not respond within {} seconds", self.config.player_timeout));
             }
 
-            if s.flush().is_err() {
-                return Err("couldn't flush buffer".to_string());
+            match offline.read(peerid, stage, self.config.max_message_size) {
+                Ok(Some(msg)) => return Ok(msg),
+                Ok(None) => {
+                    if !waiting {
+                        info!("Waiting for {} (peerid={})", offline.response_path(peerid, stage).display(), peerid.to_hex());
+                        waiting = true;
+                    }
+                },
+                Err(e) => {
+                    warn!("Could not read {:?} from peerid={}: {}", ty, peerid.to_hex(), e);
+                }
             }
 
-            let mut ack: [u8; 4] = [0; 4];
-            let _ = s.read_exact(&mut ack);
+            thread::sleep(Duration::from_secs(10));
+        }
+    }
+
+    /// The stage of the turn `peerid` is taking, or 0 while they have
+    /// yet to commit.
+    fn stage_of(&self, peerid: &PeerKey) -> usize {
+        let schedule = self.schedule.lock().unwrap();
+
+        match schedule.current {
+            Some((stage, player, _)) if schedule.players.get(player) == Some(peerid) => stage,
+            _ => 0
+        }
+    }
+
+    /// Records the rejection of a player's contribution in the
+    /// transcript, and disconnects them. The ceremony carries on without
+    /// them, from the last accepted contribution.
+    fn reject<W: Write>(
+        &self,
+        transcript: &mut TranscriptWriter<W>,
+        dropped: &mut Vec<usize>,
+        peerid: &PeerKey,
+        rejection: Rejection
+    )
+    {
+        error!("Rejecting contribution to stage{} of player {}: {} (peerid={})",
+               rejection.stage, rejection.player + 1, rejection.reason, peerid.to_hex());
+
+        transcript.write(RecordType::Rejected, &rejection).unwrap();
+
+        dropped.push(rejection.player);
+        self.schedule.lock().unwrap().reject(rejection.player);
+        self.peers.lock().unwrap().remove(peerid);
+    }
+
+    /// Marks the start of a player's turn, and logs the queue of players
+    /// after them in this stage.
+    fn begin_turn(&self, stage: usize, player: usize) {
+        let mut schedule = self.schedule.lock().unwrap();
+        schedule.begin_turn(stage, player);
+
+        for entry in schedule.queue().iter().skip(1).filter(|entry| entry.stage == stage) {
+            info!("Queued for stage{}: player {} (peerid={}, {}), starting {}",
+                  entry.stage, entry.player + 1, entry.peerid.to_hex(),
+                  if entry.online { "online" } else { "offline" }, format_eta(entry.eta));
+        }
+    }
+
+    fn end_turn(&self) {
+        self.schedule.lock().unwrap().end_turn();
+    }
+
+    /// Tells the player whether they must take their turn in `stage`
+    /// again. Offline players are told by a new challenge taking the
+    /// place of the old one.
+    fn conclude_turn(&self, peerid: &PeerKey, stage: usize, again: bool) {
+        if again {
+            info!("Asking peerid={} to take their turn in stage{} again", peerid.to_hex(), stage);
+        }
+
+        match self.offline {
+            Some(ref offline) => {
+                if again {
+                    if let Err(e) = offline.retry(peerid, stage) {
+                        panic!("could not set aside the response of peerid={}: {}", peerid.to_hex(), e);
+                    }
+                }
+            },
+            None => {
+                // A player who cannot be told is rejected once their next
+                // turn cannot be sent to them either.
+                if let Err(e) = self.write(peerid, MessageType::Retry, &again) {
+                    warn!("Could not tell peerid={} whether to take their turn in stage{} again: they {}",
+                          peerid.to_hex(), stage, e);
+                }
+            }
+        }
+    }
 
-            if ack != NETWORK_ACK {
-                return Err("bad ack".to_string())
+    fn track_transfer(&self, peerid: &PeerKey, ty: MessageType, sending: bool, done: u64, total: u64) {
+        let mut schedule = self.schedule.lock().unwrap();
+
+        if done == total {
+            schedule.transfers.remove(peerid);
+        } else {
+            schedule.transfers.insert(*peerid, TransferProgress {
+                ty: ty,
+                sending: sending,
+                done: done,
+                total: total
+            });
+        }
+    }
+
+    fn record_discs(&self, stage: usize, player: usize, sent: Digest256, received: Digest256) {
+        info!("Hashes of the discs of player {} in stage{}: sent {}, received {}",
+              player + 1, stage, sent.to_string(), received.to_string());
+
+        self.schedule.lock().unwrap().discs.push(DiscHashes {
+            stage: stage,
+            player: player,
+            sent: sent,
+            received: received
+        });
+    }
+
+    /// Waits for the verification of a contribution. If it is valid, it
+    /// is written to the transcript and becomes the accepted contribution
+    /// that later players build upon; otherwise the player is rejected,
+    /// and `retry` is the player who built upon it and takes their turn
+    /// again, if any.
+    fn resolve<T: Encodable, W: Write>(
+        &self,
+        transcript: &mut TranscriptWriter<W>,
+        dropped: &mut Vec<usize>,
+        pending: Pending<T>,
+        stage: usize,
+        record: RecordType,
+        retry: Option<usize>,
+        accepted: &mut Arc<T>,
+        last_message_hash: &mut Digest256
+    ) -> bool
+    {
+        if !pending.verified.join().unwrap_or(false) {
+            let mut evidence = vec![];
+            if let Some((ref pubkey, ref nizks)) = pending.key {
+                evidence.push(as_evidence(RecordType::PublicKey, pubkey));
+                evidence.push(as_evidence(RecordType::PublicKeyNizks, nizks));
             }
+            evidence.push(as_evidence(record, &*pending.contents));
+            evidence.push(as_evidence(RecordType::IHash, &pending.ihash));
+
+            self.reject(transcript, dropped, &pending.peerid, Rejection {
+                player: pending.player,
+                stage: stage,
+                reason: format!("did not perform valid stage{} transformation", stage),
+                evidence: evidence,
+                retried: retry
+            });
+            self.discard_pending();
 
-            Ok(())
-        })
+            return false;
+        }
+
+        info!("Contribution of player {} to stage{} verified, writing it to transcript",
+              pending.player + 1, stage);
+
+        if let Some((ref pubkey, ref nizks)) = pending.key {
+            transcript.write(RecordType::PublicKey, pubkey).unwrap();
+            transcript.write(RecordType::PublicKeyNizks, nizks).unwrap();
+        }
+        transcript.write(record, &*pending.contents).unwrap();
+        transcript.write(RecordType::IHash, &pending.ihash).unwrap();
+        self.discard_pending();
+
+        *accepted = pending.contents;
+        *last_message_hash = pending.last_message_hash;
+
+        true
     }
 
-    fn run(&self, new_peers: Receiver<[u8; 8]>)
+    fn run(&self, events: Receiver<Event>, ready: Sender<Result<(), String>>)
     {
-        use std::fs::File;
-
         info!("Loading R1CS from disk and performing QAP reduction...");
 
         let cs = {
-            if USE_DUMMY_CS {
+            if self.config.use_dummy_cs {
                 CS::dummy()
             } else {
                 CS::from_file()
             }
         };
 
-        info!("Creating transcript file...");
-        let mut transcript = File::create("transcript").unwrap();
-        encode_into(&PLAYERS, &mut transcript, Infinite).unwrap();
+        // A ceremony built upon an accumulator starts stage 1 from its
+        // first powers, which must be enough for the circuit.
+        let accumulator = match self.config.accumulator_path {
+            Some(ref path) => {
+                info!("Loading the powers of tau accumulator...");
 
-        info!("Waiting for players to connect...");
+                let acc = File::open(path).map_err(|e| e.to_string())
+                              .and_then(|mut f| decode_from(&mut BufReader::new(&mut f), Infinite).map_err(|e| e.to_string()))
+                              .and_then(|acc: Accumulator| acc.truncate(&cs).map(|initial| (acc, initial)));
 
-        let mut peers = vec![];
-        let mut commitments: Vec<Digest256> = vec![];
-        for peerid in new_peers.into_iter().take(PLAYERS) {
-            info!("Initializing new player (peerid={})", peerid.to_hex());
-            info!("Asking for commitment to PublicKey (peerid={})", peerid.to_hex());
-            let comm: Digest256 = self.read(&peerid);
-            info!("PublicKey Commitment received (peerid={})", peerid.to_hex());
+                match acc {
+                    Ok(acc) => Some(acc),
+                    Err(e) => {
+                        let _ = ready.send(Err(format!("could not use the accumulator {}: {}", path, e)));
+                        return;
+                    }
+                }
+            },
+            None => None
+        };
 
-            info!("Writing commitment to transcript");
-            encode_into(&comm, &mut transcript, Infinite).unwrap();
+        let (mut transcript, resume) = match self.open_transcript(&cs, accumulator.as_ref().map(|&(ref acc, _)| acc)) {
+            Ok(opened) => opened,
+            Err(e) => {
+                let _ = ready.send(Err(e));
+                return;
+            }
+        };
 
-            commitments.push(comm);
-            peers.push(peerid);
+        {
+            let mut recovered = self.recovered.lock().unwrap();
+            let mut schedule = self.schedule.lock().unwrap();
+            schedule.commitments = resume.commitments.clone();
+            for (i, peerid) in resume.peers.iter().enumerate() {
+                schedule.players.push(*peerid);
+
+                if resume.dropped.contains(&i) {
+                    schedule.reject(i);
+                } else {
+                    recovered.insert(*peerid, resume.msgid(i));
+                    schedule.set_state(peerid, if i < resume.done[2] { SessionState::Finished } else { SessionState::Waiting });
+                }
+            }
         }
 
-        // The remote end should never hang up, so this should always be `PLAYERS`.
-        assert_eq!(peers.len(), PLAYERS);
-
-        // Hash of all the commitments.
-        let hash_of_commitments = Digest512::from(&commitments).unwrap();
+        let _ = ready.send(Ok(()));
 
-        info!("All players are ready");
+        info!("Waiting for players to connect...");
 
-        // Hash of the last message
-        let mut last_message_hash = Digest256::from(&commitments).unwrap();
+        let mut peers = resume.peers;
+        let mut commitments = resume.commitments;
+        let mut dropped = resume.dropped;
+        // Each new player's commitment is awaited in a session of its
+        // own, and players take their turns in the order in which their
+        // commitments arrive.
+        while commitments.len() < self.config.players {
+            match events.recv().unwrap() {
+                Event::Connected(peerid) => {
+                    info!("Initializing new player (peerid={})", peerid.to_hex());
+
+                    let handler = self.clone();
+                    thread::spawn(move || {
+                        info!("Asking for commitment to PublicKey (peerid={})", peerid.to_hex());
+                        match handler.read(&peerid, MessageType::Commitment) {
+                            Ok(comm) => {
+                                let _ = handler.notifier.send(Event::Committed(peerid, comm));
+                            },
+                            Err(invalid) => {
+                                warn!("Turning away peerid={}, who {}", peerid.to_hex(), invalid.reason);
 
-        info!("Initializing stage1 with constraint system");
+                                let mut peers = handler.peers.lock().unwrap();
+                                let mut schedule = handler.schedule.lock().unwrap();
+                                peers.remove(&peerid);
+                                schedule.sessions.remove(&peerid);
+                            }
+                        }
+                    });
+                },
+                Event::Committed(peerid, comm) => {
+                    info!("PublicKey Commitment received (peerid={})", peerid.to_hex());
 
-        let mut stage1 = Stage1Contents::new(&cs);
-        for (comm, peerid) in commitments.iter().zip(peers.iter()) {
-            info!("Sending stage1 to peerid={}", peerid.to_hex());
+                    peers.push(peerid);
+                    self.save_peers(&peers).unwrap();
 
-            self.write(peerid, &hash_of_commitments);
-            self.write(peerid, &stage1);
-            self.write(peerid, &last_message_hash);
+                    info!("Writing commitment to transcript");
+                    transcript.write(RecordType::Commitment, &comm).unwrap();
 
-            info!("Receiving public key from peerid={}", peerid.to_hex());
-            let pubkey = self.read::<PublicKey>(peerid);
+                    commitments.push(comm);
 
-            info!("Receiving nizks from peerid={}", peerid.to_hex());
-            let nizks = self.read::<PublicKeyNizks>(peerid);
+                    let mut schedule = self.schedule.lock().unwrap();
+                    schedule.players.push(peerid);
+                    schedule.commitments.push(comm);
+                    schedule.set_state(&peerid, SessionState::Waiting);
 
-            if pubkey.hash() != *comm {
-                error!("Peer did not properly commit to their public key (peerid={})", peerid.to_hex());
-                panic!("cannot recover.");
+                    info!("{} of {} players have committed", commitments.len(), self.config.players);
+                }
             }
+        }
 
-            if !nizks.is_valid(&pubkey, &hash_of_commitments) {
-                error!("Peer did not provide proof that they possess the secrets! (peerid={})", peerid.to_hex());
-                panic!("cannot recover.");
+        // No further players are accepted, and those who connected but
+        // did not commit in time are turned away.
+        drop(events);
+        {
+            let mut peers = self.peers.lock().unwrap();
+            let mut schedule = self.schedule.lock().unwrap();
+            let late: Vec<PeerKey> = schedule.sessions.iter()
+                                             .filter(|&(_, session)| session.state == SessionState::Committing)
+                                             .map(|(peerid, _)| *peerid)
+                                             .collect();
+
+            for peerid in late {
+                warn!("Turning away peerid={}, who did not commit before the ceremony filled up", peerid.to_hex());
+                schedule.sessions.remove(&peerid);
+                peers.remove(&peerid);
             }
+        }
+
+        // The remote end should never hang up, so this should always be `players`.
+        assert_eq!(peers.len(), self.config.players);
 
-            info!("Receiving stage1 transformation from peerid={}", peerid.to_hex());
-            let new_stage1 = self.read::<Stage1Contents>(peerid);
+        // Hash of all the commitments.
+        let hash_of_commitments = Digest512::from(&commitments).unwrap();
 
-            let ihash = self.read::<Digest256>(peerid);
+        info!("All players are ready");
 
-            if !new_stage1.is_well_formed(&stage1) {
-                error!("Peer did not perform valid stage1 transformation (peerid={})", peerid.to_hex());
-                panic!("cannot recover.");
-            } else {
-                info!("Writing `PublicKey` to transcript");
-                encode_into(&pubkey, &mut transcript, Infinite).unwrap();
-                info!("Writing `PublicKeyNizks` to transcript");
-                encode_into(&nizks, &mut transcript, Infinite).unwrap();
-                info!("Writing new stage1 to transcript");
-                encode_into(&new_stage1, &mut transcript, Infinite).unwrap();
+        // Hash of the last accepted message
+        let mut last_message_hash = match resume.last_message_hash {
+            Some(h) => h,
+            None => Digest256::from(&commitments).unwrap()
+        };
+
+        let mut pubkeys = resume.pubkeys;
+        pubkeys.resize(self.config.players, None);
+
+        // Each contribution is sent on to the next player while it is
+        // still being verified, and is resolved once the next player's
+        // contribution comes back. Until then, `current` is what the
+        // next player receives and `accepted` is the last verified
+        // contribution.
+        let mut accepted = Arc::new(match (resume.stage1, accumulator) {
+            (Some(stage1), _) => stage1,
+            (None, Some((_, initial))) => {
+                info!("Initializing stage1 with the powers of the accumulator");
+                transcript.write(RecordType::Stage1, &initial).unwrap();
+                initial
+            },
+            (None, None) => {
+                info!("Initializing stage1 with constraint system");
+                Stage1Contents::new(&cs)
+            }
+        });
+        let mut current = (accepted.clone(), last_message_hash);
+        let mut pending: Option<Pending<Stage1Contents>> = None;
+        if let Some(saved) = resume.pending1 {
+            let pubkey = saved.key.as_ref().map(|&(ref pubkey, _)| pubkey.clone())
+                                  .expect("contributions to stage 1 come with the player's key");
+            pubkeys[saved.player] = Some(pubkey.clone());
+            let restored = self.restore_pending(1, &peers[saved.player], saved, &accepted, pubkey,
+                                                Stage1Contents::verify_transform_observed);
+            current = (restored.contents.clone(), restored.last_message_hash);
+            pending = Some(restored);
+        }
+        let first = pending.as_ref().map(|p| p.player + 1).unwrap_or(resume.done[0]);
+        for (i, (comm, peerid)) in commitments.iter().zip(peers.iter()).enumerate().skip(first) {
+            loop {
+                self.begin_turn(1, i);
+                info!("Sending stage1 to peerid={}", peerid.to_hex());
+
+                let (stage1, prev_msg_hash) = current.clone();
+                let sent = digest256_from_parts!(hash_of_commitments, stage1, prev_msg_hash);
+
+                let received = (|| -> Result<_, (String, Evidence)> {
+                    try!(self.write(peerid, MessageType::CommitmentsHash, &hash_of_commitments).map_err(|e| (e, vec![])));
+                    try!(self.write(peerid, MessageType::Stage1, &*stage1).map_err(|e| (e, vec![])));
+                    try!(self.write(peerid, MessageType::PrevMessageHash, &prev_msg_hash).map_err(|e| (e, vec![])));
+
+                    info!("Receiving public key from peerid={}", peerid.to_hex());
+                    let pubkey = try!(self.read::<PublicKey>(peerid, MessageType::PublicKey)
+                                      .map_err(|e| e.into_evidence(RecordType::PublicKey)));
+
+                    info!("Receiving nizks from peerid={}", peerid.to_hex());
+                    let nizks = try!(self.read::<PublicKeyNizks>(peerid, MessageType::PublicKeyNizks)
+                                     .map_err(|e| e.into_evidence(RecordType::PublicKeyNizks)));
+
+                    info!("Receiving stage1 transformation from peerid={}", peerid.to_hex());
+                    let new_stage1 = try!(self.read::<Stage1Contents>(peerid, MessageType::Stage1)
+                                          .map_err(|e| e.into_evidence(RecordType::Stage1)));
+
+                    let ihash = try!(self.read::<Digest256>(peerid, MessageType::IHash)
+                                     .map_err(|e| e.into_evidence(RecordType::IHash)));
+
+                    Ok((pubkey, nizks, new_stage1, ihash))
+                })();
+                self.end_turn();
+
+                if let Some(pending) = pending.take() {
+                    // A player who built upon a contribution that turns
+                    // out to be invalid takes their turn again.
+                    let retry = if received.is_ok() { Some(i) } else { None };
+
+                    if !self.resolve(&mut transcript, &mut dropped, pending, 1, RecordType::Stage1,
+                                     retry, &mut accepted, &mut last_message_hash)
+                    {
+                        current = (accepted.clone(), last_message_hash);
+
+                        if retry.is_some() {
+                            self.conclude_turn(peerid, 1, true);
+                            continue;
+                        }
+                    }
+                }
 
-                encode_into(&ihash, &mut transcript, Infinite).unwrap();
+                let (pubkey, nizks, new_stage1, ihash) = match received {
+                    Ok(received) => received,
+                    Err((reason, evidence)) => {
+                        self.reject(&mut transcript, &mut dropped, peerid, Rejection {
+                            player: i,
+                            stage: 1,
+                            reason: reason,
+                            evidence: evidence,
+                            retried: None
+                        });
+                        current = (accepted.clone(), last_message_hash);
+                        break;
+                    }
+                };
+
+                let reason = if pubkey.hash() != *comm {
+                    Some("did not properly commit to their public key")
+                } else if !nizks.is_valid(&pubkey, &hash_of_commitments) {
+                    Some("did not provide proof that they possess the secrets")
+                } else if !new_stage1.is_well_formed(&stage1) {
+                    Some("did not perform valid stage1 transformation")
+                } else {
+                    None
+                };
+
+                if let Some(reason) = reason {
+                    let evidence = vec![
+                        as_evidence(RecordType::PublicKey, &pubkey),
+                        as_evidence(RecordType::PublicKeyNizks, &nizks),
+                        as_evidence(RecordType::Stage1, &new_stage1),
+                        as_evidence(RecordType::IHash, &ihash)
+                    ];
+                    self.reject(&mut transcript, &mut dropped, peerid, Rejection {
+                        player: i,
+                        stage: 1,
+                        reason: reason.into(),
+                        evidence: evidence,
+                        retried: None
+                    });
+                    current = (accepted.clone(), last_message_hash);
+                    break;
+                }
+
+                info!("Verifying transformation of stage1 from peerid={} in the background", peerid.to_hex());
 
-                last_message_hash = digest256_from_parts!(
+                let msg_hash = digest256_from_parts!(
                     pubkey, nizks, new_stage1, ihash
                 );
+                let new_stage1 = Arc::new(new_stage1);
+                let verified = {
+                    let (new_stage1, pubkey) = (new_stage1.clone(), pubkey.clone());
+                    verify_in_background(1, *peerid, move |observer| {
+                        new_stage1.verify_transform_observed(&stage1, &pubkey, observer)
+                    })
+                };
+
+                pubkeys[i] = Some(pubkey.clone());
+                self.record_discs(1, i, sent, msg_hash);
+                current = (new_stage1.clone(), msg_hash);
+                let contribution = Pending {
+                    player: i,
+                    peerid: *peerid,
+                    key: Some((pubkey, nizks)),
+                    contents: new_stage1,
+                    ihash: ihash,
+                    last_message_hash: msg_hash,
+                    verified: verified
+                };
+
+                // The player moves on once told that their turn is over,
+                // so their contribution must survive a restart by then.
+                self.save_pending(1, &contribution).unwrap();
+                self.conclude_turn(peerid, 1, false);
+                pending = Some(contribution);
+
+                break;
+            }
+        }
+
+        if let Some(pending) = pending.take() {
+            self.resolve(&mut transcript, &mut dropped, pending, 1, RecordType::Stage1,
+                         None, &mut accepted, &mut last_message_hash);
+        }
 
-                stage1 = new_stage1;
+        if dropped.len() == self.config.players {
+            error!("Every player has been rejected, the ceremony cannot be completed.");
+            return;
+        }
+
+        let mut accepted = Arc::new(match resume.stage2 {
+            Some(stage2) => stage2,
+            None => {
+                info!("Initializing stage2 with constraint system and stage1");
+                Stage2Contents::new(&cs, &accepted)
             }
+        });
+        let mut current = (accepted.clone(), last_message_hash);
+        let mut pending: Option<Pending<Stage2Contents>> = None;
+        if let Some(saved) = resume.pending2 {
+            let pubkey = pubkeys[saved.player].clone().unwrap();
+            let restored = self.restore_pending(2, &peers[saved.player], saved, &accepted, pubkey,
+                                                Stage2Contents::verify_transform_observed);
+            current = (restored.contents.clone(), restored.last_message_hash);
+            pending = Some(restored);
         }
+        let first = pending.as_ref().map(|p| p.player + 1).unwrap_or(resume.done[1]);
+        for (i, peerid) in peers.iter().enumerate().skip(first) {
+            if dropped.contains(&i) {
+                continue;
+            }
 
-        info!("Initializing stage2 with constraint system and stage1");
+            loop {
+                self.begin_turn(2, i);
+                info!("Sending stage2 to peerid={}", peerid.to_hex());
 
-        let mut stage2 = Stage2Contents::new(&cs, &stage1);
-        for peerid in peers.iter() {
-            info!("Sending stage2 to peerid={}", peerid.to_hex());
+                let (stage2, prev_msg_hash) = current.clone();
+                let sent = digest256_from_parts!(stage2, prev_msg_hash);
 
-            self.write(peerid, &stage2);
-            self.write(peerid, &last_message_hash);
+                let received = (|| -> Result<_, (String, Evidence)> {
+                    try!(self.write(peerid, MessageType::Stage2, &*stage2).map_err(|e| (e, vec![])));
+                    try!(self.write(peerid, MessageType::PrevMessageHash, &prev_msg_hash).map_err(|e| (e, vec![])));
 
-            info!("Receiving stage2 transformation from peerid={}", peerid.to_hex());
+                    info!("Receiving stage2 transformation from peerid={}", peerid.to_hex());
+                    let new_stage2 = try!(self.read::<Stage2Contents>(peerid, MessageType::Stage2)
+                                          .map_err(|e| e.into_evidence(RecordType::Stage2)));
 
-            let new_stage2 = self.read::<Stage2Contents>(peerid);
-            let ihash = self.read::<Digest256>(peerid);
+                    let ihash = try!(self.read::<Digest256>(peerid, MessageType::IHash)
+                                     .map_err(|e| e.into_evidence(RecordType::IHash)));
 
-            if !new_stage2.is_well_formed(&stage2) {
-                error!("Peer did not perform valid stage2 transformation (peerid={})", peerid.to_hex());
-                panic!("cannot recover.");
-            } else {
-                info!("Writing new stage2 to transcript");
-                encode_into(&new_stage2, &mut transcript, Infinite).unwrap();
-                encode_into(&ihash, &mut transcript, Infinite).unwrap();
+                    Ok((new_stage2, ihash))
+                })();
+                self.end_turn();
+
+                if let Some(pending) = pending.take() {
+                    // A player who built upon a contribution that turns
+                    // out to be invalid takes their turn again.
+                    let retry = if received.is_ok() { Some(i) } else { None };
+
+                    if !self.resolve(&mut transcript, &mut dropped, pending, 2, RecordType::Stage2,
+                                     retry, &mut accepted, &mut last_message_hash)
+                    {
+                        current = (accepted.clone(), last_message_hash);
+
+                        if retry.is_some() {
+                            self.conclude_turn(peerid, 2, true);
+                            continue;
+                        }
+                    }
+                }
+
+                let (new_stage2, ihash) = match received {
+                    Ok(received) => received,
+                    Err((reason, evidence)) => {
+                        self.reject(&mut transcript, &mut dropped, peerid, Rejection {
+                            player: i,
+                            stage: 2,
+                            reason: reason,
+                            evidence: evidence,
+                            retried: None
+                        });
+                        current = (accepted.clone(), last_message_hash);
+                        break;
+                    }
+                };
+
+                if !new_stage2.is_well_formed(&stage2) {
+                    let evidence = vec![
+                        as_evidence(RecordType::Stage2, &new_stage2),
+                        as_evidence(RecordType::IHash, &ihash)
+                    ];
+                    self.reject(&mut transcript, &mut dropped, peerid, Rejection {
+                        player: i,
+                        stage: 2,
+                        reason: "did not perform valid stage2 transformation".into(),
+                        evidence: evidence,
+                        retried: None
+                    });
+                    current = (accepted.clone(), last_message_hash);
+                    break;
+                }
+
+                info!("Verifying transformation of stage2 from peerid={} in the background", peerid.to_hex());
 
-                last_message_hash = digest256_from_parts!(
+                let msg_hash = digest256_from_parts!(
                     new_stage2, ihash
                 );
+                let new_stage2 = Arc::new(new_stage2);
+                let verified = {
+                    let (new_stage2, pubkey) = (new_stage2.clone(), pubkeys[i].clone().unwrap());
+                    verify_in_background(2, *peerid, move |observer| {
+                        new_stage2.verify_transform_observed(&stage2, &pubkey, observer)
+                    })
+                };
+
+                self.record_discs(2, i, sent, msg_hash);
+                current = (new_stage2.clone(), msg_hash);
+                let contribution = Pending {
+                    player: i,
+                    peerid: *peerid,
+                    key: None,
+                    contents: new_stage2,
+                    ihash: ihash,
+                    last_message_hash: msg_hash,
+                    verified: verified
+                };
+
+                // The player moves on once told that their turn is over,
+                // so their contribution must survive a restart by then.
+                self.save_pending(2, &contribution).unwrap();
+                self.conclude_turn(peerid, 2, false);
+                pending = Some(contribution);
+
+                break;
+            }
+        }
 
-                stage2 = new_stage2;
+        if let Some(pending) = pending.take() {
+            self.resolve(&mut transcript, &mut dropped, pending, 2, RecordType::Stage2,
+                         None, &mut accepted, &mut last_message_hash);
+        }
+
+        if dropped.len() == self.config.players {
+            error!("Every player has been rejected, the ceremony cannot be completed.");
+            return;
+        }
+
+        let mut accepted = Arc::new(match resume.stage3 {
+            Some(stage3) => stage3,
+            None => {
+                info!("Initializing stage3 with constraint system and stage2");
+                Stage3Contents::new(&cs, &accepted)
             }
+        });
+        let mut current = (accepted.clone(), last_message_hash);
+        let mut pending: Option<Pending<Stage3Contents>> = None;
+        if let Some(saved) = resume.pending3 {
+            let pubkey = pubkeys[saved.player].clone().unwrap();
+            let restored = self.restore_pending(3, &peers[saved.player], saved, &accepted, pubkey,
+                                                Stage3Contents::verify_transform_observed);
+            current = (restored.contents.clone(), restored.last_message_hash);
+            pending = Some(restored);
         }
+        let first = pending.as_ref().map(|p| p.player + 1).unwrap_or(resume.done[2]);
+        for (i, peerid) in peers.iter().enumerate().skip(first) {
+            if dropped.contains(&i) {
+                continue;
+            }
 
-        info!("Initializing stage3 with constraint system and stage2");
+            loop {
+                self.begin_turn(3, i);
+                info!("Sending stage3 to peerid={}", peerid.to_hex());
 
-        let mut stage3 = Stage3Contents::new(&cs, &stage2);
-        for peerid in peers.iter() {
-            info!("Sending stage3 to peerid={}", peerid.to_hex());
+                let (stage3, prev_msg_hash) = current.clone();
+                let sent = digest256_from_parts!(stage3, prev_msg_hash);
 
-            self.write(peerid, &stage3);
-            self.write(peerid, &last_message_hash);
+                let received = (|| -> Result<_, (String, Evidence)> {
+                    try!(self.write(peerid, MessageType::Stage3, &*stage3).map_err(|e| (e, vec![])));
+                    try!(self.write(peerid, MessageType::PrevMessageHash, &prev_msg_hash).map_err(|e| (e, vec![])));
 
-            info!("Receiving stage3 transformation from peerid={}", peerid.to_hex());
+                    info!("Receiving stage3 transformation from peerid={}", peerid.to_hex());
+                    let new_stage3 = try!(self.read::<Stage3Contents>(peerid, MessageType::Stage3)
+                                          .map_err(|e| e.into_evidence(RecordType::Stage3)));
 
-            let new_stage3 = self.read::<Stage3Contents>(peerid);
-            let ihash = self.read::<Digest256>(peerid);
+                    let ihash = try!(self.read::<Digest256>(peerid, MessageType::IHash)
+                                     .map_err(|e| e.into_evidence(RecordType::IHash)));
 
-            info!("Verifying transformation of stage3 from peerid={}", peerid.to_hex());
+                    Ok((new_stage3, ihash))
+                })();
+                self.end_turn();
 
-            if !new_stage3.is_well_formed(&stage3) {
-                error!("Peer did not perform valid stage3 transformation (peerid={})", peerid.to_hex());
-                panic!("cannot recover.");
-            } else {
-                info!("Writing new stage3 to transcript");
-                encode_into(&new_stage3, &mut transcript, Infinite).unwrap();
-                encode_into(&ihash, &mut transcript, Infinite).unwrap();
+                if let Some(pending) = pending.take() {
+                    // A player who built upon a contribution that turns
+                    // out to be invalid takes their turn again.
+                    let retry = if received.is_ok() { Some(i) } else { None };
+
+                    if !self.resolve(&mut transcript, &mut dropped, pending, 3, RecordType::Stage3,
+                                     retry, &mut accepted, &mut last_message_hash)
+                    {
+                        current = (accepted.clone(), last_message_hash);
+
+                        if retry.is_some() {
+                            self.conclude_turn(peerid, 3, true);
+                            continue;
+                        }
+                    }
+                }
+
+                let (new_stage3, ihash) = match received {
+                    Ok(received) => received,
+                    Err((reason, evidence)) => {
+                        self.reject(&mut transcript, &mut dropped, peerid, Rejection {
+                            player: i,
+                            stage: 3,
+                            reason: reason,
+                            evidence: evidence,
+                            retried: None
+                        });
+                        current = (accepted.clone(), last_message_hash);
+                        break;
+                    }
+                };
+
+                if !new_stage3.is_well_formed(&stage3) {
+                    let evidence = vec![
+                        as_evidence(RecordType::Stage3, &new_stage3),
+                        as_evidence(RecordType::IHash, &ihash)
+                    ];
+                    self.reject(&mut transcript, &mut dropped, peerid, Rejection {
+                        player: i,
+                        stage: 3,
+                        reason: "did not perform valid stage3 transformation".into(),
+                        evidence: evidence,
+                        retried: None
+                    });
+                    current = (accepted.clone(), last_message_hash);
+                    break;
+                }
 
-                last_message_hash = digest256_from_parts!(
+                info!("Verifying transformation of stage3 from peerid={} in the background", peerid.to_hex());
+
+                let msg_hash = digest256_from_parts!(
                     new_stage3, ihash
                 );
-
-                stage3 = new_stage3;
+                let new_stage3 = Arc::new(new_stage3);
+                let verified = {
+                    let (new_stage3, pubkey) = (new_stage3.clone(), pubkeys[i].clone().unwrap());
+                    verify_in_background(3, *peerid, move |observer| {
+                        new_stage3.verify_transform_observed(&stage3, &pubkey, observer)
+                    })
+                };
+
+                self.record_discs(3, i, sent, msg_hash);
+                current = (new_stage3.clone(), msg_hash);
+                let contribution = Pending {
+                    player: i,
+                    peerid: *peerid,
+                    key: None,
+                    contents: new_stage3,
+                    ihash: ihash,
+                    last_message_hash: msg_hash,
+                    verified: verified
+                };
+
+                // The player moves on once told that their turn is over,
+                // so their contribution must survive a restart by then.
+                self.save_pending(3, &contribution).unwrap();
+                self.conclude_turn(peerid, 3, false);
+                pending = Some(contribution);
+
+                break;
             }
         }
 
+        if let Some(pending) = pending.take() {
+            self.resolve(&mut transcript, &mut dropped, pending, 3, RecordType::Stage3,
+                         None, &mut accepted, &mut last_message_hash);
+        }
+
+        if dropped.len() == self.config.players {
+            error!("Every player has been rejected, the ceremony cannot be completed.");
+            return;
+        }
+
         info!("MPC complete, flushing transcript to disk.");
 
         transcript.flush().unwrap();
 
         info!("Transcript flushed to disk.");
+        self.schedule.lock().unwrap().complete = true;
+    }
+
+    /// Admits each offline player in the order of their directories, which
+    /// is the order of their turns, so each player's commitment is awaited
+    /// before the next player is admitted.
+    fn admit_offline(&self) {
+        for player in 0..self.config.players {
+            let peerid = Offline::peerid(player);
+
+            if self.recovered.lock().unwrap().remove(&peerid).is_some() {
+                continue;
+            }
+
+            // The session exists before `run` can receive the commitment.
+            self.schedule.lock().unwrap().set_state(&peerid, SessionState::Committing);
+
+            if self.notifier.send(Event::Connected(peerid)).is_err() {
+                self.schedule.lock().unwrap().sessions.remove(&peerid);
+                return;
+            }
+
+            loop {
+                if self.schedule.lock().unwrap().sessions.get(&peerid).map(|s| s.state) != Some(SessionState::Committing) {
+                    break;
+                }
+
+                thread::sleep(Duration::from_secs(1));
+            }
+        }
     }
 
-    fn accept(&self, peerid: [u8; 8], mut stream: TcpStream, remote_msgid: u8) {
+    /// Authenticates a new connection from `addr` and hands it to
+    /// `accept`, or drops it if the remote end does not handshake.
+    fn admit(&self, mut stream: Box<Stream>, addr: &str, key: &StaticKey) {
+        let _ = stream.set_timeout(Some(Duration::from_secs(5)));
+
+        let mut magic = [0; 8];
+
+        if let Err(e) = stream.read_exact(&mut magic) {
+            warn!("Remote host {} did not handshake; {}", addr, e);
+            return;
+        }
+
+        if magic != NETWORK_MAGIC {
+            warn!("Remote host {} did not supply correct network magic.", addr);
+            return;
+        }
+
+        if stream.write_all(&COORDINATOR_MAGIC).and_then(|_| stream.flush()).is_err() {
+            warn!("Remote host {} did not handshake", addr);
+            return;
+        }
+
+        let (mut stream, peerid) = match respond(stream, key) {
+            Ok(secure) => secure,
+            Err(e) => {
+                warn!("Remote host {} could not be authenticated; {}", addr, e);
+                return;
+            }
+        };
+
+        let remote_msgid = match decode_from(&mut stream, Infinite) {
+            Ok(msgid) => msgid,
+            Err(e) => {
+                warn!("Remote host {} did not handshake; {}", addr, e);
+                return;
+            }
+        };
+
+        if stream.get_ref().set_timeout(Some(Duration::from_secs(self.config.network_timeout))).is_ok() {
+            self.accept(peerid, stream, remote_msgid);
+        } else {
+            warn!("Failed to set read/write timeouts for remote host {}", addr);
+        }
+    }
+
+    fn accept(&self, peerid: PeerKey, mut stream: SecureStream<Box<Stream>>, remote_msgid: u64) {
         use std::collections::hash_map::Entry::{Occupied, Vacant};
 
-        fn send_msgid(stream: &mut TcpStream, msgid: u8) {
-            let _ = stream.write_all(&[msgid]);
+        fn send_msgid(stream: &mut SecureStream<Box<Stream>>, msgid: u64) {
+            let _ = encode_into(&msgid, stream, Infinite);
             let _ = stream.flush();
         }
 
@@ -336,14 +2076,29 @@ impl ConnectionHandler {
                     };
                     send_msgid(&mut stream, our_msgid);
                     already.insert(Some((stream, our_msgid, remote_msgid)));
+                    self.schedule.lock().unwrap().set_online(&peerid, true);
                 }
             },
             Vacant(vacant) => {
-                match self.notifier.send(peerid) {
+                let recovered = self.recovered.lock().unwrap().remove(&peerid);
+
+                if let Some(our_msgid) = recovered {
+                    info!("Player reconnected after a restart (peerid={})", peerid.to_hex());
+                    send_msgid(&mut stream, our_msgid);
+                    vacant.insert(Some((stream, our_msgid, remote_msgid)));
+                    self.schedule.lock().unwrap().set_online(&peerid, true);
+                    return;
+                }
+
+                match self.notifier.send(Event::Connected(peerid)) {
                     Ok(_) => {
                         info!("Accepted new connection (peerid={})", peerid.to_hex());
                         send_msgid(&mut stream, 0);
                         vacant.insert(Some((stream, 0, remote_msgid)));
+
+                        let mut schedule = self.schedule.lock().unwrap();
+                        schedule.set_state(&peerid, SessionState::Committing);
+                        schedule.set_online(&peerid, true);
                     },
                     Err(_) => {
                         warn!("Rejecting connection from peerid={}, no longer accepting new players.", peerid.to_hex());
@@ -355,6 +2110,26 @@ impl ConnectionHandler {
 }
 
 fn main() {
+    let options = Config::from_args(
+        std::env::args().skip(1),
+        &["dummy-cs", "help"],
+        &["players", "listen", "transcript", "key", "accumulator", "threads", "timeout", "player-timeout", "max-message-size", "status", "offline"]
+    );
+    if options.as_ref().map(|o| o.flag("help")).unwrap_or(false) {
+        println!("{}", USAGE);
+        return;
+    }
+
+    let config = match options.and_then(|o| CoordinatorConfig::from(&o)) {
+        Ok(config) => config,
+        Err(e) => {
+            println!("{}\n\n{}", e, USAGE);
+            std::process::exit(1);
+        }
+    };
+
+    set_threads(config.threads);
+
     {
         // Initialize the logger.
         let start_time = time::now();
@@ -386,52 +2161,62 @@ fn main() {
         builder.init().unwrap();
     }
 
-    info!("Opening TCP listener on {}", LISTEN_ADDR);
-    let listener = TcpListener::bind(LISTEN_ADDR).unwrap();
-
-    let handler = ConnectionHandler::new();
+    let key = match StaticKey::load_or_generate(&config.key_path) {
+        Ok(key) => key,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    info!("Coordinator public key: {}", key.public().to_hex());
+
+    let listener = match config.offline_dir {
+        Some(ref dir) => {
+            info!("Exchanging files with players in {}", dir);
+            None
+        },
+        None => {
+            info!("Listening on {}", config.listen_addr);
+            match transport::listen(&config.listen_addr) {
+                Ok(listener) => Some(listener),
+                Err(e) => {
+                    error!("Could not listen on {}: {}", config.listen_addr, e);
+                    std::process::exit(1);
+                }
+            }
+        }
+    };
+
+    let (status_addr, players) = (config.status_addr.clone(), config.players);
+    let (handler, ceremony) = match ConnectionHandler::new(config) {
+        Ok(handler) => handler,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
 
-    for stream in listener.incoming() {
-        match stream {
-            Ok(mut stream) => {
-                let _ = stream.set_read_timeout(Some(Duration::from_secs(5)));
-                let _ = stream.set_write_timeout(Some(Duration::from_secs(5)));
+    if let Some(addr) = status_addr {
+        info!("Serving the status page on {}", addr);
+        if let Err(e) = serve_status(&addr, handler.schedule.clone(), players) {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    }
 
-                match stream.peer_addr() {
-                    Ok(addr) => {
-                        let mut magic = [0; 8];
-                        let mut peerid = [0; 8];
-                        let mut msgi_buf: [u8; 1] = [0];
+    let listener = match listener {
+        Some(listener) => listener,
+        None => {
+            handler.admit_offline();
+            let _ = ceremony.join();
+            return;
+        }
+    };
 
-                        match stream.read_exact(&mut magic)
-                                    .and_then(|_| stream.read_exact(&mut peerid))
-                                    .and_then(|_| stream.read_exact(&mut msgi_buf))
-                        {
-                            Err(e) => {
-                                warn!("Remote host {} did not handshake; {}", addr, e);
-                            },
-                            Ok(_) => {
-                                if magic != NETWORK_MAGIC {
-                                    warn!("Remote host {} did not supply correct network magic.", addr);
-                                } else {
-                                    if
-                                        stream.write_all(&COORDINATOR_MAGIC).is_ok() &&
-                                        stream.flush().is_ok() &&
-                                        stream.set_read_timeout(Some(Duration::from_secs(NETWORK_TIMEOUT))).is_ok() &&
-                                        stream.set_write_timeout(Some(Duration::from_secs(NETWORK_TIMEOUT))).is_ok()
-                                    {
-                                        handler.accept(peerid, stream, msgi_buf[0]);
-                                    } else {
-                                        warn!("Failed to set read/write timeouts for remote host {}", addr);
-                                    }
-                                }
-                            }
-                        }
-                    },
-                    Err(e) => {
-                        warn!("Failed connection attempt from unknown host: {}", e);
-                    }
-                }
+    loop {
+        match listener.accept() {
+            Ok((stream, addr)) => {
+                handler.admit(stream, &addr, &key);
             },
             Err(e) => {
                 warn!("Failed to establish connection with remote client, {}", e);
@@ -439,3 +2224,474 @@ fn main() {
         }
     }
 }
+
+/// Plays one player's part of the ceremony, as the compute and network
+/// machines would between them.
+#[cfg(test)]
+fn play(client: mpc::client::Client) {
+    play_pausing(client, &|| {});
+}
+
+/// Plays like `play`, but calls `pause` whenever stage 1 contents have
+/// been received, before working on them.
+#[cfg(test)]
+fn play_pausing(mut client: mpc::client::Client, pause: &Fn()) {
+    let rng = &mut rand::thread_rng();
+    let privkey = PrivateKey::new(rng);
+    let pubkey = privkey.pubkey(rng);
+
+    client.write(MessageType::Commitment, &pubkey.hash());
+
+    loop {
+        let hash_of_commitments: Digest512 = client.read(MessageType::CommitmentsHash);
+        let mut stage1: Stage1Contents = client.read(MessageType::Stage1);
+        let prev_msg_hash: Digest256 = client.read(MessageType::PrevMessageHash);
+        pause();
+        let ihash = digest256_from_parts!(hash_of_commitments, stage1, prev_msg_hash);
+        let nizks = pubkey.nizks(rng, &privkey, &hash_of_commitments);
+        stage1.transform(&privkey);
+        client.write(MessageType::PublicKey, &pubkey);
+        client.write(MessageType::PublicKeyNizks, &nizks);
+        client.write(MessageType::Stage1, &stage1);
+        client.write(MessageType::IHash, &ihash);
+
+        if !client.read::<bool>(MessageType::Retry) {
+            break;
+        }
+    }
+
+    loop {
+        let mut stage2: Stage2Contents = client.read(MessageType::Stage2);
+        let prev_msg_hash: Digest256 = client.read(MessageType::PrevMessageHash);
+        let ihash = digest256_from_parts!(stage2, prev_msg_hash);
+        stage2.transform(&privkey);
+        client.write(MessageType::Stage2, &stage2);
+        client.write(MessageType::IHash, &ihash);
+
+        if !client.read::<bool>(MessageType::Retry) {
+            break;
+        }
+    }
+
+    loop {
+        let mut stage3: Stage3Contents = client.read(MessageType::Stage3);
+        let prev_msg_hash: Digest256 = client.read(MessageType::PrevMessageHash);
+        let ihash = digest256_from_parts!(stage3, prev_msg_hash);
+        stage3.transform(&privkey);
+        client.write(MessageType::Stage3, &stage3);
+        client.write(MessageType::IHash, &ihash);
+
+        if !client.read::<bool>(MessageType::Retry) {
+            break;
+        }
+    }
+}
+
+/// Commits like an honest player, but transforms stage 1 with a key
+/// other than the one they committed to, which is only noticed once
+/// the next player has built upon it.
+#[cfg(test)]
+fn play_invalid(mut client: mpc::client::Client) {
+    let rng = &mut rand::thread_rng();
+    let privkey = PrivateKey::new(rng);
+    let pubkey = privkey.pubkey(rng);
+
+    client.write(MessageType::Commitment, &pubkey.hash());
+
+    let hash_of_commitments: Digest512 = client.read(MessageType::CommitmentsHash);
+    let mut stage1: Stage1Contents = client.read(MessageType::Stage1);
+    let prev_msg_hash: Digest256 = client.read(MessageType::PrevMessageHash);
+    let ihash = digest256_from_parts!(hash_of_commitments, stage1, prev_msg_hash);
+    stage1.transform(&PrivateKey::new(rng));
+    client.write(MessageType::PublicKey, &pubkey);
+    client.write(MessageType::PublicKeyNizks, &pubkey.nizks(rng, &privkey, &hash_of_commitments));
+    client.write(MessageType::Stage1, &stage1);
+    client.write(MessageType::IHash, &ihash);
+
+    // The contribution is only found to be invalid after the turn is
+    // over, and the player is then disconnected.
+    assert!(!client.read::<bool>(MessageType::Retry));
+}
+
+/// Commits like an honest player, but sends stage 1 contents that do
+/// not decode.
+#[cfg(test)]
+fn play_malformed(mut client: mpc::client::Client) {
+    let rng = &mut rand::thread_rng();
+    let privkey = PrivateKey::new(rng);
+    let pubkey = privkey.pubkey(rng);
+
+    client.write(MessageType::Commitment, &pubkey.hash());
+
+    let hash_of_commitments: Digest512 = client.read(MessageType::CommitmentsHash);
+    let _: Stage1Contents = client.read(MessageType::Stage1);
+    let _: Digest256 = client.read(MessageType::PrevMessageHash);
+    client.write(MessageType::PublicKey, &pubkey);
+    client.write(MessageType::PublicKeyNizks, &pubkey.nizks(rng, &privkey, &hash_of_commitments));
+    client.write(MessageType::Stage1, &vec![0xffu8; 256]);
+}
+
+/// Commits, then goes away for good before their first turn.
+#[cfg(test)]
+fn play_vanished(mut client: mpc::client::Client) {
+    let rng = &mut rand::thread_rng();
+    let pubkey = PrivateKey::new(rng).pubkey(rng);
+
+    client.write(MessageType::Commitment, &pubkey.hash());
+}
+
+#[cfg(test)]
+fn test_dir() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("mpc-ceremony-{}", rand::random::<u64>()));
+    fs::create_dir(&dir).unwrap();
+
+    dir
+}
+
+/// The configuration of a ceremony among `players` on the dummy
+/// constraint system, keeping its transcript in `dir`.
+#[cfg(test)]
+fn test_config(dir: &PathBuf, players: usize) -> CoordinatorConfig {
+    CoordinatorConfig {
+        listen_addr: "memory".into(),
+        players: players,
+        transcript_path: dir.join("transcript").to_str().unwrap().to_string(),
+        key_path: String::new(),
+        accumulator_path: None,
+        threads: 4,
+        network_timeout: 60,
+        player_timeout: 30,
+        max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+        status_addr: None,
+        offline_dir: None,
+        use_dummy_cs: true
+    }
+}
+
+/// Starts a coordinator over the memory transport, returning how to
+/// connect to it and the thread of its ceremony.
+#[cfg(test)]
+fn serve(config: CoordinatorConfig, key: Arc<StaticKey>) -> (transport::MemoryConnector, JoinHandle<()>) {
+    use mpc::transport::Listener;
+
+    let (listener, connector) = transport::memory();
+    let (handler, ceremony) = ConnectionHandler::new(config).unwrap();
+
+    thread::spawn(move || {
+        while let Ok((stream, addr)) = listener.accept() {
+            handler.admit(stream, &addr, &key);
+        }
+    });
+
+    (connector, ceremony)
+}
+
+/// Runs a ceremony over the memory transport, in which each player
+/// takes their part with the corresponding function of `players`, and
+/// returns the transcript.
+#[cfg(test)]
+fn ceremony_with(players: &[fn(mpc::client::Client)]) -> Vec<u8> {
+    use mpc::client::Client;
+
+    let dir = test_dir();
+    let transcript_path = dir.join("transcript");
+
+    let key = Arc::new(StaticKey::generate().unwrap());
+    let coordinator_key = *key.public();
+    let (connector, ceremony) = serve(test_config(&dir, players.len()), key);
+
+    let players: Vec<_> = players.iter().map(|&play| {
+        let connector = Box::new(connector.clone());
+        let key = StaticKey::generate().unwrap();
+
+        thread::spawn(move || {
+            play(Client::connect(connector, key, coordinator_key, 60, DEFAULT_MAX_MESSAGE_SIZE).unwrap());
+        })
+    }).collect();
+
+    ceremony.join().unwrap();
+    for player in players {
+        player.join().unwrap();
+    }
+
+    let mut transcript = vec![];
+    File::open(&transcript_path).unwrap().read_to_end(&mut transcript).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    transcript
+}
+
+#[test]
+fn ceremony_over_memory_transport() {
+    let transcript = ceremony_with(&[play, play, play]);
+
+    match replay(&mut io::Cursor::new(&transcript[..]), &CS::dummy()) {
+        Ok(Progress::Complete) => {},
+        Ok(_) => panic!("the ceremony was not completed"),
+        Err(e) => panic!("the transcript is invalid: {}", e)
+    }
+}
+
+#[test]
+fn malformed_contribution() {
+    use mpc::verify::verify;
+
+    // The malformed contribution is rejected, rather than asked for
+    // again forever, and the others carry on without it.
+    let transcript = ceremony_with(&[play as fn(_), play_malformed, play]);
+
+    let (report, kp) = verify(&mut io::Cursor::new(&transcript[..]), &CS::dummy(), None, None, &quiet, |_| {});
+    assert!(report.is_valid());
+    assert!(kp.is_some());
+
+    let rejected: Vec<_> = report.players.iter().filter_map(|p| p.rejected.as_ref()).collect();
+    assert_eq!(rejected.len(), 1);
+    assert_eq!(rejected[0].stage, 1);
+    assert!(rejected[0].reason.contains("could not decode Stage1"));
+
+    // What the player sent is in the transcript, and does not decode
+    // for the verifier either.
+    assert_eq!(rejected[0].evidence.len(), 1);
+    assert!(report.is_fully_valid());
+}
+
+#[test]
+fn invalid_contribution() {
+    use mpc::verify::verify;
+
+    // The third player builds upon the invalid contribution of the
+    // second, and is sent the first player's contribution to build upon
+    // instead.
+    let transcript = ceremony_with(&[play as fn(_), play_invalid, play]);
+
+    let (report, kp) = verify(&mut io::Cursor::new(&transcript[..]), &CS::dummy(), None, None, &quiet, |_| {});
+    assert!(report.is_fully_valid());
+    assert!(kp.is_some());
+    assert_eq!(report.contributions(1), 2);
+    assert_eq!(report.players[2].stages.len(), 3);
+
+    let rejection = report.players[1].rejected.as_ref().unwrap();
+    assert_eq!(rejection.stage, 1);
+    assert_eq!(rejection.retried, Some(2));
+}
+
+#[test]
+fn vanished_player() {
+    use mpc::verify::verify;
+
+    // The player who never takes their turn is rejected once the
+    // player timeout runs out, rather than waited for forever.
+    let transcript = ceremony_with(&[play as fn(_), play_vanished, play]);
+
+    let (report, kp) = verify(&mut io::Cursor::new(&transcript[..]), &CS::dummy(), None, None, &quiet, |_| {});
+    assert!(report.is_fully_valid());
+    assert!(kp.is_some());
+
+    let rejected: Vec<_> = report.players.iter().filter_map(|p| p.rejected.as_ref()).collect();
+    assert_eq!(rejected.len(), 1);
+    assert_eq!(rejected[0].stage, 1);
+    assert!(rejected[0].reason.contains("did not respond"));
+    assert!(rejected[0].evidence.is_empty());
+}
+
+/// A connection to whichever coordinator the players of a test are
+/// pointed at. It fails once they are pointed at another one, as a
+/// connection to a coordinator that stopped would.
+#[cfg(test)]
+struct Redirected {
+    inner: Box<Stream>,
+    generation: usize,
+    current: Arc<std::sync::atomic::AtomicUsize>,
+    timeout: std::cell::Cell<Option<Duration>>
+}
+
+#[cfg(test)]
+impl Redirected {
+    fn check(&self) -> io::Result<()> {
+        if self.current.load(std::sync::atomic::Ordering::SeqCst) != self.generation {
+            return Err(io::Error::new(io::ErrorKind::ConnectionAborted, "the coordinator stopped"));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl Read for Redirected {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // The inner stream times out often, so that a read does not
+        // outlive the coordinator.
+        let started = Instant::now();
+
+        loop {
+            try!(self.check());
+
+            match self.inner.read(buf) {
+                Err(ref e) if e.kind() == io::ErrorKind::TimedOut &&
+                              self.timeout.get().map_or(true, |timeout| started.elapsed() < timeout) => {},
+                result => return result
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+impl Write for Redirected {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        try!(self.check());
+
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+impl Stream for Redirected {
+    fn set_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.timeout.set(timeout);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+struct Redirector {
+    target: Arc<Mutex<transport::MemoryConnector>>,
+    current: Arc<std::sync::atomic::AtomicUsize>
+}
+
+#[cfg(test)]
+impl transport::Connector for Redirector {
+    fn connect(&self) -> io::Result<Box<Stream>> {
+        let target = self.target.lock().unwrap();
+        let inner = try!(target.connect());
+        try!(inner.set_timeout(Some(Duration::from_millis(100))));
+
+        Ok(Box::new(Redirected {
+            inner: inner,
+            generation: self.current.load(std::sync::atomic::Ordering::SeqCst),
+            current: self.current.clone(),
+            timeout: std::cell::Cell::new(None)
+        }))
+    }
+}
+
+/// Stops the coordinator of a ceremony among two players while the
+/// first player's contribution to stage 1 is still being verified, and
+/// continues the ceremony with a new coordinator, which starts from
+/// `accumulator` if it is given.
+#[cfg(test)]
+fn restart_while_pending_from(accumulator: Option<&Accumulator>) {
+    use mpc::client::Client;
+    use mpc::verify::verify;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    let (before, after) = (test_dir(), test_dir());
+    let config = |dir: &PathBuf| {
+        let mut config = test_config(dir, 2);
+        if let Some(acc) = accumulator {
+            let path = dir.join("accumulator");
+            encode_into(acc, &mut File::create(&path).unwrap(), Infinite).unwrap();
+            config.accumulator_path = Some(path.to_str().unwrap().to_string());
+        }
+
+        config
+    };
+
+    let key = Arc::new(StaticKey::generate().unwrap());
+    let coordinator_key = *key.public();
+    let (connector, _) = serve(config(&before), key.clone());
+
+    let target = Arc::new(Mutex::new(connector));
+    let current = Arc::new(AtomicUsize::new(0));
+    let (paused_tx, paused_rx) = channel();
+    let (resume_tx, resume_rx) = channel::<()>();
+    let resume_rx = Arc::new(Mutex::new(resume_rx));
+    let started = Arc::new(AtomicBool::new(false));
+
+    // The second player to take their turn in stage 1 pauses once they
+    // have received the first player's contribution.
+    let players: Vec<_> = (0..2).map(|_| {
+        let connector = Box::new(Redirector { target: target.clone(), current: current.clone() });
+        let (paused_tx, resume_rx, started) = (paused_tx.clone(), resume_rx.clone(), started.clone());
+
+        thread::spawn(move || {
+            let client = Client::connect(connector, StaticKey::generate().unwrap(), coordinator_key, 60,
+                                         DEFAULT_MAX_MESSAGE_SIZE).unwrap();
+
+            play_pausing(client, &|| {
+                if started.swap(true, Ordering::SeqCst) {
+                    paused_tx.send(()).unwrap();
+                    resume_rx.lock().unwrap().recv().unwrap();
+                }
+            });
+        })
+    }).collect();
+
+    // The coordinator stops while the first player's contribution is
+    // still being verified: they have been told that their turn is over,
+    // but their contribution is not in the transcript.
+    paused_rx.recv().unwrap();
+    for name in &["transcript", "transcript.peers", "transcript.pending"] {
+        fs::copy(before.join(name), after.join(name)).unwrap();
+    }
+
+    let (connector, ceremony) = serve(config(&after), key);
+    {
+        let mut target = target.lock().unwrap();
+        *target = connector;
+        current.fetch_add(1, Ordering::SeqCst);
+    }
+    resume_tx.send(()).unwrap();
+
+    ceremony.join().unwrap();
+    for player in players {
+        player.join().unwrap();
+    }
+
+    let mut transcript = vec![];
+    File::open(after.join("transcript")).unwrap().read_to_end(&mut transcript).unwrap();
+    assert!(fs::metadata(after.join("transcript.pending")).is_err());
+    fs::remove_dir_all(&before).unwrap();
+    fs::remove_dir_all(&after).unwrap();
+
+    let powers = accumulator.map(|acc| acc.powers());
+    let (report, kp) = verify(&mut io::Cursor::new(&transcript[..]), &CS::dummy(), powers, None, &quiet, |_| {});
+    assert!(report.is_fully_valid());
+    assert!(kp.is_some());
+    assert!(report.players.iter().all(|p| p.rejected.is_none()));
+    assert_eq!(report.contributions(1), 2);
+    assert_eq!(report.contributions(3), 2);
+}
+
+#[test]
+fn restart_while_pending() {
+    restart_while_pending_from(None);
+}
+
+#[test]
+fn restart_from_accumulator() {
+    use mpc::protocol::powersoftau;
+
+    let cs = CS::dummy();
+    let mut k = 0;
+    while (1 << k) < cs.d {
+        k += 1;
+    }
+    let mut acc = Accumulator::new(k);
+    acc.transform(&powersoftau::PrivateKey::new(&mut rand::thread_rng()));
+
+    restart_while_pending_from(Some(&acc));
+
+    // An accumulator too small for the circuit is refused up front.
+    let dir = test_dir();
+    let path = dir.join("accumulator");
+    encode_into(&Accumulator::new(k - 1), &mut File::create(&path).unwrap(), Infinite).unwrap();
+    let mut config = test_config(&dir, 2);
+    config.accumulator_path = Some(path.to_str().unwrap().to_string());
+    assert!(ConnectionHandler::new(config).is_err());
+    fs::remove_dir_all(&dir).unwrap();
+}
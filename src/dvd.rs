@@ -2,154 +2,132 @@ use std::io::{Read, Write, self};
 use std::thread;
 use std::time::Duration;
 use std::fs::{self, File};
-use std::process::Command;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
 use protocol::*;
+use media::Media;
+use ui::Ui;
+pub use media::{TemporaryFile, MediaStatus};
 
 const REMOTEPATH_ALPINE_RELEASE: &'static str = ".alpine-release";
 const REMOTEPATH_TEST_BURN: &'static str = "mpc_testburn";
 
-/// Clears the entire terminal screen, moves cursor to top left.
-pub fn reset() {
-    print!("{}[2J", 27 as char);
-    print!("{}[1;1H", 27 as char);
-    println!("[MPC] Do not exit this process or shut the system off.");
-    println!("");
+/// How long a scripted run waits before answering a prompt, since the
+/// other machine may still be busy.
+const SCRIPT_DELAY: u64 = 500;
+
+/// Where the answers to prompts come from.
+pub enum Input {
+    /// The user, at the terminal.
+    Terminal,
+    /// The lines of a script, for unattended runs. Once the script runs
+    /// out, every prompt is answered with an empty line.
+    Script(Mutex<VecDeque<String>>)
 }
 
-pub fn prompt(s: &str) -> String {
-    loop {
-        let mut input = String::new();
-        reset();
-        println!("{}", s);
-        println!("\x07");
-
-        if io::stdin().read_line(&mut input).is_ok() {
-            println!("Please wait...");
-            return (&input[0..input.len()-1]).into();
-        }
+impl Input {
+    pub fn script(contents: &str) -> Input {
+        Input::Script(Mutex::new(contents.lines().map(|line| line.into()).collect()))
     }
 }
 
-pub struct TemporaryFile {
-    path: String,
-    f: Option<File>
+/// Configuration for exchanging discs with the other machine.
+#[derive(Clone)]
+pub struct DiscConfig {
+    /// Directory (with trailing slash) where disc contents are staged
+    /// before burning and after reading.
+    pub directory_prefix: String,
+    /// Whether the user is asked to write down the hash of every disc.
+    pub ask_user_to_record_hashes: bool,
+    /// The medium discs are written to and read from.
+    pub media: Arc<Media>,
+    pub input: Arc<Input>,
+    /// What the participant is shown between prompts.
+    pub ui: Arc<Ui>
 }
 
-impl TemporaryFile {
-    pub fn reset(&mut self) {
-        self.f = None;
-        self.f = Some(File::open(&self.path).unwrap());
-    }
-}
+impl DiscConfig {
+    /// `directory_prefix` for staging discs in `directory`, which is
+    /// joined with disc names as is.
+    pub fn directory_prefix(directory: &str) -> String {
+        let mut prefix = directory.to_string();
+        if !prefix.ends_with('/') {
+            prefix.push('/');
+        }
 
-impl Read for TemporaryFile {
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.f.as_mut().unwrap().read(buf)
+        prefix
     }
-}
 
-impl Drop for TemporaryFile {
-    fn drop(&mut self) {
-        // Close the file descriptor...
-        self.f = None;
+    /// Shows `s` and waits for the answer, from the user or the script.
+    pub fn prompt(&self, s: &str) -> String {
+        match *self.input {
+            Input::Terminal => loop {
+                self.ui.draw();
 
-        // Delete the file.
-        loop {
-            if fs::remove_file(&self.path).is_ok() {
-                return;
+                if let Some(answer) = ask(s) {
+                    return answer;
+                }
+            },
+            Input::Script(ref lines) => {
+                println!("{}", s);
+                thread::sleep(Duration::from_millis(SCRIPT_DELAY));
+
+                let answer = lines.lock().unwrap().pop_front().unwrap_or(String::new());
+                println!("> {}", answer);
+
+                answer
             }
-            println!("Failed to remove file! Trying again...");
-            thread::sleep(Duration::from_secs(1));
         }
     }
-}
 
-pub fn write_to_dvd(dvd_path: &str, local_path: &str) -> bool {
-    println!("Burning...");
-
-    let output = Command::new("/usr/bin/xorriso")
-                         .arg("-outdev")
-                         .arg("/dev/sr0")
-                         .arg("-md5")
-                         .arg("on")
-                         .arg("-blank")
-                         .arg("as_needed")
-                         .arg("-map")
-                         .arg(local_path)
-                         .arg(dvd_path)
-                         .arg("-add")
-                         .arg(local_path)
-                         .arg("--")
-                         .arg("-commit")
-                         .arg("-close")
-                         .arg("on")
-                         .output()
-                         .expect("failed to execute xorriso");
-
-    //let stderr = String::from_utf8_lossy(&output.stderr);
-    //println!("stderr of write: {}", stderr);
-
-    output.status.success()
+    pub fn is_scripted(&self) -> bool {
+        match *self.input {
+            Input::Script(_) => true,
+            Input::Terminal => false
+        }
+    }
 }
 
-pub enum DvdStatus {
-    File(TemporaryFile),
-    Blank,
-    Error
+/// Clears the entire terminal screen, moves cursor to top left.
+pub fn reset() {
+    print!("{}[2J", 27 as char);
+    print!("{}[1;1H", 27 as char);
+    println!("[MPC] Do not exit this process or shut the system off.");
+    println!("");
 }
 
-pub fn read_from_dvd(dvd_path: &str, local_path: &str) -> DvdStatus {
-    let output = Command::new("/usr/bin/xorriso")
-                         .arg("-md5")
-                         .arg("on")
-                         .arg("-osirrox")
-                         .arg("on")
-                         .arg("-indev")
-                         .arg("/dev/sr0")
-                         .arg("-extract")
-                         .arg(dvd_path)
-                         .arg(local_path)
-                         .output()
-                         .expect("failed to execute xorriso");
-
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        //println!("stderr of read: {}", stderr);
-        if stderr.contains("is blank") {
-            return DvdStatus::Blank
-        } else {
-            // Remove the file in case it did not write fully to local file, but
-            // xorriso didn't delete the partial contents. (Not sure if this
-            // actually ever happens, but we don't want participants running
-            // out of memory.)
-            let _ = fs::remove_file(local_path);
+pub fn prompt(s: &str) -> String {
+    loop {
+        reset();
 
-            return DvdStatus::Error
+        if let Some(answer) = ask(s) {
+            return answer;
         }
     }
+}
 
-    match File::open(local_path) {
-        Ok(f) => {
-            DvdStatus::File(TemporaryFile {
-                path: local_path.into(),
-                f: Some(f)
-            })
-        },
-        Err(_) => {
-            DvdStatus::Error
-        }
+fn ask(s: &str) -> Option<String> {
+    let mut input = String::new();
+    println!("{}", s);
+    println!("\x07");
+
+    if io::stdin().read_line(&mut input).is_ok() {
+        println!("Please wait...");
+        Some((&input[0..input.len()-1]).into())
+    } else {
+        None
     }
 }
 
-pub fn eject() {
-    let _ = Command::new("/usr/bin/eject")
-            .arg("/dev/sr0")
-            .output();
-}
+pub fn perform_diagnostics(config: &DiscConfig) {
+    // The checks assume a DVD drive which the machine booted from, and
+    // blank discs which can only be burned once.
+    if !config.media.is_boot_medium() {
+        return;
+    }
 
-pub fn perform_diagnostics() {
     loop {
-        if let DvdStatus::File(_) = read_from_dvd(REMOTEPATH_ALPINE_RELEASE, &format!("{}read_from_iso", ::DIRECTORY_PREFIX)) {
+        if let MediaStatus::File(_) = config.media.read(REMOTEPATH_ALPINE_RELEASE, &format!("{}read_from_iso", config.directory_prefix)) {
             break;
         } else {
             println!("ERROR! There was a problem reading from the drive or you removed the boot disk from the drive.");
@@ -158,47 +136,47 @@ pub fn perform_diagnostics() {
     }
 
     loop {
-        if let DvdStatus::File(_) = read_from_dvd(REMOTEPATH_ALPINE_RELEASE, &format!("{}read_from_iso", ::DIRECTORY_PREFIX)) {
-            eject();
-            prompt("Please remove the disk from the drive and place it somewhere safe.\n\n \
-                    Press [ENTER] when ready to continue.");
+        if let MediaStatus::File(_) = config.media.read(REMOTEPATH_ALPINE_RELEASE, &format!("{}read_from_iso", config.directory_prefix)) {
+            config.media.eject();
+            config.prompt("Please remove the disk from the drive and place it somewhere safe.\n\n \
+                           Press [ENTER] when ready to continue.");
         } else {
             break;
         }
     }
 
-    eject();
-    prompt("Please place a blank DVD into the drive. Press [ENTER] when ready to continue.");
+    config.media.eject();
+    config.prompt("Please place a blank DVD into the drive. Press [ENTER] when ready to continue.");
 
     loop {
-        if let DvdStatus::Blank = read_from_dvd(REMOTEPATH_ALPINE_RELEASE, &format!("{}read_from_iso", ::DIRECTORY_PREFIX)) {
+        if let MediaStatus::Blank = config.media.read(REMOTEPATH_ALPINE_RELEASE, &format!("{}read_from_iso", config.directory_prefix)) {
             break;
         } else {
-            eject();
-            prompt("Try again! Please place a blank DVD into the drive. Press [ENTER] when ready to continue.");
+            config.media.eject();
+            config.prompt("Try again! Please place a blank DVD into the drive. Press [ENTER] when ready to continue.");
         }
     }
 
     {
-        let mut f = File::create(&format!("{}mpc_testburn", ::DIRECTORY_PREFIX)).unwrap();
+        let mut f = File::create(&format!("{}mpc_testburn", config.directory_prefix)).unwrap();
         f.write(&[0xff, 0xff, 0xfa, 0x00]).unwrap();
         f.flush().unwrap();
     }
 
     loop {
-        if write_to_dvd(REMOTEPATH_TEST_BURN, &format!("{}mpc_testburn", ::DIRECTORY_PREFIX)) {
+        if config.media.write(REMOTEPATH_TEST_BURN, &format!("{}mpc_testburn", config.directory_prefix)) {
             break;
         }
 
         thread::sleep(Duration::from_secs(3));
     }
 
-    if !fs::remove_file(&format!("{}mpc_testburn", ::DIRECTORY_PREFIX)).is_ok() {
+    if !fs::remove_file(&format!("{}mpc_testburn", config.directory_prefix)).is_ok() {
         panic!("could not remove local testburn file");
     }
 
     loop {
-        if let DvdStatus::File(mut f) = read_from_dvd(REMOTEPATH_TEST_BURN, &format!("{}mpc_testburn", ::DIRECTORY_PREFIX)) {
+        if let MediaStatus::File(mut f) = config.media.read(REMOTEPATH_TEST_BURN, &format!("{}mpc_testburn", config.directory_prefix)) {
             let mut contents = vec![];
             f.read_to_end(&mut contents).unwrap();
             assert!((&*contents) == &[0xff, 0xff, 0xfa, 0x00]);
@@ -209,16 +187,16 @@ pub fn perform_diagnostics() {
         }
     }
 
-    eject();
-    prompt("Please remove the DVD from the drive and label it 'testburn'. It will not be used again.\n\n\
-            Press [ENTER] when the drive is clear.");
+    config.media.eject();
+    config.prompt("Please remove the DVD from the drive and label it 'testburn'. It will not be used again.\n\n\
+                   Press [ENTER] when the drive is clear.");
 
     loop {
-        if let DvdStatus::Error = read_from_dvd(REMOTEPATH_ALPINE_RELEASE, &format!("{}read_from_iso", ::DIRECTORY_PREFIX)) {
+        if let MediaStatus::Error = config.media.read(REMOTEPATH_ALPINE_RELEASE, &format!("{}read_from_iso", config.directory_prefix)) {
             break;
         } else {
-            eject();
-            prompt("Try again! Please remove the DVD from the drive. Press [ENTER] when the drive is clear.");
+            config.media.eject();
+            config.prompt("Try again! Please remove the DVD from the drive. Press [ENTER] when the drive is clear.");
         }
     }
 }
@@ -227,6 +205,16 @@ pub fn hash_of_file<R: Read>(f: &mut R) -> Digest256 {
     Digest256::from_reader(f)
 }
 
+/// The name of disc `name` on the `attempt`th try of a turn, counting
+/// from 1, since a player may have to take a turn again.
+pub fn retry_disc(name: &str, attempt: usize) -> String {
+    if attempt == 1 {
+        name.into()
+    } else {
+        format!("{}{}", name, attempt)
+    }
+}
+
 pub fn exchange_disc<
     T,
     R1,
@@ -234,140 +222,228 @@ pub fn exchange_disc<
     F1: Fn(&mut File) -> Result<(), R1>,
     F2: Fn(&mut TemporaryFile, Option<Digest256>) -> Result<T, R2>
 >(
+    config: &DiscConfig,
     our_disc: &str,
     their_disc: &str,
     our_cb: F1,
     their_cb: F2
 ) -> T
 {
-    let newdisc_localpath = &format!("{}disc{}", ::DIRECTORY_PREFIX, our_disc);
+    exchange_discs(config, our_disc, &[their_disc], our_cb, |_, f, h| their_cb(f, h))
+}
+
+/// Burns `our_disc`, then reads whichever of `their_discs` the other
+/// machine burned in return, passing its index to `their_cb`.
+pub fn exchange_discs<
+    T,
+    R1,
+    R2,
+    F1: Fn(&mut File) -> Result<(), R1>,
+    F2: Fn(usize, &mut TemporaryFile, Option<Digest256>) -> Result<T, R2>
+>(
+    config: &DiscConfig,
+    our_disc: &str,
+    their_discs: &[&str],
+    our_cb: F1,
+    their_cb: F2
+) -> T
+{
+    let their_disc = their_discs.join("' or '");
+    let newdisc_localpath = &format!("{}disc{}", config.directory_prefix, our_disc);
     let newdisc_remotepath = &format!("disc{}", our_disc);
     {
         let mut newdisc = File::create(newdisc_localpath).unwrap();
         our_cb(&mut newdisc).ok().unwrap();
     }
-    if ::ASK_USER_TO_RECORD_HASHES {
+    if config.ask_user_to_record_hashes {
         let mut newdisc = File::open(newdisc_localpath).unwrap();
         let h = hash_of_file(&mut newdisc);
 
-        write_down_disc_please(&h, our_disc);
+        write_down_disc_please(config, &h, our_disc);
     }
 
+    config.ui.set_step(&format!("Burning disc '{}', then reading disc '{}'", our_disc, their_disc));
+
     let mut already_burned = false;
 
     loop {
         if already_burned {
-            eject();
-            prompt(&format!("Insert disc '{}' from the other machine. If the burn of disc '{}' failed,\n\
-                             insert another blank disc to burn it again. Press [ENTER] when ready.",
-                            their_disc, our_disc));
+            config.media.eject();
+            config.prompt(&format!("Insert disc '{}' from the other machine. If the burn of disc '{}' failed,\n\
+                                    insert another blank disc to burn it again. Press [ENTER] when ready.",
+                                   their_disc, our_disc));
         } else {
-            eject();
-            prompt(&format!("Please insert a blank DVD to burn disc '{}'. Then press [ENTER].",
-                            our_disc));
+            config.media.eject();
+            config.prompt(&format!("Please insert a blank DVD to burn disc '{}'. Then press [ENTER].",
+                                   our_disc));
+        }
+
+        // The medium is blank if it has none of their discs, unless it
+        // could not be read at all.
+        let mut status = MediaStatus::Error;
+        let mut found = 0;
+        for (i, name) in their_discs.iter().enumerate() {
+            match config.media.read(&format!("disc{}", name), &format!("{}disc{}", config.directory_prefix, name)) {
+                MediaStatus::File(f) => {
+                    status = MediaStatus::File(f);
+                    found = i;
+                    break;
+                },
+                MediaStatus::Blank => {
+                    status = MediaStatus::Blank;
+                },
+                MediaStatus::Error => {}
+            }
         }
 
-        match read_from_dvd(&format!("disc{}", their_disc), &format!("{}disc{}", ::DIRECTORY_PREFIX, their_disc)) {
-            DvdStatus::File(mut f) => {
+        match status {
+            MediaStatus::File(mut f) => {
+                let their_disc = their_discs[found];
                 let h;
-                if ::ASK_USER_TO_RECORD_HASHES {
+                if config.ask_user_to_record_hashes {
                     h = Some(hash_of_file(&mut f));
-                    write_down_disc_please(&h.clone().unwrap(), their_disc);
+                    write_down_disc_please(config, &h.clone().unwrap(), their_disc);
                     f.reset();
                 } else {
                     h = None;
                 }
 
-                match their_cb(&mut f, h) {
+                match their_cb(found, &mut f, h) {
                     Ok(data) => {
                         let _ = fs::remove_file(newdisc_localpath);
+                        config.ui.disc_done(their_disc);
 
                         return data;
                     },
                     Err(_) => {
-                        eject();
-                        prompt(&format!("The disc '{}' you inserted may be corrupted. Burn it again \
-                                         on the other machine. Then insert the new disc '{}' and \
-                                         press [ENTER].", their_disc, their_disc));
+                        config.media.eject();
+                        config.prompt(&format!("The disc '{}' you inserted may be corrupted. Burn it again \
+                                                on the other machine. Then insert the new disc '{}' and \
+                                                press [ENTER].", their_disc, their_disc));
                     }
                 }
             },
-            DvdStatus::Error => {
-                eject();
+            MediaStatus::Error => {
+                config.media.eject();
             },
-            DvdStatus::Blank => {
-                write_to_dvd(newdisc_remotepath, newdisc_localpath);
-                eject();
+            MediaStatus::Blank => {
+                config.media.write(newdisc_remotepath, newdisc_localpath);
+                config.media.eject();
                 already_burned = true;
+                config.ui.disc_done(our_disc);
 
-                prompt(&format!("Disc {} has been burned. Label the disc and transfer it to the\n\
-                                 other machine. Press [ENTER] when the drive is clear.", our_disc));
+                config.prompt(&format!("Disc {} has been burned. Label the disc and transfer it to the\n\
+                                        other machine. Press [ENTER] when the drive is clear.", our_disc));
             }
         }
     }
 }
 
-pub fn write_disc<
-    R,
-    F: Fn(&mut File) -> Result<(), R>
+/// Burns `our_disc`, the last disc of the player's turn in the last
+/// stage, unless the other machine burns `again` in return because the
+/// turn is to be taken again. Reads `again` with `their_cb` in that case,
+/// and returns what it read. Nothing tells a scripted run that the turn
+/// is over, so it is over once the last disc is written.
+pub fn write_last_disc<
+    T,
+    R1,
+    R2,
+    F1: Fn(&mut File) -> Result<(), R1>,
+    F2: Fn(&mut TemporaryFile, Option<Digest256>) -> Result<T, R2>
 >(
+    config: &DiscConfig,
     our_disc: &str,
-    our_cb: F
-)
+    again: &str,
+    our_cb: F1,
+    their_cb: F2
+) -> Option<T>
 {
-    let newdisc_localpath = &format!("{}disc{}", ::DIRECTORY_PREFIX, our_disc);
+    let newdisc_localpath = &format!("{}disc{}", config.directory_prefix, our_disc);
     let newdisc_remotepath = &format!("disc{}", our_disc);
     {
         let mut newdisc = File::create(newdisc_localpath).unwrap();
         our_cb(&mut newdisc).ok().unwrap();
     }
-    if ::ASK_USER_TO_RECORD_HASHES {
+    if config.ask_user_to_record_hashes {
         let mut newdisc = File::open(newdisc_localpath).unwrap();
         let h = hash_of_file(&mut newdisc);
 
-        write_down_disc_please(&h, our_disc);
+        write_down_disc_please(config, &h, our_disc);
     }
 
+    config.ui.set_step(&format!("Burning disc '{}'", our_disc));
+
     let mut already_burned = false;
-    eject();
+    config.media.eject();
 
     loop {
         if already_burned {
-            prompt(&format!("If the burn of disc '{}' failed, you can insert another blank\n\
-                             DVD to burn again. Then press [ENTER] to continue.",
-                            our_disc));
+            config.prompt(&format!("If the other machine burns disc '{}' for disc '{}' to be computed again,\n\
+                                    insert it. If the burn of disc '{}' failed, you can insert another\n\
+                                    blank DVD to burn again. Then press [ENTER] to continue.",
+                                   again, our_disc, our_disc));
         } else {
-            prompt(&format!("Please insert a blank DVD to burn disc '{}'.\n\n\
-                             Then press [ENTER] to continue.",
-                            our_disc));
+            config.prompt(&format!("Please insert a blank DVD to burn disc '{}'.\n\n\
+                                    Then press [ENTER] to continue.",
+                                   our_disc));
         }
 
-        match read_from_dvd(newdisc_remotepath, newdisc_localpath) {
-            DvdStatus::Blank => {
-                write_to_dvd(newdisc_remotepath, newdisc_localpath);
-                eject();
+        match config.media.read(&format!("disc{}", again), &format!("{}disc{}", config.directory_prefix, again)) {
+            MediaStatus::File(mut f) => {
+                let h;
+                if config.ask_user_to_record_hashes {
+                    h = Some(hash_of_file(&mut f));
+                    write_down_disc_please(config, &h.clone().unwrap(), again);
+                    f.reset();
+                } else {
+                    h = None;
+                }
+
+                match their_cb(&mut f, h) {
+                    Ok(data) => {
+                        let _ = fs::remove_file(newdisc_localpath);
+                        config.ui.disc_done(again);
+
+                        return Some(data);
+                    },
+                    Err(_) => {
+                        config.media.eject();
+                        config.prompt(&format!("The disc '{}' you inserted may be corrupted. Burn it again \
+                                                on the other machine. Then press [ENTER].", again));
+                    }
+                }
+            },
+            MediaStatus::Blank => {
+                config.media.write(newdisc_remotepath, newdisc_localpath);
+                config.media.eject();
                 already_burned = true;
+                config.ui.disc_done(our_disc);
+
+                config.prompt(&format!("Disc {} has been burned. Label the disc and transfer it to the\n\
+                                        other machine. Press [ENTER] when the drive is clear.", our_disc));
 
-                prompt(&format!("Disc {} has been burned. Label the disc and transfer it to the\n\
-                                 other machine. Press [ENTER] when the drive is clear.", our_disc));
+                if config.is_scripted() {
+                    return None;
+                }
             },
-            _ => {
-                eject();
+            MediaStatus::Error => {
+                config.media.eject();
             }
         }
     }
 }
 
-pub fn read_disc<T, R, F: Fn(&mut TemporaryFile, Option<Digest256>) -> Result<T, R>>(name: &str, message: &str, cb: F) -> T {
-    prompt(message);
+pub fn read_disc<T, R, F: Fn(&mut TemporaryFile, Option<Digest256>) -> Result<T, R>>(config: &DiscConfig, name: &str, message: &str, cb: F) -> T {
+    config.ui.set_step(&format!("Reading disc '{}'", name));
+    config.prompt(message);
 
     loop {
-        match read_from_dvd(&format!("disc{}", name), &format!("{}disc{}", ::DIRECTORY_PREFIX, name)) {
-            DvdStatus::File(mut f) => {
+        match config.media.read(&format!("disc{}", name), &format!("{}disc{}", config.directory_prefix, name)) {
+            MediaStatus::File(mut f) => {
                 let h;
-                if ::ASK_USER_TO_RECORD_HASHES {
+                if config.ask_user_to_record_hashes {
                     h = Some(hash_of_file(&mut f));
-                    write_down_disc_please(&h.clone().unwrap(), name);
+                    write_down_disc_please(config, &h.clone().unwrap(), name);
                     f.reset();
                 } else {
                     h = None;
@@ -375,36 +451,46 @@ pub fn read_disc<T, R, F: Fn(&mut TemporaryFile, Option<Digest256>) -> Result<T,
 
                 match cb(&mut f, h) {
                     Ok(data) => {
+                        config.ui.disc_done(name);
+
                         return data;
                     },
                     Err(_) => {
-                        eject();
-                        prompt(&format!("The disc you inserted may be corrupted. Burn it again \
-                                on the other machine.\n\n{}", message));
+                        config.media.eject();
+                        config.prompt(&format!("The disc you inserted may be corrupted. Burn it again \
+                                       on the other machine.\n\n{}", message));
                     }
                 }
             },
-            DvdStatus::Error => {
-                eject();
-                prompt(message);
+            MediaStatus::Error => {
+                config.media.eject();
+                config.prompt(message);
             },
-            DvdStatus::Blank => {
-                eject();
-                prompt(&format!("You placed a blank DVD in the drive, but we're expecting \
-                                 disc '{}'.\n\n{}", name, message));
+            MediaStatus::Blank => {
+                config.media.eject();
+                config.prompt(&format!("You placed a blank DVD in the drive, but we're expecting \
+                                        disc '{}'.\n\n{}", name, message));
             }
         }
     }
 }
 
-pub fn write_down_disc_please(h: &Digest256, name: &str) {
+pub fn write_down_disc_please(config: &DiscConfig, h: &Digest256, name: &str) {
+    if config.is_scripted() {
+        println!("The hash of disc '{}' is: {}", name, h.to_string());
+        config.ui.record_hash(name, h);
+        return;
+    }
+
     loop {
-        if "recorded" == prompt(&format!("Please write down and publish the string: {}\n\
-                                          It is the hash of disc '{}'.\n\n\
-                                          Type 'recorded' and press [ENTER] to confirm you've written it down.",
-                                          h.to_string(),
-                                          name)) {
+        if "recorded" == config.prompt(&format!("Please write down and publish the string: {}\n\
+                                                 It is the hash of disc '{}'.\n\n\
+                                                 Type 'recorded' and press [ENTER] to confirm you've written it down.",
+                                                 h.to_string(),
+                                                 name)) {
             break;
         }
     }
+
+    config.ui.record_hash(name, h);
 }
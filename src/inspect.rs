@@ -0,0 +1,118 @@
+#![allow(non_snake_case, dead_code)]
+
+extern crate mpc;
+
+use mpc::config::Config;
+use mpc::transcript::*;
+
+use std::fs::File;
+use std::process;
+
+const TRANSCRIPT_PATH: &'static str = "transcript";
+
+fn usage() -> ! {
+    println!("Usage:");
+    println!("  transcript inspect [--transcript PATH]");
+    println!("      List every record in the transcript with its size and digest.");
+    println!("  transcript extract STAGE PLAYER RECORD OUTPUT [--transcript PATH]");
+    println!("      Write a single record to OUTPUT. STAGE is 0 for commitments, PLAYER");
+    println!("      starts at 1, and RECORD is one of commitment, pubkey, nizks, stage1,");
+    println!("      stage2, stage3 or ihash.");
+    process::exit(1);
+}
+
+/// Loads the index saved next to the transcript, rebuilding it if it
+/// is missing or out of date.
+fn load_index(transcript: &mut File, path: &str) -> TranscriptIndex {
+    let index_path = format!("{}.index", path);
+    let transcript_len = transcript.metadata().unwrap().len();
+
+    if let Some(index) = TranscriptIndex::load(&index_path) {
+        if index.transcript_len == transcript_len {
+            return index;
+        }
+    }
+
+    println!("Indexing {}...", path);
+    let index = match TranscriptIndex::build(transcript) {
+        Ok(index) => index,
+        Err(e) => {
+            println!("Could not index transcript: {}", e);
+            process::exit(1);
+        }
+    };
+
+    if let Err(e) = index.save(&index_path) {
+        println!("Warning: could not save index to {}: {}", index_path, e);
+    }
+
+    index
+}
+
+fn inspect(index: &TranscriptIndex) {
+    let header = &index.header;
+
+    if header.is_legacy() {
+        println!("Legacy transcript, {} players", header.players);
+    } else {
+        println!("Transcript version {}, curve {:?}, {} players", header.version, header.curve, header.players);
+    }
+    if let Some(ref cs_hash) = header.cs_hash {
+        println!("Constraint system: {}", cs_hash.to_string());
+    }
+    println!("");
+
+    println!("{:>12} {:>12} {:>5} {:>6} {:>10}  {}", "offset", "size", "stage", "player", "record", "digest");
+    for e in &index.entries {
+        println!("{:>12} {:>12} {:>5} {:>6} {:>10}  {}",
+                 e.offset, e.len, e.stage, e.player + 1, e.record.name(), e.digest.to_string());
+    }
+}
+
+fn main() {
+    let options = Config::from_args(std::env::args().skip(1), &[], &["transcript"]).unwrap_or_else(|_| usage());
+    let args = options.positional();
+    let path = options.get("transcript").unwrap_or(TRANSCRIPT_PATH).to_string();
+
+    let mut f = match File::open(&path) {
+        Ok(f) => f,
+        Err(e) => {
+            println!("Could not open {}: {}", path, e);
+            process::exit(1);
+        }
+    };
+
+    match args.first().map(|s| &s[..]) {
+        Some("inspect") if args.len() == 1 => {
+            let index = load_index(&mut f, &path);
+            inspect(&index);
+        },
+        Some("extract") if args.len() == 5 => {
+            let stage: usize = args[1].parse().unwrap_or_else(|_| usage());
+            let player: usize = args[2].parse().unwrap_or_else(|_| usage());
+            let record = RecordType::from_name(&args[3]).unwrap_or_else(|| usage());
+
+            if player == 0 {
+                usage();
+            }
+
+            let index = load_index(&mut f, &path);
+            let entry = match index.find(stage, player - 1, record) {
+                Some(entry) => entry.clone(),
+                None => {
+                    println!("No {} record for player {} in stage {}", record.name(), player, stage);
+                    process::exit(1);
+                }
+            };
+
+            let mut out = File::create(&args[4]).unwrap();
+            if let Err(e) = index.extract(&mut f, &entry, &mut out) {
+                println!("Could not extract record: {}", e);
+                process::exit(1);
+            }
+
+            println!("Wrote {} bytes to {} (digest {})", entry.len, args[4], entry.digest.to_string());
+        },
+        _ => usage()
+    }
+}
@@ -0,0 +1,41 @@
+//! Multi-party computation for the key-generation step of Pinocchio
+//! zkSNARKs. The `coordinator`, `compute`, `network` and `verifier`
+//! binaries are built on top of this library, and it can be used
+//! directly by other tooling, for instance to verify a transcript.
+//!
+//! The `transport` and `client` modules let the session logic run over
+//! TCP, Unix sockets, shared directories or in memory.
+//!
+//! The number of threads used by the protocol is configured at runtime
+//! with `set_threads`.
+
+#![allow(non_snake_case, dead_code)]
+
+extern crate bn;
+extern crate rand;
+#[cfg(feature = "snark")]
+extern crate snark;
+extern crate crossbeam;
+extern crate rustc_serialize;
+extern crate blake2_rfc;
+extern crate bincode;
+extern crate byteorder;
+extern crate crypto;
+
+#[macro_use]
+pub mod protocol;
+pub use protocol::*;
+
+pub mod consts;
+pub mod channel;
+pub mod wire;
+pub mod transport;
+pub mod client;
+pub mod config;
+pub mod media;
+pub mod dvd;
+pub mod ui;
+pub mod transcript;
+
+#[cfg(feature = "snark")]
+pub mod verify;
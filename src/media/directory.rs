@@ -0,0 +1,104 @@
+//! Discs as files in a directory: either on a mounted USB drive, or in
+//! a local directory that simulates a drive for rehearsals.
+//!
+//! A directory is always writable, so a disc that is not there reads
+//! as blank, and writing it again replaces it.
+
+use std::fs::{self, File};
+use std::path::PathBuf;
+use std::process::Command;
+
+use super::{Media, MediaStatus, copied};
+
+pub struct Directory {
+    path: PathBuf,
+    /// Whether `path` is the mount point of a removable drive, which is
+    /// unmounted when it is ejected.
+    removable: bool
+}
+
+impl Directory {
+    pub fn usb(mount_point: &str) -> Directory {
+        Directory {
+            path: mount_point.into(),
+            removable: true
+        }
+    }
+
+    pub fn simulated(path: &str) -> Directory {
+        Directory {
+            path: path.into(),
+            removable: false
+        }
+    }
+
+    /// Whether a drive is mounted at the mount point, which is on a
+    /// different device than the directory containing it if so.
+    #[cfg(unix)]
+    fn is_mounted(&self) -> bool {
+        use std::os::unix::fs::MetadataExt;
+
+        let parent = match self.path.parent() {
+            Some(parent) => parent,
+            None => return true
+        };
+
+        match (fs::metadata(&self.path), fs::metadata(parent)) {
+            (Ok(ours), Ok(theirs)) => ours.dev() != theirs.dev(),
+            _ => false
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn is_mounted(&self) -> bool {
+        self.path.is_dir()
+    }
+}
+
+impl Media for Directory {
+    fn write(&self, remote_path: &str, local_path: &str) -> bool {
+        if self.removable && !self.is_mounted() {
+            return false;
+        } else if !self.removable && fs::create_dir_all(&self.path).is_err() {
+            return false;
+        }
+
+        let path = self.path.join(remote_path);
+        let partial = self.path.join(format!("{}.partial", remote_path));
+
+        // The copy must reach the drive before it is unmounted, and is
+        // renamed into place once complete so that the other machine
+        // never reads a partial disc.
+        fs::copy(local_path, &partial).is_ok() &&
+        File::open(&partial).and_then(|f| f.sync_all()).is_ok() &&
+        fs::rename(&partial, &path).is_ok()
+    }
+
+    fn read(&self, remote_path: &str, local_path: &str) -> MediaStatus {
+        if self.removable && !self.is_mounted() {
+            return MediaStatus::Error;
+        }
+
+        let path = self.path.join(remote_path);
+
+        if !path.is_file() {
+            return MediaStatus::Blank;
+        }
+
+        if fs::copy(&path, local_path).is_err() {
+            let _ = fs::remove_file(local_path);
+
+            return MediaStatus::Error;
+        }
+
+        copied(local_path)
+    }
+
+    fn eject(&self) {
+        if self.removable && self.is_mounted() {
+            let _ = Command::new("/bin/umount")
+                    .arg(&self.path)
+                    .output();
+        }
+    }
+}
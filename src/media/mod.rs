@@ -0,0 +1,118 @@
+//! Removable media used to carry discs across the air gap between the
+//! compute and network machines.
+//!
+//! The disc exchange in `dvd` only needs to write a file to a medium
+//! and read one back, so it is written against the `Media` trait. The
+//! medium is chosen with `open`: `optical:DEVICE` burns DVDs with
+//! xorriso (the default, on `/dev/sr0`), `usb:PATH` uses a USB drive
+//! mounted at `PATH`, `qr:PATH` renders discs as sequences of QR codes
+//! and decodes scanned ones in `PATH`, and `dir:PATH` simulates discs
+//! with files in a local directory, so the flow can be rehearsed
+//! without a drive.
+
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+mod optical;
+mod directory;
+mod qr;
+pub use self::optical::*;
+pub use self::directory::*;
+pub use self::qr::*;
+
+pub const DEFAULT_MEDIA: &'static str = "optical:/dev/sr0";
+
+/// What was found on the medium when reading a disc from it.
+pub enum MediaStatus {
+    File(TemporaryFile),
+    Blank,
+    Error
+}
+
+/// A removable medium, on which each disc is a file.
+pub trait Media: Send + Sync {
+    /// Writes the file at `local_path` to the medium as `remote_path`.
+    fn write(&self, remote_path: &str, local_path: &str) -> bool;
+
+    /// Copies `remote_path` from the medium to `local_path`.
+    fn read(&self, remote_path: &str, local_path: &str) -> MediaStatus;
+
+    fn eject(&self);
+
+    /// Whether the machine boots from this kind of medium, in which case
+    /// the diagnostics make sure the boot medium has been removed.
+    fn is_boot_medium(&self) -> bool {
+        false
+    }
+}
+
+/// Opens the medium described by `spec`, see the module documentation
+/// for its format.
+pub fn open(spec: &str) -> Result<Arc<Media>, String> {
+    let (kind, path) = match spec.find(':') {
+        Some(i) => (&spec[0..i], &spec[i+1..]),
+        None => return Err(format!("invalid medium {}, expected KIND:PATH", spec))
+    };
+
+    match kind {
+        "optical" => Ok(Arc::new(Optical::new(path))),
+        "usb" => Ok(Arc::new(Directory::usb(path))),
+        "qr" => Ok(Arc::new(QrCodes::new(path))),
+        "dir" => Ok(Arc::new(Directory::simulated(path))),
+        _ => Err(format!("unknown kind of medium {}", kind))
+    }
+}
+
+/// A disc copied off the medium, which is deleted once it is no longer
+/// needed.
+pub struct TemporaryFile {
+    path: String,
+    f: Option<File>
+}
+
+impl TemporaryFile {
+    pub fn open(path: &str) -> io::Result<TemporaryFile> {
+        Ok(TemporaryFile {
+            path: path.into(),
+            f: Some(try!(File::open(path)))
+        })
+    }
+
+    pub fn reset(&mut self) {
+        self.f = None;
+        self.f = Some(File::open(&self.path).unwrap());
+    }
+}
+
+impl Read for TemporaryFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.f.as_mut().unwrap().read(buf)
+    }
+}
+
+impl Drop for TemporaryFile {
+    fn drop(&mut self) {
+        // Close the file descriptor...
+        self.f = None;
+
+        // Delete the file.
+        loop {
+            if fs::remove_file(&self.path).is_ok() {
+                return;
+            }
+            println!("Failed to remove file! Trying again...");
+            thread::sleep(Duration::from_secs(1));
+        }
+    }
+}
+
+/// Opens a disc that was copied to `local_path`.
+fn copied(local_path: &str) -> MediaStatus {
+    match TemporaryFile::open(local_path) {
+        Ok(f) => MediaStatus::File(f),
+        Err(_) => MediaStatus::Error
+    }
+}
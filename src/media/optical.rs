@@ -0,0 +1,91 @@
+//! DVDs, burned and read with xorriso.
+
+use std::fs;
+use std::process::Command;
+
+use super::{Media, MediaStatus, copied};
+
+pub struct Optical {
+    device: String
+}
+
+impl Optical {
+    pub fn new(device: &str) -> Optical {
+        Optical {
+            device: device.into()
+        }
+    }
+}
+
+impl Media for Optical {
+    fn write(&self, dvd_path: &str, local_path: &str) -> bool {
+        println!("Burning...");
+
+        let output = Command::new("/usr/bin/xorriso")
+                             .arg("-outdev")
+                             .arg(&self.device)
+                             .arg("-md5")
+                             .arg("on")
+                             .arg("-blank")
+                             .arg("as_needed")
+                             .arg("-map")
+                             .arg(local_path)
+                             .arg(dvd_path)
+                             .arg("-add")
+                             .arg(local_path)
+                             .arg("--")
+                             .arg("-commit")
+                             .arg("-close")
+                             .arg("on")
+                             .output()
+                             .expect("failed to execute xorriso");
+
+        //let stderr = String::from_utf8_lossy(&output.stderr);
+        //println!("stderr of write: {}", stderr);
+
+        output.status.success()
+    }
+
+    fn read(&self, dvd_path: &str, local_path: &str) -> MediaStatus {
+        let output = Command::new("/usr/bin/xorriso")
+                             .arg("-md5")
+                             .arg("on")
+                             .arg("-osirrox")
+                             .arg("on")
+                             .arg("-indev")
+                             .arg(&self.device)
+                             .arg("-extract")
+                             .arg(dvd_path)
+                             .arg(local_path)
+                             .output()
+                             .expect("failed to execute xorriso");
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            //println!("stderr of read: {}", stderr);
+            if stderr.contains("is blank") {
+                return MediaStatus::Blank
+            } else {
+                // Remove the file in case it did not write fully to local file, but
+                // xorriso didn't delete the partial contents. (Not sure if this
+                // actually ever happens, but we don't want participants running
+                // out of memory.)
+                let _ = fs::remove_file(local_path);
+
+                return MediaStatus::Error
+            }
+        }
+
+        copied(local_path)
+    }
+
+    fn eject(&self) {
+        let _ = Command::new("/usr/bin/eject")
+                .arg(&self.device)
+                .output();
+    }
+
+    fn is_boot_medium(&self) -> bool {
+        true
+    }
+}
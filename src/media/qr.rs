@@ -0,0 +1,177 @@
+//! Discs as sequences of QR codes, for machines without a writable
+//! drive. A disc is split into frames rendered with qrencode as images
+//! `NAME/0000.png`, `NAME/0001.png` and so on, to be shown on screen or
+//! printed. The other machine decodes photographs or scans of them,
+//! placed in the same layout, with zbarimg.
+
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use rustc_serialize::base64::{ToBase64, FromBase64, STANDARD};
+
+use super::{Media, MediaStatus, copied};
+
+/// Bytes of the disc in each frame, which fits a QR code with room to
+/// spare once encoded as base64.
+const FRAME_SIZE: usize = 1024;
+
+pub struct QrCodes {
+    path: PathBuf
+}
+
+impl QrCodes {
+    pub fn new(path: &str) -> QrCodes {
+        QrCodes {
+            path: path.into()
+        }
+    }
+}
+
+/// Parses a frame, which starts with its index and the number of frames
+/// so that missing or repeated frames are noticed.
+fn parse_frame(text: &str) -> Option<(usize, usize, Vec<u8>)> {
+    let colon = match text.find(':') {
+        Some(colon) => colon,
+        None => return None
+    };
+
+    let mut header = text[0..colon].split('/');
+    let index = header.next().and_then(|i| i.parse().ok());
+    let total = header.next().and_then(|n| n.parse().ok());
+
+    match (index, total, text[colon+1..].from_base64()) {
+        (Some(index), Some(total), Ok(data)) => Some((index, total, data)),
+        _ => None
+    }
+}
+
+fn decode_frame(image: &Path) -> Option<(usize, usize, Vec<u8>)> {
+    let output = match Command::new("/usr/bin/zbarimg").arg("--raw").arg("-q").arg(image).output() {
+        Ok(output) => output,
+        Err(_) => return None
+    };
+
+    if !output.status.success() {
+        return None;
+    }
+
+    parse_frame(String::from_utf8_lossy(&output.stdout).trim())
+}
+
+impl QrCodes {
+    fn assemble(&self, dir: &Path, local_path: &str) -> bool {
+        let images: Vec<PathBuf> = match fs::read_dir(dir) {
+            Ok(entries) => entries.filter_map(|e| e.ok()).map(|e| e.path()).collect(),
+            Err(_) => return false
+        };
+
+        let mut frames: Vec<Option<Vec<u8>>> = vec![];
+
+        for image in images {
+            let (index, total, data) = match decode_frame(&image) {
+                Some(frame) => frame,
+                None => {
+                    println!("Could not decode {}.", image.display());
+                    return false;
+                }
+            };
+
+            if frames.is_empty() {
+                frames = vec![None; total];
+            }
+
+            if total != frames.len() || index >= total {
+                println!("{} belongs to another disc.", image.display());
+                return false;
+            }
+
+            frames[index] = Some(data);
+        }
+
+        let mut f = match File::create(local_path) {
+            Ok(f) => f,
+            Err(_) => return false
+        };
+
+        for (index, frame) in frames.iter().enumerate() {
+            match *frame {
+                Some(ref data) => {
+                    if f.write_all(data).is_err() {
+                        return false;
+                    }
+                },
+                None => {
+                    println!("Frame {} is missing.", index);
+                    return false;
+                }
+            }
+        }
+
+        true
+    }
+}
+
+impl Media for QrCodes {
+    fn write(&self, remote_path: &str, local_path: &str) -> bool {
+        let dir = self.path.join(remote_path);
+        let _ = fs::remove_dir_all(&dir);
+
+        let mut contents = vec![];
+        if fs::create_dir_all(&dir).and_then(|_| File::open(local_path))
+                                   .and_then(|mut f| f.read_to_end(&mut contents)).is_err()
+        {
+            return false;
+        }
+
+        let frames: Vec<&[u8]> = contents.chunks(FRAME_SIZE).collect();
+
+        for (index, frame) in frames.iter().enumerate() {
+            let text = format!("{}/{}:{}", index, frames.len(), frame.to_base64(STANDARD));
+
+            match Command::new("/usr/bin/qrencode")
+                          .arg("-o")
+                          .arg(dir.join(format!("{:04}.png", index)))
+                          .arg(&text)
+                          .status()
+            {
+                Ok(status) if status.success() => {},
+                _ => return false
+            }
+        }
+
+        println!("Disc written as {} QR codes in {}.", frames.len(), dir.display());
+
+        true
+    }
+
+    fn read(&self, remote_path: &str, local_path: &str) -> MediaStatus {
+        let dir = self.path.join(remote_path);
+
+        if fs::read_dir(&dir).map(|mut entries| entries.next().is_none()).unwrap_or(true) {
+            return MediaStatus::Blank;
+        }
+
+        if !self.assemble(&dir, local_path) {
+            let _ = fs::remove_file(local_path);
+
+            return MediaStatus::Error;
+        }
+
+        copied(local_path)
+    }
+
+    fn eject(&self) {
+        // There is nothing to eject, the frames are shown or scanned
+        // outside of this program.
+    }
+}
+
+#[test]
+fn frame_parsing() {
+    let text = format!("2/5:{}", b"hello".to_base64(STANDARD));
+
+    assert_eq!(parse_frame(&text), Some((2, 5, b"hello".to_vec())));
+    assert_eq!(parse_frame("2/5"), None);
+    assert_eq!(parse_frame("x/5:aGVsbG8="), None);
+}
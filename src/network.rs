@@ -1,200 +1,152 @@
 #![allow(non_snake_case, dead_code)]
 
-extern crate bn;
-extern crate rand;
-extern crate crossbeam;
 extern crate rustc_serialize;
-extern crate blake2_rfc;
 extern crate bincode;
-extern crate byteorder;
-
-mod protocol;
-use self::protocol::*;
-mod consts;
-use self::consts::*;
-mod dvd;
-use self::dvd::*;
-
-use rand::Rng;
-use std::io::{Read,Write};
-use std::net::{TcpStream};
-use std::thread;
-use std::time::Duration;
+
+extern crate mpc;
+use mpc::*;
+use mpc::consts::*;
+use mpc::dvd::*;
+use mpc::config::Config;
+use mpc::channel::{self, StaticKey};
+use mpc::transport;
+use mpc::media;
+use mpc::ui::Ui;
+use mpc::client::Client;
+use mpc::wire::MessageType;
+
+use std::sync::Arc;
 use bincode::SizeLimit::Infinite;
 use bincode::rustc_serialize::{encode_into, decode_from};
-use rustc_serialize::{Decodable, Encodable};
-
-pub const THREADS: usize = 8;
-pub const DIRECTORY_PREFIX: &'static str = "/";
-pub const ASK_USER_TO_RECORD_HASHES: bool = false;
-const COORDINATOR_ADDR: &'static str = "mpc847619423.z.cash:65530";
-
-struct ConnectionHandler {
-    peerid: [u8; 8],
-    s: TcpStream,
-    msgid: u8
-}
 
-impl ConnectionHandler {
-    fn new() -> ConnectionHandler {
-        let peerid = rand::thread_rng().gen();
+const DEFAULT_THREADS: usize = 8;
+const DEFAULT_DIRECTORY: &'static str = "/";
+const ASK_USER_TO_RECORD_HASHES: bool = false;
+const DEFAULT_COORDINATOR_ADDR: &'static str = "mpc847619423.z.cash:65530";
+const DEFAULT_KEY_PATH: &'static str = "participant.key";
+
+const USAGE: &'static str = "\
+Usage: network [options]
+
+Options:
+  --config PATH         read options from a file of `key = value` lines
+  --coordinator ADDR    address of the coordinator (default mpc847619423.z.cash:65530);
+                        `unix:PATH` connects to a Unix socket and `dir:PATH`
+                        exchanges files through a shared directory
+  --coordinator-key HEX public key of the coordinator, as printed when it starts (required)
+  --key PATH            this participant's long-term key, generated if missing
+                        (default participant.key)
+  --timeout SECS        network read/write timeout (default 60)
+  --max-message-size N  largest message accepted from the coordinator, in bytes
+                        (default 1073741824)
+  --media SPEC          medium discs are exchanged on: optical:DEVICE, usb:MOUNT,
+                        qr:DIR or dir:DIR (default optical:/dev/sr0)
+  --directory PATH      where discs are staged (default /)
+  --threads N           threads used to compute the stages (default 8)";
 
-        let mut tmp = ConnectionHandler {
-            peerid: peerid,
-            s: TcpStream::connect(COORDINATOR_ADDR).unwrap(),
-            msgid: 0
-        };
-
-        tmp.handshake().expect("could not handshake with coordinator");
+fn main() {
+    let options = match Config::from_args(
+        std::env::args().skip(1),
+        &["help"],
+        &["coordinator", "coordinator-key", "key", "timeout", "max-message-size", "media", "directory", "threads"]
+    ) {
+        Ok(options) => options,
+        Err(e) => {
+            println!("{}\n\n{}", e, USAGE);
+            std::process::exit(1);
+        }
+    };
 
-        tmp
+    if options.flag("help") {
+        println!("{}", USAGE);
+        return;
     }
 
-    fn handshake(&mut self) -> Option<u8> {
-        if self.s.set_read_timeout(Some(Duration::from_secs(5))).is_err() {
-            return None;
+    let coordinator_addr = options.get("coordinator").unwrap_or(DEFAULT_COORDINATOR_ADDR).to_string();
+    let network_timeout = match options.get_or("timeout", DEFAULT_NETWORK_TIMEOUT) {
+        Ok(timeout) => timeout,
+        Err(e) => {
+            println!("{}\n\n{}", e, USAGE);
+            std::process::exit(1);
         }
-        if self.s.set_write_timeout(Some(Duration::from_secs(5))).is_err() {
-            return None;
-        }
-        if self.s.write_all(&NETWORK_MAGIC).is_err() {
-            return None;
-        }
-        if self.s.write_all(&self.peerid).is_err() {
-            return None;
-        }
-        if self.s.write_all(&[self.msgid]).is_err() {
-            return None;
-        }
-        if self.s.flush().is_err() {
-            return None;
-        }
-        let _ = self.s.set_read_timeout(Some(Duration::from_secs(NETWORK_TIMEOUT)));
-        let _ = self.s.set_write_timeout(Some(Duration::from_secs(NETWORK_TIMEOUT)));
+    };
 
-        let mut buf: [u8; 8] = [0; 8];
-        if self.s.read_exact(&mut buf).is_err() {
-            return None;
+    let max_message_size = match options.get_or("max-message-size", DEFAULT_MAX_MESSAGE_SIZE) {
+        Ok(size) => size,
+        Err(e) => {
+            println!("{}\n\n{}", e, USAGE);
+            std::process::exit(1);
         }
+    };
 
-        if buf != COORDINATOR_MAGIC {
-            return None;
+    let coordinator_key = match options.get("coordinator-key").map(channel::parse_key) {
+        Some(Ok(key)) => key,
+        Some(Err(e)) => {
+            println!("--coordinator-key: {}\n\n{}", e, USAGE);
+            std::process::exit(1);
+        },
+        None => {
+            println!("--coordinator-key is required\n\n{}", USAGE);
+            std::process::exit(1);
         }
+    };
 
-        let mut buf: [u8; 1] = [0];
-        match self.s.read_exact(&mut buf) {
-            Ok(_) => Some(buf[0]),
-            Err(_) => None
+    let key = match StaticKey::load_or_generate(options.get("key").unwrap_or(DEFAULT_KEY_PATH)) {
+        Ok(key) => key,
+        Err(e) => {
+            println!("{}", e);
+            std::process::exit(1);
         }
-    }
+    };
 
-    fn do_with_stream<T, E, F: Fn(&mut TcpStream, u8) -> Result<T, E>>(&mut self, cb: F) -> T
-    {
-        let mut their_msgid = 0;
-
-        loop {
-            let val = cb(&mut self.s, their_msgid);
-
-            match val {
-                Ok(s) => {
-                    return s;
-                },
-                Err(_) => {
-                    let mut failed = false;
-
-                    loop {
-                        match TcpStream::connect(COORDINATOR_ADDR) {
-                            Ok(s) => {
-                                self.s = s;
-                                match self.handshake() {
-                                    Some(id) => {
-                                        their_msgid = id;
-                                        if failed {
-                                            println!("Reconnected to coordinator.");
-                                        }
-                                        break;
-                                    },
-                                    None => {
-                                        thread::sleep(Duration::from_secs(2));
-                                    }
-                                }
-                            },
-                            Err(_) => {
-                                failed = true;
-                                println!("Failed to connect to coordinator, trying again...");
-                                thread::sleep(Duration::from_secs(2));
-                            }
-                        }
-                    }
-                }
-            }
+    let media = match media::open(options.get("media").unwrap_or(media::DEFAULT_MEDIA)) {
+        Ok(media) => media,
+        Err(e) => {
+            println!("--media: {}\n\n{}", e, USAGE);
+            std::process::exit(1);
         }
-    }
-
-    fn read<T: Decodable>(&mut self) -> T {
-        let msg = self.do_with_stream(|s, _| {
-            decode_from(s, Infinite)
-        });
-
-        self.msgid += 1;
-
-        let _ = self.s.write_all(&NETWORK_ACK);
-        let _ = self.s.flush();
-
-        msg
-    }
-
-    fn write<T: Encodable>(&mut self, obj: &T) {
-        self.msgid += 1;
-
-        let msgid = self.msgid;
-
-        self.do_with_stream(|s, theirid| {
-            if theirid >= msgid {
-                // They have the message we're sending already.
-                return Ok(());
-            }
-
-            if encode_into(obj, s, Infinite).is_err() {
-                // Couldn't write to the socket, this error will trigger
-                // a reconnect.
-                return Err("couldn't send data".to_string())
-            }
-
-            if s.flush().is_err() {
-                // Couldn't flush the buffer, assume the connection failed.
-                return Err("couldn't flush buffer".to_string())
-            }
-
-            // We expect an ACK now.
-            let mut ack: [u8; 4] = [0; 4];
-            let _ = s.read_exact(&mut ack);
-
-            if ack != NETWORK_ACK {
-                // Bad ACK, this error will trigger reconnect.
-                return Err("bad or no ack".to_string())
-            }
+    };
 
-            // All good.
-            Ok(())
-        })
+    match options.get_or("threads", DEFAULT_THREADS) {
+        Ok(threads) => set_threads(threads),
+        Err(e) => {
+            println!("{}\n\n{}", e, USAGE);
+            std::process::exit(1);
+        }
     }
-}
 
-fn main() {
-    prompt("Press [ENTER] when you're ready to perform diagnostics of the DVD drive.");
-    perform_diagnostics();
-    prompt("Diagnostics complete. Press [ENTER] when you're ready to begin the ceremony.");
+    let config = DiscConfig {
+        directory_prefix: DiscConfig::directory_prefix(options.get("directory").unwrap_or(DEFAULT_DIRECTORY)),
+        ask_user_to_record_hashes: ASK_USER_TO_RECORD_HASHES,
+        media: media,
+        input: Arc::new(Input::Terminal),
+        ui: Arc::new(Ui::new(true))
+    };
+
+    config.prompt("Press [ENTER] when you're ready to perform diagnostics of the DVD drive.");
+    perform_diagnostics(&config);
+    config.prompt("Diagnostics complete. Press [ENTER] when you're ready to begin the ceremony.");
+
+    let connector = match transport::connector(&coordinator_addr) {
+        Ok(connector) => connector,
+        Err(e) => {
+            println!("--coordinator: {}", e);
+            std::process::exit(1);
+        }
+    };
 
-    let mut handler = ConnectionHandler::new();
+    let mut handler = match Client::connect(connector, key, coordinator_key, network_timeout, max_message_size) {
+        Ok(handler) => handler,
+        Err(e) => panic!("{}", e)
+    };
 
     let comm;
     {
         let mut entered_wrong = false;
         loop {
-            let msg = prompt(&format!("Please enter the commitment from the compute machine. It contains a checksum,\n\
-                              so don't worry (much) about entering it in wrong. We'll let you keep trying.{}\n\n",
-                              if entered_wrong { "\n\nInvalid, try again!"} else { "" }));
+            let msg = config.prompt(&format!("Please enter the commitment from the compute machine. It contains a checksum,\n\
+                                     so don't worry (much) about entering it in wrong. We'll let you keep trying.{}\n\n",
+                                     if entered_wrong { "\n\nInvalid, try again!"} else { "" }));
 
             if let Some(c) = Digest256::from_string(&msg) {
                 comm = c;
@@ -205,101 +157,142 @@ fn main() {
         }
     }
 
-    handler.write(&comm);
+    handler.write(MessageType::Commitment, &comm);
 
-    println!("Waiting to receive disc 'A' from coordinator server...");
-    let hash_of_commitments = handler.read::<Digest512>();
-    let stage1_before = handler.read::<Stage1Contents>();
-    let prev_msg_hash = handler.read::<Digest256>();
+    // A turn is taken again if the coordinator rejects the contribution
+    // it was built upon, on discs numbered by the attempt.
+    let mut attempt = 1;
+    loop {
+        let (request, response) = (retry_disc("A", attempt), retry_disc("B", attempt));
+
+        println!("Waiting to receive disc '{}' from coordinator server...", request);
+        let hash_of_commitments = handler.read::<Digest512>(MessageType::CommitmentsHash);
+        let stage1_before = handler.read::<Stage1Contents>(MessageType::Stage1);
+        let prev_msg_hash = handler.read::<Digest256>(MessageType::PrevMessageHash);
+
+        let (pubkey, nizks, stage1_after, ihash): (PublicKey, PublicKeyNizks, Stage1Contents, Digest256) = exchange_disc(
+            &config,
+            &request,
+            &response,
+            |f| -> Result<(), bincode::rustc_serialize::EncodingError> {
+                try!(encode_into(&hash_of_commitments, f, Infinite));
+                try!(encode_into(&stage1_before, f, Infinite));
+
+                encode_into(&prev_msg_hash, f, Infinite)
+            },
+            |f, _| -> Result<(PublicKey, PublicKeyNizks, Stage1Contents, Digest256), bincode::rustc_serialize::DecodingError> {
+                let pubkey: PublicKey = try!(decode_from(f, Infinite));
+                let nizks: PublicKeyNizks = try!(decode_from(f, Infinite));
+                let stage: Stage1Contents = try!(decode_from(f, Infinite));
+                let ihash: Digest256 = try!(decode_from(f, Infinite));
+
+                Ok((pubkey, nizks, stage, ihash))
+            }
+        );
 
-    let (pubkey, nizks, stage1_after, ihash): (PublicKey, PublicKeyNizks, Stage1Contents, Digest256) = exchange_disc(
-        "A",
-        "B",
-        |f| -> Result<(), bincode::rustc_serialize::EncodingError> {
-            try!(encode_into(&hash_of_commitments, f, Infinite));
-            try!(encode_into(&stage1_before, f, Infinite));
+        println!("Sending disc '{}' to the coordinator server...", response);
+        handler.write(MessageType::PublicKey, &pubkey);
+        handler.write(MessageType::PublicKeyNizks, &nizks);
+        handler.write(MessageType::Stage1, &stage1_after);
+        handler.write(MessageType::IHash, &ihash);
 
-            encode_into(&prev_msg_hash, f, Infinite)
-        },
-        |f, _| -> Result<(PublicKey, PublicKeyNizks, Stage1Contents, Digest256), bincode::rustc_serialize::DecodingError> {
-            let pubkey: PublicKey = try!(decode_from(f, Infinite));
-            let nizks: PublicKeyNizks = try!(decode_from(f, Infinite));
-            let stage: Stage1Contents = try!(decode_from(f, Infinite));
-            let ihash: Digest256 = try!(decode_from(f, Infinite));
+        drop(stage1_before);
+        drop(stage1_after);
 
-            Ok((pubkey, nizks, stage, ihash))
+        if !handler.read::<bool>(MessageType::Retry) {
+            break;
         }
-    );
-
-    println!("Sending disc 'B' to the coordinator server...");
-    handler.write(&pubkey);
-    handler.write(&nizks);
-    handler.write(&stage1_after);
-    handler.write(&ihash);
 
-    drop(stage1_before);
-    drop(stage1_after);
+        println!("The coordinator rejected the contribution disc '{}' was built upon.", request);
+        attempt += 1;
+    }
 
-    println!("Waiting to receive disc 'C' from coordinator server...");
-    let stage2_before = handler.read::<Stage2Contents>();
-    let prev_msg_hash = handler.read::<Digest256>();
+    let mut attempt = 1;
+    loop {
+        let (request, response) = (retry_disc("C", attempt), retry_disc("D", attempt));
+
+        println!("Waiting to receive disc '{}' from coordinator server...", request);
+        let stage2_before = handler.read::<Stage2Contents>(MessageType::Stage2);
+        let prev_msg_hash = handler.read::<Digest256>(MessageType::PrevMessageHash);
+
+        let (stage2_after, ihash): (Stage2Contents, Digest256) = exchange_disc(
+            &config,
+            &request,
+            &response,
+            |f| {
+                try!(encode_into(&stage2_before, f, Infinite));
+
+                encode_into(&prev_msg_hash, f, Infinite)
+            },
+            |f, _| -> Result<(Stage2Contents, Digest256), bincode::rustc_serialize::DecodingError> {
+                let stage2_after: Stage2Contents = try!(decode_from(f, Infinite));
+                let ihash: Digest256 = try!(decode_from(f, Infinite));
+
+                Ok((stage2_after, ihash))
+            }
+        );
 
-    let (stage2_after, ihash): (Stage2Contents, Digest256) = exchange_disc(
-        "C",
-        "D",
-        |f| {
-            try!(encode_into(&stage2_before, f, Infinite));
+        println!("Sending disc '{}' to the coordinator server...", response);
+        handler.write(MessageType::Stage2, &stage2_after);
+        handler.write(MessageType::IHash, &ihash);
 
-            encode_into(&prev_msg_hash, f, Infinite)
-        },
-        |f, _| -> Result<(Stage2Contents, Digest256), bincode::rustc_serialize::DecodingError> {
-            let stage2_after: Stage2Contents = try!(decode_from(f, Infinite));
-            let ihash: Digest256 = try!(decode_from(f, Infinite));
+        drop(stage2_before);
+        drop(stage2_after);
 
-            Ok((stage2_after, ihash))
+        if !handler.read::<bool>(MessageType::Retry) {
+            break;
         }
-    );
-
-    println!("Sending disc 'D' to the coordinator server...");
-    handler.write(&stage2_after);
-    handler.write(&ihash);
 
-    drop(stage2_before);
-    drop(stage2_after);
+        println!("The coordinator rejected the contribution disc '{}' was built upon.", request);
+        attempt += 1;
+    }
 
-    println!("Waiting to receive disc 'E' from coordinator server...");
-    let stage3_before = handler.read::<Stage3Contents>();
-    let prev_msg_hash = handler.read::<Digest256>();
+    let mut attempt = 1;
+    loop {
+        let (request, response) = (retry_disc("E", attempt), retry_disc("F", attempt));
+
+        println!("Waiting to receive disc '{}' from coordinator server...", request);
+        let stage3_before = handler.read::<Stage3Contents>(MessageType::Stage3);
+        let prev_msg_hash = handler.read::<Digest256>(MessageType::PrevMessageHash);
+
+        let (stage3_after, ihash): (Stage3Contents, Digest256) = exchange_disc(
+            &config,
+            &request,
+            &response,
+            |f| {
+                try!(encode_into(&stage3_before, f, Infinite));
+
+                encode_into(&prev_msg_hash, f, Infinite)
+            },
+            |f, _| -> Result<(Stage3Contents, Digest256), bincode::rustc_serialize::DecodingError> {
+                let stage3_after: Stage3Contents = try!(decode_from(f, Infinite));
+                let ihash: Digest256 = try!(decode_from(f, Infinite));
+
+                Ok((stage3_after, ihash))
+            }
+        );
 
-    let (stage3_after, ihash): (Stage3Contents, Digest256) = exchange_disc(
-        "E",
-        "F",
-        |f| {
-            try!(encode_into(&stage3_before, f, Infinite));
+        println!("Sending disc '{}' to the coordinator server...", response);
+        handler.write(MessageType::Stage3, &stage3_after);
+        handler.write(MessageType::IHash, &ihash);
 
-            encode_into(&prev_msg_hash, f, Infinite)
-        },
-        |f, _| -> Result<(Stage3Contents, Digest256), bincode::rustc_serialize::DecodingError> {
-            let stage3_after: Stage3Contents = try!(decode_from(f, Infinite));
-            let ihash: Digest256 = try!(decode_from(f, Infinite));
+        drop(stage3_before);
+        drop(stage3_after);
 
-            Ok((stage3_after, ihash))
+        if !handler.read::<bool>(MessageType::Retry) {
+            break;
         }
-    );
-
-    println!("Sending disc 'F' to the coordinator server...");
-    handler.write(&stage3_after);
-    handler.write(&ihash);
 
-    drop(stage3_before);
-    drop(stage3_after);
+        println!("The coordinator rejected the contribution disc '{}' was built upon.", request);
+        attempt += 1;
+    }
 
-    eject();
+    config.media.eject();
 
     loop {
-        prompt("Done! Both machines can be shut down.\n\
-                Do not destroy any DVDs, and ensure there are no DVDs still\n\
-                inside of either machine. Place them all in a safe and secure\n\
-                place.");
+        config.prompt("Done! Both machines can be shut down.\n\
+                       Do not destroy any DVDs, and ensure there are no DVDs still\n\
+                       inside of either machine. Place them all in a safe and secure\n\
+                       place.");
     }
 }
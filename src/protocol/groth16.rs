@@ -0,0 +1,604 @@
+//! Parameters for Groth16 zk-SNARKs, generated by a ceremony of the
+//! same shape as the one for PGHR13:
+//!
+//! 1. *Stage 1: Powers of Tau* - As for PGHR13, except that it starts
+//!    from `Stage1Contents::new_groth16`, which has the powers of tau up
+//!    to `tau^(2d-2)` that the query for `h` needs.
+//! 2. *Stage 2: Random coefficients alpha and beta* - The QAP is
+//!    evaluated at tau, and the players multiply `A_i(tau)` by beta and
+//!    `B_i(tau)` by alpha in turn, in `Stage2Contents`.
+//! 3. *Stage 3: Random coefficient delta* - The coordinator adds
+//!    `C_i(tau)` to the result, and the players divide the query for
+//!    the auxiliary variables and the query for `h` by delta in turn, in
+//!    `Stage3Contents`.
+//!
+//! Gamma is one, so the part of the verifying key for the inputs needs
+//! no contributions of its own. `keypair` assembles the proving and
+//! verifying keys from the final contents of each stage.
+//!
+//! The first two stages can instead be taken from a universal
+//! `powersoftau::Accumulator` of a large enough degree, with
+//! `Stage2Contents::from_accumulator`, leaving only stage 3 to be run
+//! for each circuit.
+
+use bn::*;
+use rand::Rng;
+use std::fs::File;
+use rustc_serialize::Encodable;
+use bincode::SizeLimit::Infinite;
+use bincode::rustc_serialize::encode_into;
+
+#[cfg(feature = "snark")]
+use snark::CS;
+
+use super::{Stage1Contents, Observer, quiet};
+#[cfg(feature = "snark")]
+use super::powersoftau::Accumulator;
+use super::spair::{Spair, same_power, checkvec};
+use super::nizk::Nizk;
+use super::digest::{Digest256, Digest512};
+use super::multicore::mul_all_by;
+#[cfg(feature = "snark")]
+use super::qap::evaluate_groth16;
+
+/// The secrets sampled by a player of a Groth16 ceremony.
+pub struct PrivateKey {
+    pub tau: Fr,
+    pub alpha: Fr,
+    pub beta: Fr,
+    pub delta: Fr
+}
+
+impl PrivateKey {
+    pub fn new<R: Rng>(rng: &mut R) -> PrivateKey {
+        PrivateKey {
+            tau: Fr::random(rng),
+            alpha: Fr::random(rng),
+            beta: Fr::random(rng),
+            delta: Fr::random(rng)
+        }
+    }
+
+    /// Construct a "blank" private key for accumulating
+    /// in tests.
+    #[cfg(feature = "snark")]
+    pub fn new_blank() -> PrivateKey {
+        PrivateKey {
+            tau: Fr::one(),
+            alpha: Fr::one(),
+            beta: Fr::one(),
+            delta: Fr::one()
+        }
+    }
+
+    #[cfg(feature = "snark")]
+    pub fn multiply(&mut self, other: &Self) {
+        self.tau = self.tau * other.tau;
+        self.alpha = self.alpha * other.alpha;
+        self.beta = self.beta * other.beta;
+        self.delta = self.delta * other.delta;
+    }
+
+    /// Generates the keypair directly from the secrets, as a single
+    /// trusted party would, to compare the result of a ceremony with.
+    #[cfg(feature = "snark")]
+    pub fn keypair(&self, cs: &CS) -> Keypair {
+        let mut powers = Vec::with_capacity(2 * cs.d - 1);
+        let mut c = Fr::one();
+        for _ in 0..(2 * cs.d - 1) {
+            powers.push(c);
+            c = c * self.tau;
+        }
+
+        let g1_powers: Vec<G1> = powers.iter().map(|&p| G1::one() * p).collect();
+        let g2_powers: Vec<G2> = powers.iter().map(|&p| G2::one() * p).collect();
+        let (at, bt1, bt2, ct) = evaluate_groth16(&g1_powers, &g2_powers, cs);
+
+        let delta_inverse = self.delta.inverse().unwrap();
+        let combined: Vec<G1> = (0..cs.num_vars).map(|i| {
+            at[i] * self.beta + bt1[i] * self.alpha + ct[i]
+        }).collect();
+
+        Keypair {
+            pk: ProvingKey {
+                alpha_g1: G1::one() * self.alpha,
+                beta_g1: G1::one() * self.beta,
+                beta_g2: G2::one() * self.beta,
+                delta_g1: G1::one() * self.delta,
+                delta_g2: G2::one() * self.delta,
+                a: at,
+                b_g1: bt1,
+                b_g2: bt2,
+                h: (0..cs.d - 1).map(|i| (g1_powers[i + cs.d] - g1_powers[i]) * delta_inverse).collect(),
+                l: combined[cs.num_inputs + 1..].iter().map(|&g| g * delta_inverse).collect()
+            },
+            vk: VerifyingKey {
+                alpha_g1: G1::one() * self.alpha,
+                beta_g2: G2::one() * self.beta,
+                gamma_g2: G2::one(),
+                delta_g2: G2::one() * self.delta,
+                ic: combined[0..cs.num_inputs + 1].to_vec()
+            }
+        }
+    }
+
+    /// Construct the "public key" used to verify that the player
+    /// is performing their transformations correctly.
+    pub fn pubkey<R: Rng>(&self, rng: &mut R) -> PublicKey {
+        PublicKey {
+            tau: Spair::random(rng, self.tau).unwrap(),
+            alpha: Spair::random(rng, self.alpha).unwrap(),
+            beta: Spair::random(rng, self.beta).unwrap(),
+            delta: Spair::random(rng, self.delta).unwrap()
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, RustcEncodable, RustcDecodable)]
+pub struct PublicKey {
+    tau: Spair<G2>, // (f1, f1 * tau)
+    alpha: Spair<G2>, // (f2, f2 * alpha)
+    beta: Spair<G2>, // (f3, f3 * beta)
+    delta: Spair<G2> // (f4, f4 * delta)
+}
+
+impl PublicKey {
+    pub fn hash(&self) -> Digest256 {
+        Digest256::from(self).expect("PublicKey should never fail to encode")
+    }
+
+    pub fn nizks<R: Rng>(&self, rng: &mut R, privkey: &PrivateKey, extra: &Digest512) -> PublicKeyNizks {
+        PublicKeyNizks {
+            tau: self.tau.nizk(rng, privkey.tau, extra),
+            alpha: self.alpha.nizk(rng, privkey.alpha, extra),
+            beta: self.beta.nizk(rng, privkey.beta, extra),
+            delta: self.delta.nizk(rng, privkey.delta, extra)
+        }
+    }
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct PublicKeyNizks {
+    tau: Nizk<G2>,
+    alpha: Nizk<G2>,
+    beta: Nizk<G2>,
+    delta: Nizk<G2>
+}
+
+impl PublicKeyNizks {
+    pub fn is_valid(&self, pubkey: &PublicKey, extra: &Digest512) -> bool {
+        pubkey.tau.verify_nizk(&self.tau, extra) &&
+        pubkey.alpha.verify_nizk(&self.alpha, extra) &&
+        pubkey.beta.verify_nizk(&self.beta, extra) &&
+        pubkey.delta.verify_nizk(&self.delta, extra)
+    }
+}
+
+impl Stage1Contents {
+    /// The powers of tau for the Groth16 parameters of `cs`.
+    #[cfg(feature = "snark")]
+    pub fn new_groth16(cs: &CS) -> Self {
+        Stage1Contents::with_powers(2 * cs.d - 1)
+    }
+
+    /// Like `transform_observed`, with the secrets of a Groth16 player.
+    pub fn transform_groth16(&mut self, s: &PrivateKey, observer: &Observer) {
+        self.multiply_by_tau(s.tau, observer)
+    }
+
+    /// Like `verify_transform_observed`, for the contribution of a
+    /// Groth16 player.
+    pub fn verify_transform_groth16(&self, prev: &Self, p: &PublicKey, observer: &Observer) -> bool {
+        self.verify_tau(prev, &p.tau, observer)
+    }
+}
+
+/// Random coefficients alpha and beta.
+#[derive(Clone, RustcEncodable, RustcDecodable)]
+pub struct Stage2Contents {
+    alpha_g1: G1,
+    beta_g1: G1,
+    beta_g2: G2,
+    beta_a: Vec<G1>,
+    alpha_b: Vec<G1>
+}
+
+impl Stage2Contents {
+    #[cfg(feature = "snark")]
+    pub fn new(cs: &CS, stage1: &Stage1Contents) -> Self {
+        let (at, bt1, _, _) = evaluate(cs, stage1);
+
+        Stage2Contents {
+            alpha_g1: G1::one(),
+            beta_g1: G1::one(),
+            beta_g2: G2::one(),
+            beta_a: at,
+            alpha_b: bt1
+        }
+    }
+
+    /// The final contents of stage 2, from a universal accumulator which
+    /// already holds the powers of tau multiplied by alpha and beta. The
+    /// powers of tau to go with it are `acc.powers()`. Fails if the
+    /// degree of `cs` is larger than that of the accumulator.
+    #[cfg(feature = "snark")]
+    pub fn from_accumulator(cs: &CS, acc: &Accumulator) -> Result<Self, String> {
+        if cs.d > acc.degree() {
+            return Err(format!("the circuit has degree {}, more than the accumulator's {}",
+                               cs.d, acc.degree()));
+        }
+
+        // The lagrange coefficients are linear in the powers, so
+        // evaluating the QAP with alpha tau^i and beta tau^i gives
+        // alpha B_i(tau) and beta A_i(tau).
+        let (beta_a, _, _, _) = evaluate_groth16(acc.beta_tau_g1(), &acc.powers().v2, cs);
+        let (_, alpha_b, _, _) = evaluate_groth16(acc.alpha_tau_g1(), &acc.powers().v2, cs);
+
+        Ok(Stage2Contents {
+            alpha_g1: acc.alpha_tau_g1()[0],
+            beta_g1: acc.beta_tau_g1()[0],
+            beta_g2: acc.beta_g2(),
+            beta_a: beta_a,
+            alpha_b: alpha_b
+        })
+    }
+
+    /// The number of group elements `transform` works through.
+    pub fn size(&self) -> usize {
+        self.beta_a.len() + self.alpha_b.len()
+    }
+
+    pub fn transform(&mut self, s: &PrivateKey) {
+        self.transform_observed(s, &quiet)
+    }
+
+    /// Like `transform`, reporting the progress through each vector to
+    /// `observer`.
+    pub fn transform_observed(&mut self, s: &PrivateKey, observer: &Observer) {
+        self.alpha_g1 = self.alpha_g1 * s.alpha;
+        self.beta_g1 = self.beta_g1 * s.beta;
+        self.beta_g2 = self.beta_g2 * s.beta;
+        mul_all_by(&mut self.beta_a, s.beta, observer, "beta_a");
+        mul_all_by(&mut self.alpha_b, s.alpha, observer, "alpha_b");
+    }
+
+    pub fn is_well_formed(&self, prev: &Self) -> bool {
+        !prev.alpha_g1.is_zero() &&
+        !prev.beta_g1.is_zero() &&
+        !prev.beta_g2.is_zero() &&
+        !self.alpha_g1.is_zero() &&
+        !self.beta_g1.is_zero() &&
+        !self.beta_g2.is_zero() &&
+        self.beta_a.len() == prev.beta_a.len() &&
+        self.alpha_b.len() == prev.alpha_b.len()
+    }
+
+    pub fn verify_transform(&self, prev: &Self, p: &PublicKey) -> bool {
+        self.verify_transform_observed(prev, p, &quiet)
+    }
+
+    /// Like `verify_transform`, reporting the progress through each
+    /// vector to `observer`.
+    pub fn verify_transform_observed(&self, prev: &Self, p: &PublicKey, observer: &Observer) -> bool {
+        self.is_well_formed(prev) &&
+        same_power(
+            &Spair::new(prev.alpha_g1, self.alpha_g1).unwrap(),
+            &p.alpha
+        ) &&
+        same_power(
+            &Spair::new(prev.beta_g1, self.beta_g1).unwrap(),
+            &p.beta
+        ) &&
+        same_power(
+            &Spair::new(prev.beta_g1, self.beta_g1).unwrap(),
+            &Spair::new(prev.beta_g2, self.beta_g2).unwrap()
+        ) &&
+        checkvec(
+            &prev.beta_a,
+            &self.beta_a,
+            &p.beta,
+            observer,
+            "beta_a"
+        ) &&
+        checkvec(
+            &prev.alpha_b,
+            &self.alpha_b,
+            &p.alpha,
+            observer,
+            "alpha_b"
+        )
+    }
+}
+
+/// Random coefficient delta.
+#[derive(Clone, RustcEncodable, RustcDecodable)]
+pub struct Stage3Contents {
+    delta_g1: G1,
+    delta_g2: G2,
+    l: Vec<G1>,
+    h: Vec<G1>
+}
+
+impl Stage3Contents {
+    #[cfg(feature = "snark")]
+    pub fn new(cs: &CS, stage1: &Stage1Contents, stage2: &Stage2Contents) -> Self {
+        assert_eq!(stage2.beta_a.len(), cs.num_vars);
+        assert_eq!(stage2.alpha_b.len(), cs.num_vars);
+
+        let (_, _, _, ct) = evaluate(cs, stage1);
+
+        Stage3Contents {
+            delta_g1: G1::one(),
+            delta_g2: G2::one(),
+            l: combine(stage2, &ct, cs.num_inputs + 1, cs.num_vars),
+            // tau^i Z(tau), where Z(tau) = tau^d - 1
+            h: (0..cs.d - 1).map(|i| stage1.v1[i + cs.d] - stage1.v1[i]).collect()
+        }
+    }
+
+    /// The number of group elements `transform` works through.
+    pub fn size(&self) -> usize {
+        self.l.len() + self.h.len()
+    }
+
+    pub fn transform(&mut self, s: &PrivateKey) {
+        self.transform_observed(s, &quiet)
+    }
+
+    /// Like `transform`, reporting the progress through each vector to
+    /// `observer`.
+    pub fn transform_observed(&mut self, s: &PrivateKey, observer: &Observer) {
+        let delta_inverse = s.delta.inverse().expect("delta should never be zero");
+
+        self.delta_g1 = self.delta_g1 * s.delta;
+        self.delta_g2 = self.delta_g2 * s.delta;
+        mul_all_by(&mut self.l, delta_inverse, observer, "l");
+        mul_all_by(&mut self.h, delta_inverse, observer, "h");
+    }
+
+    pub fn is_well_formed(&self, prev: &Self) -> bool {
+        !prev.delta_g1.is_zero() &&
+        !prev.delta_g2.is_zero() &&
+        !self.delta_g1.is_zero() &&
+        !self.delta_g2.is_zero() &&
+        self.l.len() == prev.l.len() &&
+        self.h.len() == prev.h.len()
+    }
+
+    pub fn verify_transform(&self, prev: &Self, p: &PublicKey) -> bool {
+        self.verify_transform_observed(prev, p, &quiet)
+    }
+
+    /// Like `verify_transform`, reporting the progress through each
+    /// vector to `observer`.
+    pub fn verify_transform_observed(&self, prev: &Self, p: &PublicKey, observer: &Observer) -> bool {
+        self.is_well_formed(prev) &&
+        same_power(
+            &Spair::new(prev.delta_g1, self.delta_g1).unwrap(),
+            &p.delta
+        ) &&
+        same_power(
+            &Spair::new(prev.delta_g1, self.delta_g1).unwrap(),
+            &Spair::new(prev.delta_g2, self.delta_g2).unwrap()
+        ) &&
+        // The queries were divided by delta, so the previous ones are
+        // the new ones multiplied by it.
+        checkvec(
+            &self.l,
+            &prev.l,
+            &p.delta,
+            observer,
+            "l"
+        ) &&
+        checkvec(
+            &self.h,
+            &prev.h,
+            &p.delta,
+            observer,
+            "h"
+        )
+    }
+}
+
+#[derive(Clone, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct ProvingKey {
+    pub alpha_g1: G1,
+    pub beta_g1: G1,
+    pub beta_g2: G2,
+    pub delta_g1: G1,
+    pub delta_g2: G2,
+    /// A_i(tau), for every variable.
+    pub a: Vec<G1>,
+    /// B_i(tau), for every variable.
+    pub b_g1: Vec<G1>,
+    pub b_g2: Vec<G2>,
+    /// tau^i Z(tau) / delta, for i from 0 to d-2.
+    pub h: Vec<G1>,
+    /// (beta A_i(tau) + alpha B_i(tau) + C_i(tau)) / delta, for the
+    /// auxiliary variables.
+    pub l: Vec<G1>
+}
+
+#[derive(Clone, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct VerifyingKey {
+    pub alpha_g1: G1,
+    pub beta_g2: G2,
+    pub gamma_g2: G2,
+    pub delta_g2: G2,
+    /// (beta A_i(tau) + alpha B_i(tau) + C_i(tau)) / gamma, for the
+    /// constant one and the inputs.
+    pub ic: Vec<G1>
+}
+
+#[derive(Clone, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct Keypair {
+    pub pk: ProvingKey,
+    pub vk: VerifyingKey
+}
+
+impl Keypair {
+    /// Writes the proving and verifying keys, encoded with bincode, to
+    /// `pk_path` and `vk_path`.
+    pub fn write_to_disk(&self, pk_path: &str, vk_path: &str) -> Result<(), String> {
+        try!(write(pk_path, &self.pk));
+
+        write(vk_path, &self.vk)
+    }
+}
+
+fn write<T: Encodable>(path: &str, obj: &T) -> Result<(), String> {
+    let mut f = try!(File::create(path).map_err(|e| format!("could not create {}: {}", path, e)));
+
+    encode_into(obj, &mut f, Infinite).map_err(|e| format!("could not write {}: {}", path, e))
+}
+
+/// Assembles the Groth16 keypair from the final contents of each stage.
+#[cfg(feature = "snark")]
+pub fn keypair(
+    cs: &CS,
+    stage1: &Stage1Contents,
+    stage2: &Stage2Contents,
+    stage3: &Stage3Contents
+) -> Keypair {
+    let (at, bt1, bt2, ct) = evaluate(cs, stage1);
+
+    Keypair {
+        pk: ProvingKey {
+            alpha_g1: stage2.alpha_g1,
+            beta_g1: stage2.beta_g1,
+            beta_g2: stage2.beta_g2,
+            delta_g1: stage3.delta_g1,
+            delta_g2: stage3.delta_g2,
+            a: at,
+            b_g1: bt1,
+            b_g2: bt2,
+            h: stage3.h.clone(),
+            l: stage3.l.clone()
+        },
+        vk: VerifyingKey {
+            alpha_g1: stage2.alpha_g1,
+            beta_g2: stage2.beta_g2,
+            gamma_g2: G2::one(),
+            delta_g2: stage3.delta_g2,
+            ic: combine(stage2, &ct, 0, cs.num_inputs + 1)
+        }
+    }
+}
+
+/// Evaluates the QAP at tau, from the final powers of tau. There may
+/// be more of them than the `2d-1` needed, as in a universal accumulator.
+#[cfg(feature = "snark")]
+fn evaluate(cs: &CS, stage1: &Stage1Contents) -> (Vec<G1>, Vec<G1>, Vec<G2>, Vec<G1>) {
+    assert!(stage1.v1.len() >= 2 * cs.d - 1);
+
+    evaluate_groth16(&stage1.v1, &stage1.v2, cs)
+}
+
+/// beta A_i(tau) + alpha B_i(tau) + C_i(tau), for the variables from
+/// `start` up to `end`.
+#[cfg(feature = "snark")]
+fn combine(stage2: &Stage2Contents, ct: &[G1], start: usize, end: usize) -> Vec<G1> {
+    (start..end).map(|i| stage2.beta_a[i] + stage2.alpha_b[i] + ct[i]).collect()
+}
+
+#[test]
+fn compare_to_direct_generation() {
+    let rng = &mut ::rand::thread_rng();
+
+    let privkeys: Vec<_> = (0..3).map(|_| PrivateKey::new(rng)).collect();
+    let pubkeys: Vec<_> = privkeys.iter().map(|p| p.pubkey(rng)).collect();
+
+    let extra = Digest512::from(&"test").unwrap();
+    let nizks = pubkeys[0].nizks(rng, &privkeys[0], &extra);
+    assert!(nizks.is_valid(&pubkeys[0], &extra));
+    assert!(!nizks.is_valid(&pubkeys[1], &extra));
+
+    let cs = CS::dummy();
+
+    // Stage 1
+    let mut stage1 = Stage1Contents::new_groth16(&cs);
+    for (private, public) in privkeys.iter().zip(pubkeys.iter()) {
+        let prev = stage1.clone();
+        stage1.transform_groth16(private, &quiet);
+        assert!(stage1.verify_transform_groth16(&prev, public, &quiet));
+    }
+
+    // Stage 2
+    let mut stage2 = Stage2Contents::new(&cs, &stage1);
+    for (private, public) in privkeys.iter().zip(pubkeys.iter()) {
+        let prev = stage2.clone();
+        stage2.transform(private);
+        assert!(stage2.verify_transform(&prev, public));
+    }
+
+    // Stage 3
+    let mut stage3 = Stage3Contents::new(&cs, &stage1, &stage2);
+    for (private, public) in privkeys.iter().zip(pubkeys.iter()) {
+        let prev = stage3.clone();
+        stage3.transform(private);
+        assert!(stage3.verify_transform(&prev, public));
+    }
+
+    {
+        // A contribution made with other secrets than the public key's
+        let prev = stage3.clone();
+        let mut wrong = stage3.clone();
+        wrong.transform(&privkeys[0]);
+        assert!(!wrong.verify_transform(&prev, &pubkeys[1]));
+    }
+
+    let kp = keypair(&cs, &stage1, &stage2, &stage3);
+
+    let mut acc = PrivateKey::new_blank();
+    for private in privkeys.iter() {
+        acc.multiply(private);
+    }
+
+    assert!(kp == acc.keypair(&cs));
+}
+
+#[test]
+fn specialise_accumulator() {
+    use super::powersoftau;
+
+    let rng = &mut ::rand::thread_rng();
+
+    let cs = CS::dummy();
+    let mut k = 0;
+    while (1 << k) < cs.d {
+        k += 1;
+    }
+
+    // The universal ceremony, run by some players...
+    let universal: Vec<_> = (0..2).map(|_| powersoftau::PrivateKey::new(rng)).collect();
+    let mut acc = powersoftau::Accumulator::new(k);
+    for private in universal.iter() {
+        let prev = acc.clone();
+        acc.transform(private);
+        assert!(acc.verify_transform(&prev, &private.pubkey(rng)));
+    }
+
+    // ...and stage 3 for this circuit, run by others.
+    let privkeys: Vec<_> = (0..3).map(|_| PrivateKey::new(rng)).collect();
+    let stage2 = Stage2Contents::from_accumulator(&cs, &acc).unwrap();
+    let mut stage3 = Stage3Contents::new(&cs, acc.powers(), &stage2);
+    for private in privkeys.iter() {
+        let prev = stage3.clone();
+        stage3.transform(private);
+        assert!(stage3.verify_transform(&prev, &private.pubkey(rng)));
+    }
+
+    let kp = keypair(&cs, acc.powers(), &stage2, &stage3);
+
+    let mut secrets = PrivateKey::new_blank();
+    for private in universal.iter() {
+        secrets.tau = secrets.tau * private.tau;
+        secrets.alpha = secrets.alpha * private.alpha;
+        secrets.beta = secrets.beta * private.beta;
+    }
+    for private in privkeys.iter() {
+        secrets.delta = secrets.delta * private.delta;
+    }
+
+    assert!(kp == secrets.keypair(&cs));
+}
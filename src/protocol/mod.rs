@@ -28,6 +28,15 @@
 //!    by the coordinator, and the protocol proceeds as in the previous
 //!    two steps, except with `Stage2Contents` instead.
 //! 6. The coordinator writes the transcript to disk.
+//!
+//! These are parameters for PGHR13, as used by Sprout. The `groth16`
+//! module generates parameters for Groth16 with a ceremony of the same
+//! shape.
+//!
+//! The `powersoftau` module has a universal ceremony for the powers of
+//! tau, which does not depend on a constraint system and can stand in
+//! for stage 1 of any circuit up to its degree, with
+//! `Stage1Contents::truncate`, or for the first two Groth16 stages.
 
 use bn::*;
 
@@ -44,9 +53,12 @@ pub use self::secrets::*;
 pub use self::digest::*;
 use self::spair::*;
 use self::multicore::*;
+pub use self::multicore::{set_threads, threads, Observer, Meter, quiet};
 
 #[cfg(feature = "snark")]
 mod qap;
+pub mod groth16;
+pub mod powersoftau;
 
 /// The powers of tau.
 #[derive(Clone, RustcEncodable, RustcDecodable)]
@@ -58,24 +70,45 @@ pub struct Stage1Contents {
 impl Stage1Contents {
     #[cfg(feature = "snark")]
     pub fn new(cs: &CS) -> Self {
+        Stage1Contents::with_powers(cs.d+1)
+    }
+
+    /// The powers of tau from the 0th to the `n-1`th, before any player
+    /// has contributed.
+    fn with_powers(n: usize) -> Self {
         Stage1Contents {
-            v1: (0..cs.d+1).map(|_| G1::one()).collect(),
-            v2: (0..cs.d+1).map(|_| G2::one()).collect()
+            v1: (0..n).map(|_| G1::one()).collect(),
+            v2: (0..n).map(|_| G2::one()).collect()
         }
     }
 
+    /// The number of group elements `transform` works through.
+    pub fn size(&self) -> usize {
+        self.v1.len() + self.v2.len()
+    }
+
     pub fn transform(&mut self, s: &PrivateKey) {
+        self.transform_observed(s, &quiet)
+    }
+
+    /// Like `transform`, reporting the progress through each vector to
+    /// `observer`.
+    pub fn transform_observed(&mut self, s: &PrivateKey, observer: &Observer) {
+        self.multiply_by_tau(s.tau, observer)
+    }
+
+    fn multiply_by_tau(&mut self, tau: Fr, observer: &Observer) {
         parallel_two(&mut self.v1, &mut self.v2, |start, v1, v2| {
-            let mut c = s.tau.pow(Fr::from_str(&format!("{}", start)).unwrap());
+            let mut c = tau.pow(Fr::from_str(&format!("{}", start)).unwrap());
 
             for (g1, g2) in v1.iter_mut().zip(v2.iter_mut()) {
                 *g1 = *g1 * c;
                 g1.normalize();
                 *g2 = *g2 * c;
                 g2.normalize();
-                c = c * s.tau;
+                c = c * tau;
             }
-        }, ::THREADS);
+        }, threads(), observer, "v1, v2");
     }
 
     pub fn is_well_formed(&self, prev: &Self) -> bool {
@@ -92,13 +125,54 @@ impl Stage1Contents {
     }
 
     pub fn verify_transform(&self, prev: &Self, p: &PublicKey) -> bool {
+        self.verify_transform_observed(prev, p, &quiet)
+    }
+
+    /// Like `verify_transform`, reporting the progress through each
+    /// vector to `observer`.
+    pub fn verify_transform_observed(&self, prev: &Self, p: &PublicKey, observer: &Observer) -> bool {
+        self.verify_tau(prev, &p.tau_g2(), observer)
+    }
+
+    /// Checks that the powers are those of `prev` multiplied by the
+    /// powers of the secret in `tau`.
+    fn verify_tau(&self, prev: &Self, tau: &Spair<G2>, observer: &Observer) -> bool {
         self.is_well_formed(prev) &&
         same_power(
             &Spair::new(prev.v1[1], self.v1[1]).unwrap(),
-            &p.tau_g2()
+            tau
         ) &&
-        checkseq(&self.v1, &Spair::new(self.v2[0], self.v2[1]).unwrap()) &&
-        checkseq(&self.v2, &Spair::new(self.v1[0], self.v1[1]).unwrap())
+        checkseq(&self.v1, &Spair::new(self.v2[0], self.v2[1]).unwrap(), observer, "v1") &&
+        checkseq(&self.v2, &Spair::new(self.v1[0], self.v1[1]).unwrap(), observer, "v2")
+    }
+
+    /// The first `cs.d+1` powers of tau, which are all `cs` needs, from
+    /// the powers of a larger ceremony such as a universal
+    /// `powersoftau::Accumulator`. The result can be passed to
+    /// `Stage2Contents::new` as the final contents of stage 1. Fails if
+    /// there are fewer powers than `cs` needs.
+    #[cfg(feature = "snark")]
+    pub fn truncate(&self, cs: &CS) -> Result<Self, String> {
+        if cs.d + 1 > self.v1.len() || cs.d + 1 > self.v2.len() {
+            return Err(format!("the circuit needs {} powers of tau, but there are only {}",
+                               cs.d + 1, ::std::cmp::min(self.v1.len(), self.v2.len())));
+        }
+
+        Ok(Stage1Contents {
+            v1: self.v1[0..cs.d+1].to_vec(),
+            v2: self.v2[0..cs.d+1].to_vec()
+        })
+    }
+
+    /// Checks that these powers are the first of those in `full`, so
+    /// that they are sound if the transcript of `full` was verified.
+    pub fn is_truncation_of(&self, full: &Self) -> bool {
+        self.v1.len() == self.v2.len() &&
+        self.v1.len() >= 2 &&
+        self.v1.len() <= full.v1.len() &&
+        self.v2.len() <= full.v2.len() &&
+        self.v1[..] == full.v1[0..self.v1.len()] &&
+        self.v2[..] == full.v2[0..self.v2.len()]
     }
 }
 
@@ -139,18 +213,30 @@ impl Stage2Contents {
         }
     }
 
+    /// The number of group elements `transform` works through.
+    pub fn size(&self) -> usize {
+        self.pk_a.len() + self.pk_a_prime.len() + self.pk_b.len() + self.pk_b_temp.len() +
+        self.pk_b_prime.len() + self.pk_c.len() + self.pk_c_prime.len()
+    }
+
     pub fn transform(&mut self, s: &PrivateKey) {
+        self.transform_observed(s, &quiet)
+    }
+
+    /// Like `transform`, reporting the progress through each vector to
+    /// `observer`.
+    pub fn transform_observed(&mut self, s: &PrivateKey, observer: &Observer) {
         self.vk_a = self.vk_a * s.alpha_a;
         self.vk_b = self.vk_b * s.alpha_b;
         self.vk_c = self.vk_c * s.alpha_c;
         self.vk_z = self.vk_z * (s.rho_a * s.rho_b);
-        mul_all_by(&mut self.pk_a, s.rho_a);
-        mul_all_by(&mut self.pk_a_prime, s.rho_a * s.alpha_a);
-        mul_all_by(&mut self.pk_b, s.rho_b);
-        mul_all_by(&mut self.pk_b_temp, s.rho_b);
-        mul_all_by(&mut self.pk_b_prime, s.rho_b * s.alpha_b);
-        mul_all_by(&mut self.pk_c, s.rho_a * s.rho_b);
-        mul_all_by(&mut self.pk_c_prime, s.rho_a * s.rho_b * s.alpha_c);
+        mul_all_by(&mut self.pk_a, s.rho_a, observer, "pk_a");
+        mul_all_by(&mut self.pk_a_prime, s.rho_a * s.alpha_a, observer, "pk_a_prime");
+        mul_all_by(&mut self.pk_b, s.rho_b, observer, "pk_b");
+        mul_all_by(&mut self.pk_b_temp, s.rho_b, observer, "pk_b_temp");
+        mul_all_by(&mut self.pk_b_prime, s.rho_b * s.alpha_b, observer, "pk_b_prime");
+        mul_all_by(&mut self.pk_c, s.rho_a * s.rho_b, observer, "pk_c");
+        mul_all_by(&mut self.pk_c_prime, s.rho_a * s.rho_b * s.alpha_c, observer, "pk_c_prime");
     }
 
     pub fn is_well_formed(&self, prev: &Self) -> bool {
@@ -173,6 +259,12 @@ impl Stage2Contents {
     }
 
     pub fn verify_transform(&self, prev: &Self, p: &PublicKey) -> bool {
+        self.verify_transform_observed(prev, p, &quiet)
+    }
+
+    /// Like `verify_transform`, reporting the progress through each
+    /// vector to `observer`.
+    pub fn verify_transform_observed(&self, prev: &Self, p: &PublicKey, observer: &Observer) -> bool {
         self.is_well_formed(prev) &&
         // Check parts of the verification key
         same_power(
@@ -195,37 +287,51 @@ impl Stage2Contents {
         checkvec(
             &prev.pk_a,
             &self.pk_a,
-            &p.rho_a_g2()
+            &p.rho_a_g2(),
+            observer,
+            "pk_a"
         ) &&
         checkvec(
             &prev.pk_a_prime,
             &self.pk_a_prime,
-            &p.alpha_a_rho_a_g2()
+            &p.alpha_a_rho_a_g2(),
+            observer,
+            "pk_a_prime"
         ) &&
         checkvec(
             &prev.pk_b,
             &self.pk_b,
-            &p.rho_b_g1()
+            &p.rho_b_g1(),
+            observer,
+            "pk_b"
         ) &&
         checkvec(
             &prev.pk_b_temp,
             &self.pk_b_temp,
-            &p.rho_b_g2()
+            &p.rho_b_g2(),
+            observer,
+            "pk_b_temp"
         ) &&
         checkvec(
             &prev.pk_b_prime,
             &self.pk_b_prime,
-            &p.alpha_b_rho_b_g2()
+            &p.alpha_b_rho_b_g2(),
+            observer,
+            "pk_b_prime"
         ) &&
         checkvec(
             &prev.pk_c,
             &self.pk_c,
-            &p.rho_a_rho_b_g2()
+            &p.rho_a_rho_b_g2(),
+            observer,
+            "pk_c"
         ) &&
         checkvec(
             &prev.pk_c_prime,
             &self.pk_c_prime,
-            &p.alpha_c_rho_a_rho_b_g2()
+            &p.alpha_c_rho_a_rho_b_g2(),
+            observer,
+            "pk_c_prime"
         )
     }
 }
@@ -265,12 +371,23 @@ impl Stage3Contents {
         }
     }
 
+    /// The number of group elements `transform` works through.
+    pub fn size(&self) -> usize {
+        self.pk_k.len()
+    }
+
     pub fn transform(&mut self, s: &PrivateKey) {
+        self.transform_observed(s, &quiet)
+    }
+
+    /// Like `transform`, reporting the progress through each vector to
+    /// `observer`.
+    pub fn transform_observed(&mut self, s: &PrivateKey, observer: &Observer) {
         let betagamma = s.beta * s.gamma;
         self.vk_gamma = self.vk_gamma * s.gamma;
         self.vk_beta_gamma_one = self.vk_beta_gamma_one * betagamma;
         self.vk_beta_gamma_two = self.vk_beta_gamma_two * betagamma;
-        mul_all_by(&mut self.pk_k, s.beta);
+        mul_all_by(&mut self.pk_k, s.beta, observer, "pk_k");
     }
 
     pub fn is_well_formed(&self, prev: &Self) -> bool {
@@ -284,6 +401,12 @@ impl Stage3Contents {
     }
 
     pub fn verify_transform(&self, prev: &Self, p: &PublicKey) -> bool {
+        self.verify_transform_observed(prev, p, &quiet)
+    }
+
+    /// Like `verify_transform`, reporting the progress through each
+    /// vector to `observer`.
+    pub fn verify_transform_observed(&self, prev: &Self, p: &PublicKey, observer: &Observer) -> bool {
         self.is_well_formed(prev) &&
         same_power(
             &Spair::new(prev.vk_gamma, self.vk_gamma).unwrap(),
@@ -300,7 +423,9 @@ impl Stage3Contents {
         checkvec(
             &prev.pk_k,
             &self.pk_k,
-            &p.beta_g2()
+            &p.beta_g2(),
+            observer,
+            "pk_k"
         )
     }
 }
@@ -377,3 +502,38 @@ fn compare_to_libsnark_generate() {
 
     assert!(kp == acc.libsnark_keypair(&cs));
 }
+
+#[test]
+fn truncated_powers() {
+    let rng = &mut ::rand::thread_rng();
+
+    let privkeys: Vec<_> = (0..3).map(|_| PrivateKey::new(rng)).collect();
+    let pubkeys: Vec<_> = privkeys.iter().map(|p| p.pubkey(rng)).collect();
+
+    let cs = CS::dummy();
+
+    // The same players contribute to the powers for `cs` and to more
+    // powers than it needs.
+    let mut stage1 = Stage1Contents::new(&cs);
+    let mut full = Stage1Contents::with_powers(2 * cs.d);
+    for (private, public) in privkeys.iter().zip(pubkeys.iter()) {
+        let prev = full.clone();
+        full.transform(private);
+        assert!(full.verify_transform(&prev, public));
+
+        stage1.transform(private);
+    }
+
+    let truncated = full.truncate(&cs).unwrap();
+    assert!(truncated.is_truncation_of(&full));
+    assert!(truncated.is_truncation_of(&stage1) && stage1.is_truncation_of(&truncated));
+    assert!(!full.is_truncation_of(&truncated));
+
+    let mut wrong = truncated.clone();
+    wrong.v1[1] = wrong.v1[1] + G1::one();
+    assert!(!wrong.is_truncation_of(&full));
+
+    // There must be at least as many powers as the circuit needs.
+    assert!(Stage1Contents::with_powers(cs.d).truncate(&cs).is_err());
+    assert!(Stage1Contents::with_powers(cs.d + 1).truncate(&cs).is_ok());
+}
@@ -1,5 +1,111 @@
 use bn::*;
 use crossbeam;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use std::time::Instant;
+
+const DEFAULT_THREADS: usize = 8;
+
+/// Elements each thread works through between reports of its progress.
+const BATCH_SIZE: usize = 1024;
+
+static THREADS: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Sets the number of threads used by the protocol's parallel
+/// operations. This should be called once, before any stage
+/// contents are constructed or transformed.
+pub fn set_threads(threads: usize) {
+    assert!(threads > 0);
+
+    THREADS.store(threads, Ordering::SeqCst);
+}
+
+/// The number of threads used by the protocol's parallel operations.
+pub fn threads() -> usize {
+    match THREADS.load(Ordering::SeqCst) {
+        0 => DEFAULT_THREADS,
+        n => n
+    }
+}
+
+/// Follows the protocol's parallel operations as they work through
+/// each vector.
+pub trait Observer: Sync {
+    /// `done` of the `total` elements of the vector `name` have been
+    /// processed. This is called from the worker threads, once per batch.
+    fn progress(&self, name: &str, done: usize, total: usize);
+}
+
+impl<F: Fn(&str, usize, usize) + Sync> Observer for F {
+    fn progress(&self, name: &str, done: usize, total: usize) {
+        self(name, done, total)
+    }
+}
+
+/// An observer which ignores progress.
+pub fn quiet(_: &str, _: usize, _: usize) {}
+
+/// Decides when the progress of each vector is worth reporting, every
+/// 10% like transfers, and measures its throughput.
+pub struct Meter {
+    vectors: Mutex<HashMap<String, (Instant, Option<usize>)>>
+}
+
+impl Meter {
+    pub fn new() -> Meter {
+        Meter {
+            vectors: Mutex::new(HashMap::new())
+        }
+    }
+
+    /// Returns the percentage to report and the elements processed per
+    /// second so far, if any.
+    pub fn update(&self, name: &str, done: usize, total: usize) -> Option<(usize, f64)> {
+        let mut vectors = self.vectors.lock().unwrap();
+        let &mut (start, ref mut reported) = vectors.entry(name.to_string()).or_insert((Instant::now(), None));
+
+        let percent = if total == 0 { 100 } else { done * 100 / total };
+
+        match *reported {
+            Some(r) if percent < r + 10 && done != total => None,
+            Some(r) if percent == r => None,
+            _ => {
+                *reported = Some(percent);
+
+                let elapsed = start.elapsed();
+                let secs = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+
+                Some((percent, if secs > 0.0 { done as f64 / secs } else { 0.0 }))
+            }
+        }
+    }
+}
+
+/// Counts the elements of one vector processed by the worker threads.
+struct Tracker<'a> {
+    observer: &'a Observer,
+    name: &'a str,
+    total: usize,
+    done: AtomicUsize
+}
+
+impl<'a> Tracker<'a> {
+    fn new(observer: &'a Observer, name: &'a str, total: usize) -> Tracker<'a> {
+        Tracker {
+            observer: observer,
+            name: name,
+            total: total,
+            done: AtomicUsize::new(0)
+        }
+    }
+
+    fn advance(&self, n: usize) {
+        let done = self.done.fetch_add(n, Ordering::SeqCst) + n;
+
+        self.observer.progress(self.name, done, self.total);
+    }
+}
 
 fn calculate_window_size<T>(v: &[T], threads: usize) -> usize
 {
@@ -10,21 +116,36 @@ fn calculate_window_size<T>(v: &[T], threads: usize) -> usize
     }
 }
 
+/// Checks `v1` and `v2` in parallel, one window of each per thread.
+/// Each thread folds its window into an accumulator with `f`, one batch
+/// at a time so that its progress can be reported, starting from
+/// `init`, and `check` decides whether the window is valid from the
+/// result.
 pub fn parallel_all<
     G: Group,
-    F: Fn(&[G], &[G]) -> bool + Sync
+    A: Clone + Send,
+    F: Fn(A, &[G], &[G]) -> A + Sync,
+    C: Fn(A) -> bool + Sync
 >
-(v1: &[G], v2: &[G], f: F, threads: usize) -> bool
+(v1: &[G], v2: &[G], init: A, f: F, check: C, threads: usize, observer: &Observer, name: &str) -> bool
 {
     assert_eq!(v1.len(), v2.len());
-    let f = &f;
+    let (f, check) = (&f, &check);
+    let tracker = &Tracker::new(observer, name, v1.len());
 
     crossbeam::scope(|scope| {
         let window_size = calculate_window_size(v1, threads);
         let mut tasks = vec![];
         for i in v1.chunks(window_size).zip(v2.chunks(window_size)) {
+            let mut acc = init.clone();
+
             tasks.push(scope.spawn(move || {
-                f(i.0, i.1)
+                for (v1, v2) in i.0.chunks(BATCH_SIZE).zip(i.1.chunks(BATCH_SIZE)) {
+                    acc = f(acc, v1, v2);
+                    tracker.advance(v1.len());
+                }
+
+                check(acc)
             }));
         }
 
@@ -37,10 +158,11 @@ pub fn parallel_two<
     Group2: Group,
     F: Fn(usize, &mut [Group1], &mut [Group2]) + Sync
 >
-(v1: &mut [Group1], v2: &mut [Group2], f: F, threads: usize)
+(v1: &mut [Group1], v2: &mut [Group2], f: F, threads: usize, observer: &Observer, name: &str)
 {
     assert_eq!(v1.len(), v2.len());
     let f = &f;
+    let tracker = &Tracker::new(observer, name, v1.len() + v2.len());
 
     crossbeam::scope(|scope| {
         let window_size = calculate_window_size(v1, threads);
@@ -49,7 +171,13 @@ pub fn parallel_two<
                    .zip(v2.chunks_mut(window_size)) 
         {
             scope.spawn(move || {
-                f(j, v.0, v.1);
+                let mut start = j;
+
+                for (v1, v2) in v.0.chunks_mut(BATCH_SIZE).zip(v.1.chunks_mut(BATCH_SIZE)) {
+                    f(start, v1, v2);
+                    start += v1.len();
+                    tracker.advance(v1.len() + v2.len());
+                }
             });
 
             j += window_size;
@@ -61,16 +189,23 @@ pub fn parallel<
     G: Group,
     F: Fn(usize, &mut [G]) + Sync
 >
-(v: &mut [G], f: F, threads: usize)
+(v: &mut [G], f: F, threads: usize, observer: &Observer, name: &str)
 {
     let f = &f;
+    let tracker = &Tracker::new(observer, name, v.len());
 
     crossbeam::scope(|scope| {
         let window_size = calculate_window_size(v, threads);
         let mut j = 0;
         for v in v.chunks_mut(window_size) {
             scope.spawn(move || {
-                f(j, v);
+                let mut start = j;
+
+                for v in v.chunks_mut(BATCH_SIZE) {
+                    f(start, v);
+                    start += v.len();
+                    tracker.advance(v.len());
+                }
             });
 
             j += window_size;
@@ -78,13 +213,13 @@ pub fn parallel<
     });
 }
 
-pub fn mul_all_by<G: Group>(v: &mut [G], c: Fr) {
+pub fn mul_all_by<G: Group>(v: &mut [G], c: Fr, observer: &Observer, name: &str) {
     parallel(v, |_, v| {
         for i in v {
             *i = *i * c;
             i.normalize();
         }
-    }, ::THREADS);
+    }, threads(), observer, name);
 }
 
 pub fn add_all_to<G: Group>(v: &mut [G], other: &[G]) {
@@ -95,5 +230,47 @@ pub fn add_all_to<G: Group>(v: &mut [G], other: &[G]) {
             *a = *a + other[i];
             i += 1;
         }
-    }, ::THREADS);
+    }, threads(), &quiet, "");
+}
+
+#[test]
+fn observed_progress() {
+    let mut v: Vec<G1> = (0..5000).map(|_| G1::one()).collect();
+    let reported = Mutex::new(vec![]);
+
+    mul_all_by(&mut v, Fr::from_str("1").unwrap(), &|name: &str, done: usize, total: usize| {
+        assert_eq!(name, "v");
+        assert_eq!(total, 5000);
+        reported.lock().unwrap().push(done);
+    }, "v");
+
+    let reported = reported.into_inner().unwrap();
+    assert!(reported.len() >= 5);
+    assert_eq!(reported.iter().max(), Some(&5000));
+}
+
+#[test]
+fn one_check_per_window() {
+    let v: Vec<G1> = (0..5000).map(|_| G1::one()).collect();
+    let checks = AtomicUsize::new(0);
+
+    // Each window of 1250 elements spans two batches, which are only
+    // checked together.
+    assert!(parallel_all(&v, &v, 0, |n, v1, _| n + v1.len(), |n| {
+        checks.fetch_add(1, Ordering::SeqCst);
+        n == 1250
+    }, 4, &quiet, "v"));
+    assert_eq!(checks.load(Ordering::SeqCst), 4);
+}
+
+#[test]
+fn meter_reports_every_ten_percent() {
+    let meter = Meter::new();
+
+    assert_eq!(meter.update("v", 1, 100).map(|r| r.0), Some(1));
+    assert_eq!(meter.update("v", 5, 100), None);
+    assert_eq!(meter.update("v", 11, 100).map(|r| r.0), Some(11));
+    assert_eq!(meter.update("w", 5, 100).map(|r| r.0), Some(5));
+    assert_eq!(meter.update("v", 100, 100).map(|r| r.0), Some(100));
+    assert_eq!(meter.update("v", 100, 100), None);
 }
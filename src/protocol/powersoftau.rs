@@ -0,0 +1,310 @@
+//! A universal "powers of tau" ceremony, which does not depend on any
+//! constraint system. It is run once for a maximum degree `n = 2^k`,
+//! and its `Accumulator` then serves every circuit whose QAP has a
+//! degree of at most `n`: in place of stage 1 of PGHR13, truncated with
+//! `Accumulator::truncate`, or in place of the first two stages of
+//! Groth16 (see `groth16::Stage2Contents::from_accumulator`), since it
+//! also holds the powers of tau multiplied by alpha and by beta.
+
+use bn::*;
+use rand::Rng;
+
+#[cfg(feature = "snark")]
+use snark::CS;
+
+use super::{Stage1Contents, Observer, quiet};
+use super::spair::{Spair, same_power, checkseq};
+use super::nizk::Nizk;
+use super::digest::{Digest256, Digest512};
+use super::multicore::*;
+
+/// The secrets sampled by a player of the ceremony.
+pub struct PrivateKey {
+    pub tau: Fr,
+    pub alpha: Fr,
+    pub beta: Fr
+}
+
+impl PrivateKey {
+    pub fn new<R: Rng>(rng: &mut R) -> PrivateKey {
+        PrivateKey {
+            tau: Fr::random(rng),
+            alpha: Fr::random(rng),
+            beta: Fr::random(rng)
+        }
+    }
+
+    /// Construct a "blank" private key for accumulating
+    /// in tests.
+    pub fn new_blank() -> PrivateKey {
+        PrivateKey {
+            tau: Fr::one(),
+            alpha: Fr::one(),
+            beta: Fr::one()
+        }
+    }
+
+    pub fn multiply(&mut self, other: &Self) {
+        self.tau = self.tau * other.tau;
+        self.alpha = self.alpha * other.alpha;
+        self.beta = self.beta * other.beta;
+    }
+
+    /// Construct the "public key" used to verify that the player
+    /// is performing their transformations correctly.
+    pub fn pubkey<R: Rng>(&self, rng: &mut R) -> PublicKey {
+        PublicKey {
+            tau: Spair::random(rng, self.tau).unwrap(),
+            alpha: Spair::random(rng, self.alpha).unwrap(),
+            beta: Spair::random(rng, self.beta).unwrap()
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, RustcEncodable, RustcDecodable)]
+pub struct PublicKey {
+    tau: Spair<G2>, // (f1, f1 * tau)
+    alpha: Spair<G2>, // (f2, f2 * alpha)
+    beta: Spair<G2> // (f3, f3 * beta)
+}
+
+impl PublicKey {
+    pub fn hash(&self) -> Digest256 {
+        Digest256::from(self).expect("PublicKey should never fail to encode")
+    }
+
+    pub fn nizks<R: Rng>(&self, rng: &mut R, privkey: &PrivateKey, extra: &Digest512) -> PublicKeyNizks {
+        PublicKeyNizks {
+            tau: self.tau.nizk(rng, privkey.tau, extra),
+            alpha: self.alpha.nizk(rng, privkey.alpha, extra),
+            beta: self.beta.nizk(rng, privkey.beta, extra)
+        }
+    }
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct PublicKeyNizks {
+    tau: Nizk<G2>,
+    alpha: Nizk<G2>,
+    beta: Nizk<G2>
+}
+
+impl PublicKeyNizks {
+    pub fn is_valid(&self, pubkey: &PublicKey, extra: &Digest512) -> bool {
+        pubkey.tau.verify_nizk(&self.tau, extra) &&
+        pubkey.alpha.verify_nizk(&self.alpha, extra) &&
+        pubkey.beta.verify_nizk(&self.beta, extra)
+    }
+}
+
+/// What the players of the ceremony transform in turn.
+#[derive(Clone, RustcEncodable, RustcDecodable)]
+pub struct Accumulator {
+    /// tau^i in G1 and G2, for i from 0 to 2n-2.
+    powers: Stage1Contents,
+    /// alpha tau^i in G1, for i from 0 to n-1.
+    alpha_tau_g1: Vec<G1>,
+    /// beta tau^i in G1, for i from 0 to n-1.
+    beta_tau_g1: Vec<G1>,
+    beta_g2: G2
+}
+
+impl Accumulator {
+    /// The accumulator for circuits whose QAP has a degree of at most
+    /// `2^k`, before any player has contributed.
+    pub fn new(k: usize) -> Accumulator {
+        let n = 1 << k;
+
+        Accumulator {
+            powers: Stage1Contents::with_powers(2 * n - 1),
+            alpha_tau_g1: (0..n).map(|_| G1::one()).collect(),
+            beta_tau_g1: (0..n).map(|_| G1::one()).collect(),
+            beta_g2: G2::one()
+        }
+    }
+
+    /// The largest degree of the QAPs the accumulator can serve.
+    pub fn degree(&self) -> usize {
+        self.alpha_tau_g1.len()
+    }
+
+    /// The powers of tau alone, in the layout of the first stage.
+    pub fn powers(&self) -> &Stage1Contents {
+        &self.powers
+    }
+
+    /// The final contents of stage 1 for `cs`, whose degree must be at
+    /// most that of the accumulator. Verifiers can check the result
+    /// against the published accumulator with
+    /// `Stage1Contents::is_truncation_of`.
+    #[cfg(feature = "snark")]
+    pub fn truncate(&self, cs: &CS) -> Result<Stage1Contents, String> {
+        if cs.d > self.degree() {
+            return Err(format!("the circuit has degree {}, more than the accumulator's {}",
+                               cs.d, self.degree()));
+        }
+
+        self.powers.truncate(cs)
+    }
+
+    pub fn alpha_tau_g1(&self) -> &[G1] {
+        &self.alpha_tau_g1
+    }
+
+    pub fn beta_tau_g1(&self) -> &[G1] {
+        &self.beta_tau_g1
+    }
+
+    pub fn beta_g2(&self) -> G2 {
+        self.beta_g2
+    }
+
+    /// The number of group elements `transform` works through.
+    pub fn size(&self) -> usize {
+        self.powers.size() + self.alpha_tau_g1.len() + self.beta_tau_g1.len()
+    }
+
+    pub fn transform(&mut self, s: &PrivateKey) {
+        self.transform_observed(s, &quiet)
+    }
+
+    /// Like `transform`, reporting the progress through each vector to
+    /// `observer`.
+    pub fn transform_observed(&mut self, s: &PrivateKey, observer: &Observer) {
+        self.powers.multiply_by_tau(s.tau, observer);
+        multiply_by_powers(&mut self.alpha_tau_g1, s.alpha, s.tau, observer, "alpha_tau_g1");
+        multiply_by_powers(&mut self.beta_tau_g1, s.beta, s.tau, observer, "beta_tau_g1");
+        self.beta_g2 = self.beta_g2 * s.beta;
+    }
+
+    pub fn is_well_formed(&self, prev: &Self) -> bool {
+        let n = self.alpha_tau_g1.len();
+
+        n >= 2 &&
+        self.powers.is_well_formed(&prev.powers) &&
+        self.powers.v1.len() == 2 * n - 1 &&
+        self.alpha_tau_g1.len() == prev.alpha_tau_g1.len() &&
+        self.beta_tau_g1.len() == prev.beta_tau_g1.len() &&
+        self.beta_tau_g1.len() == n &&
+        !prev.alpha_tau_g1[0].is_zero() &&
+        !prev.beta_tau_g1[0].is_zero() &&
+        !prev.beta_g2.is_zero() &&
+        !self.alpha_tau_g1[0].is_zero() &&
+        !self.beta_tau_g1[0].is_zero() &&
+        !self.beta_g2.is_zero()
+    }
+
+    pub fn verify_transform(&self, prev: &Self, p: &PublicKey) -> bool {
+        self.verify_transform_observed(prev, p, &quiet)
+    }
+
+    /// Like `verify_transform`, reporting the progress through each
+    /// vector to `observer`.
+    pub fn verify_transform_observed(&self, prev: &Self, p: &PublicKey, observer: &Observer) -> bool {
+        self.is_well_formed(prev) &&
+        self.powers.verify_tau(&prev.powers, &p.tau, observer) &&
+        same_power(
+            &Spair::new(prev.alpha_tau_g1[0], self.alpha_tau_g1[0]).unwrap(),
+            &p.alpha
+        ) &&
+        same_power(
+            &Spair::new(prev.beta_tau_g1[0], self.beta_tau_g1[0]).unwrap(),
+            &p.beta
+        ) &&
+        same_power(
+            &Spair::new(prev.beta_tau_g1[0], self.beta_tau_g1[0]).unwrap(),
+            &Spair::new(prev.beta_g2, self.beta_g2).unwrap()
+        ) &&
+        // Having checked the first of each, the rest must be successive
+        // multiples by the tau of the powers.
+        checkseq(
+            &self.alpha_tau_g1,
+            &Spair::new(self.powers.v2[0], self.powers.v2[1]).unwrap(),
+            observer,
+            "alpha_tau_g1"
+        ) &&
+        checkseq(
+            &self.beta_tau_g1,
+            &Spair::new(self.powers.v2[0], self.powers.v2[1]).unwrap(),
+            observer,
+            "beta_tau_g1"
+        )
+    }
+}
+
+/// Multiplies the ith element of `v` by `c tau^i`.
+fn multiply_by_powers(v: &mut [G1], c: Fr, tau: Fr, observer: &Observer, name: &str) {
+    parallel(v, |start, v| {
+        let mut c = c * tau.pow(Fr::from_str(&format!("{}", start)).unwrap());
+
+        for g in v {
+            *g = *g * c;
+            g.normalize();
+            c = c * tau;
+        }
+    }, threads(), observer, name);
+}
+
+#[test]
+fn universal_ceremony() {
+    let rng = &mut ::rand::thread_rng();
+
+    let privkeys: Vec<_> = (0..3).map(|_| PrivateKey::new(rng)).collect();
+    let pubkeys: Vec<_> = privkeys.iter().map(|p| p.pubkey(rng)).collect();
+
+    let extra = Digest512::from(&"test").unwrap();
+    let nizks = pubkeys[0].nizks(rng, &privkeys[0], &extra);
+    assert!(nizks.is_valid(&pubkeys[0], &extra));
+    assert!(!nizks.is_valid(&pubkeys[1], &extra));
+
+    let mut acc = Accumulator::new(4);
+    assert_eq!(acc.degree(), 16);
+
+    for (private, public) in privkeys.iter().zip(pubkeys.iter()) {
+        let prev = acc.clone();
+        acc.transform(private);
+        assert!(acc.verify_transform(&prev, public));
+
+        // A contribution made with other secrets than the public key's
+        let mut wrong = prev.clone();
+        wrong.transform(&PrivateKey::new(rng));
+        assert!(!wrong.verify_transform(&prev, public));
+    }
+
+    let mut secrets = PrivateKey::new_blank();
+    for private in privkeys.iter() {
+        secrets.multiply(private);
+    }
+
+    let mut c = Fr::one();
+    for i in 0..acc.powers.v1.len() {
+        assert!(acc.powers.v1[i] == G1::one() * c);
+        assert!(acc.powers.v2[i] == G2::one() * c);
+
+        if i < acc.degree() {
+            assert!(acc.alpha_tau_g1[i] == G1::one() * (c * secrets.alpha));
+            assert!(acc.beta_tau_g1[i] == G1::one() * (c * secrets.beta));
+        }
+
+        c = c * secrets.tau;
+    }
+    assert!(acc.beta_g2 == G2::one() * secrets.beta);
+}
+
+#[test]
+fn truncate_to_circuit() {
+    let cs = CS::dummy();
+
+    let mut k = 0;
+    while (1 << k) < cs.d {
+        k += 1;
+    }
+
+    let acc = Accumulator::new(k);
+    assert_eq!(acc.truncate(&cs).unwrap().v1.len(), cs.d + 1);
+
+    // An accumulator of a smaller degree than the circuit's cannot serve
+    // it.
+    assert!(k > 0);
+    assert!(Accumulator::new(k - 1).truncate(&cs).is_err());
+}
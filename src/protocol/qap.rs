@@ -24,6 +24,19 @@ pub fn evaluate(g1_powers: &[G1], g2_powers: &[G2], cs: &CS) -> (Vec<G1>, Vec<G1
     (at, bt1, bt2, ct)
 }
 
+/// Evaluates the QAP A, B and C polynomials at tau for Groth16, which
+/// needs no extension with Z(tau). Only the first `cs.d` powers are used.
+pub fn evaluate_groth16(g1_powers: &[G1], g2_powers: &[G2], cs: &CS) -> (Vec<G1>, Vec<G1>, Vec<G2>, Vec<G1>)
+{
+    assert!(g1_powers.len() >= cs.d);
+    assert!(g2_powers.len() >= cs.d);
+
+    let lc1 = lagrange_coeffs(&g1_powers[0..cs.d], cs.omega);
+    let lc2 = lagrange_coeffs(&g2_powers[0..cs.d], cs.omega);
+
+    evaluate_qap_polynomials(&lc1, &lc2, cs)
+}
+
 fn evaluate_qap_polynomials(lc1: &[G1], lc2: &[G2], cs: &CS) -> (Vec<G1>, Vec<G1>, Vec<G2>, Vec<G1>)
 {
     assert_eq!(lc1.len(), cs.d);
@@ -45,10 +58,10 @@ fn lagrange_coeffs<G: Group>(v: &[G], omega: Fr) -> Vec<G>
     assert_eq!((v.len() / 2) * 2, v.len());
 
     let overd = Fr::from_str(&format!("{}", v.len())).unwrap().inverse().unwrap();
-    let mut tmp = fft(v, omega, ::THREADS);
+    let mut tmp = fft(v, omega, threads());
     tmp.reverse(); // coefficients are in reverse
 
-    mul_all_by(&mut tmp, overd);
+    mul_all_by(&mut tmp, overd, &quiet, "");
 
     tmp
 }
@@ -113,7 +126,7 @@ fn fft<G: Group>(v: &[G], omega: Fr, threads: usize) -> Vec<G>
                     acc = acc * omega;
                     i += 1;
                 }
-            }, threads);
+            }, threads, &quiet, "");
         }
 
         res
@@ -199,3 +212,80 @@ fn compare_to_libsnark() {
     // Wrong polynomials
     assert!(!cs.test_eval(&Fr::random(rng), &bt1, &bt1, &bt2, &ct));
 }
+
+#[test]
+#[cfg(feature = "libsnark")]
+fn native_matches_libsnark() {
+    use snark::native;
+    use snark::r1cs::R1CS;
+
+    let rng = &mut ::rand::thread_rng();
+
+    let cs = CS::dummy();
+
+    // The same constraint system and QAP domain
+    let mut dummy = R1CS::dummy(250, 4);
+    dummy.swap_ab_if_beneficial();
+    assert!(cs.r1cs() == dummy);
+
+    let native_cs = native::CS::from_r1cs(cs.r1cs()).unwrap();
+    assert_eq!(native_cs.d, cs.d);
+    assert_eq!(native_cs.num_vars, cs.num_vars);
+    assert_eq!(native_cs.num_inputs, cs.num_inputs);
+    assert!(native_cs.omega == cs.omega);
+
+    // The same evaluation of the QAP
+    let tau = Fr::random(rng);
+    let mut powers = vec![Fr::one()];
+    while powers.len() < cs.d {
+        let last = powers[powers.len() - 1];
+        powers.push(last * tau);
+    }
+    let lc1 = lagrange_coeffs(&powers.iter().map(|&e| G1::one() * e).collect::<Vec<_>>(), cs.omega);
+    let lc2 = lagrange_coeffs(&powers.iter().map(|&e| G2::one() * e).collect::<Vec<_>>(), cs.omega);
+    assert!(native_cs.test_compare_tau(&lc1, &lc2, &tau));
+
+    let (at, bt1, bt2, ct) = evaluate_qap_polynomials(&lc1, &lc2, &cs);
+    let mut native_at = (0..cs.num_vars).map(|_| G1::zero()).collect::<Vec<_>>();
+    let mut native_bt1 = (0..cs.num_vars).map(|_| G1::zero()).collect::<Vec<_>>();
+    let mut native_bt2 = (0..cs.num_vars).map(|_| G2::zero()).collect::<Vec<_>>();
+    let mut native_ct = (0..cs.num_vars).map(|_| G1::zero()).collect::<Vec<_>>();
+    native_cs.eval(&lc1, &lc2, &mut native_at, &mut native_bt1, &mut native_bt2, &mut native_ct);
+    assert!(native_at == at && native_bt1 == bt1 && native_bt2 == bt2 && native_ct == ct);
+    assert!(native_cs.test_eval(&tau, &at, &bt1, &bt2, &ct));
+
+    // Byte-identical keys
+    let s: Vec<Fr> = (0..8).map(|_| Fr::random(rng)).collect();
+    let kp = Keypair::generate(&cs, &s[0], &s[1], &s[2], &s[3], &s[4], &s[5], &s[6], &s[7]);
+    let native_kp = native::Keypair::generate(&native_cs, &s[0], &s[1], &s[2], &s[3], &s[4], &s[5], &s[6], &s[7]);
+    assert!(kp.to_bytes() == native_kp.to_bytes());
+}
+
+#[test]
+#[cfg(feature = "libsnark")]
+fn native_domain_matches_libsnark() {
+    use snark::native;
+    use snark::r1cs::R1CS;
+
+    let rng = &mut ::rand::thread_rng();
+
+    // Constraint counts that libsnark rounds up to a basic radix-2
+    // domain, including the smallest above a step domain's size.
+    for &(constraints, inputs) in &[(100, 4), (92, 4)] {
+        let cs = CS::from_r1cs(R1CS::dummy(constraints, inputs)).unwrap();
+        let native_cs = native::CS::from_r1cs(R1CS::dummy(constraints, inputs)).unwrap();
+
+        assert_eq!(cs.d, 128);
+        assert_eq!(native_cs.d, cs.d);
+        assert_eq!(native_cs.num_vars, cs.num_vars);
+        assert!(native_cs.omega == cs.omega);
+
+        let s: Vec<Fr> = (0..8).map(|_| Fr::random(rng)).collect();
+        let kp = Keypair::generate(&cs, &s[0], &s[1], &s[2], &s[3], &s[4], &s[5], &s[6], &s[7]);
+        let native_kp = native::Keypair::generate(&native_cs, &s[0], &s[1], &s[2], &s[3], &s[4], &s[5], &s[6], &s[7]);
+        assert!(kp.to_bytes() == native_kp.to_bytes());
+    }
+
+    // libsnark would use a step domain of 96
+    assert!(native::CS::from_r1cs(R1CS::dummy(91, 4)).is_err());
+}
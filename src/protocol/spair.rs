@@ -79,16 +79,14 @@ where Group1: Pairing<Group2> {
 }
 
 pub fn checkvec<Group1: Group, Group2: Group>(
-    v1: &[Group1], v2: &[Group1], a: &Spair<Group2>
+    v1: &[Group1], v2: &[Group1], a: &Spair<Group2>, observer: &Observer, name: &str
 ) -> bool
 where Group1: Pairing<Group2>
 {
-    parallel_all(v1, v2, |v1, v2| {
+    parallel_all(v1, v2, (Group1::zero(), Group1::zero()), |(mut p, mut q), v1, v2| {
         assert_eq!(v1.len(), v2.len());
 
         let rng = &mut ::rand::thread_rng();
-        let mut p = Group1::zero();
-        let mut q = Group1::zero();
 
         for (a, b) in v1.iter().zip(v2.iter()) {
             let alpha = Fr::random(rng);
@@ -96,6 +94,8 @@ where Group1: Pairing<Group2>
             q = q + (*b * alpha);
         }
 
+        (p, q)
+    }, |(p, q)| {
         if p.is_zero() && q.is_zero() {
             true
         } else if p.is_zero() || q.is_zero() {
@@ -103,15 +103,15 @@ where Group1: Pairing<Group2>
         } else {
             same_power(&Spair::new(p, q).unwrap(), a)
         }
-    }, ::THREADS)
+    }, threads(), observer, name)
 }
 
 pub fn checkseq<Group1: Group, Group2: Group>(
-    v: &[Group1], a: &Spair<Group2>
+    v: &[Group1], a: &Spair<Group2>, observer: &Observer, name: &str
 ) -> bool
 where Group1: Pairing<Group2>
 {
-    checkvec(&v[0..v.len()-1], &v[1..], a)
+    checkvec(&v[0..v.len()-1], &v[1..], a, observer, name)
 }
 
 #[test]
@@ -149,7 +149,7 @@ fn samepower_seq() {
                 a.push(n);
             }
 
-            assert!(checkseq(&a, &p));
+            assert!(checkseq(&a, &p, &quiet, ""));
         }
 
         // Test not working.
@@ -169,7 +169,7 @@ fn samepower_seq() {
                 }
             }
 
-            assert!(!checkseq(&a, &p));
+            assert!(!checkseq(&a, &p, &quiet, ""));
         }
     }
 
@@ -0,0 +1,340 @@
+use std::fs::File;
+use std::io::{Read, Write, Seek, SeekFrom};
+
+use blake2_rfc::blake2s::Blake2s;
+use rustc_serialize::Decodable;
+use bincode::SizeLimit::{Bounded, Infinite};
+use bincode::rustc_serialize::{encode_into, decode_from};
+
+use protocol::*;
+use super::{Header, RecordType, Rejection, TranscriptReader};
+
+/// The location of a single record in the transcript.
+#[derive(Clone, RustcEncodable, RustcDecodable)]
+pub struct IndexEntry {
+    pub record: RecordType,
+    /// The stage the record belongs to, or zero for the records before
+    /// the first turn: the commitments, and the stage 1 a ceremony built
+    /// upon an accumulator started from.
+    pub stage: usize,
+    /// The (zero-indexed) player who contributed the record.
+    pub player: usize,
+    /// Offset of the record's payload from the start of the transcript.
+    pub offset: u64,
+    pub len: u64,
+    /// Hash of the payload. Payloads are bincode encoded, so this is
+    /// the same as `Digest256::from` of the record's contents; the
+    /// digest of a `PublicKey` record is the player's commitment.
+    pub digest: Digest256
+}
+
+/// Byte offsets of every record in a transcript, so that individual
+/// records can be read without decoding everything before them.
+#[derive(Clone, RustcEncodable, RustcDecodable)]
+pub struct TranscriptIndex {
+    pub header: Header,
+    /// Length of the indexed transcript, used to detect a stale index.
+    pub transcript_len: u64,
+    pub entries: Vec<IndexEntry>
+}
+
+/// Assigns records to stages and players as they appear in the
+/// transcript.
+struct Labeller {
+    commitments: usize,
+    stage: usize,
+    player: usize,
+    /// Players whose contribution was rejected, who have no further
+    /// records in the transcript.
+    dropped: Vec<usize>
+}
+
+impl Labeller {
+    fn new() -> Labeller {
+        Labeller {
+            commitments: 0,
+            stage: 0,
+            player: 0,
+            dropped: vec![]
+        }
+    }
+
+    fn enter_stage(&mut self, stage: usize) {
+        if self.stage != stage {
+            self.stage = stage;
+            self.player = 0;
+            self.skip_dropped();
+        }
+    }
+
+    fn skip_dropped(&mut self) {
+        while self.dropped.contains(&self.player) {
+            self.player += 1;
+        }
+    }
+
+    /// A rejection says which turn it belongs to, and takes the place
+    /// of that turn's records.
+    fn reject(&mut self, rejection: &Rejection) -> (usize, usize) {
+        self.stage = rejection.stage;
+        self.player = rejection.player + 1;
+        self.dropped.push(rejection.player);
+        self.skip_dropped();
+
+        (rejection.stage, rejection.player)
+    }
+
+    fn label(&mut self, ty: RecordType) -> (usize, usize) {
+        match ty {
+            RecordType::Commitment => {
+                self.commitments += 1;
+
+                return (0, self.commitments - 1);
+            },
+            // Before the first turn, stage 1 is the truncated powers of
+            // an accumulator rather than any player's contribution.
+            RecordType::Stage1 if self.stage == 0 => return (0, 0),
+            RecordType::PublicKey | RecordType::PublicKeyNizks | RecordType::Stage1 => {
+                self.enter_stage(1)
+            },
+            RecordType::Stage2 => self.enter_stage(2),
+            RecordType::Stage3 => self.enter_stage(3),
+            RecordType::IHash => {
+                // The ihash is the last record of a player's turn.
+                let player = self.player;
+                self.player += 1;
+                self.skip_dropped();
+
+                return (self.stage, player);
+            },
+            RecordType::Rejected => unreachable!()
+        }
+
+        (self.stage, self.player)
+    }
+}
+
+/// The records of a legacy transcript, which we cannot discover from
+/// the transcript itself.
+fn legacy_layout(players: usize) -> Vec<RecordType> {
+    let mut layout = vec![];
+
+    for _ in 0..players {
+        layout.push(RecordType::Commitment);
+    }
+    for _ in 0..players {
+        layout.extend_from_slice(&[
+            RecordType::PublicKey,
+            RecordType::PublicKeyNizks,
+            RecordType::Stage1,
+            RecordType::IHash
+        ]);
+    }
+    for _ in 0..players {
+        layout.extend_from_slice(&[RecordType::Stage2, RecordType::IHash]);
+    }
+    for _ in 0..players {
+        layout.extend_from_slice(&[RecordType::Stage3, RecordType::IHash]);
+    }
+
+    layout
+}
+
+fn position<S: Seek>(s: &mut S) -> Result<u64, String> {
+    s.seek(SeekFrom::Current(0)).map_err(|e| e.to_string())
+}
+
+fn hash_payload<R: Read>(r: &mut R, len: u64) -> Result<Digest256, String> {
+    let mut hasher = Blake2s::new(32);
+    let mut payload = r.by_ref().take(len);
+    let mut buf = vec![0; 1 << 16];
+    let mut read = 0;
+
+    loop {
+        match payload.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => {
+                hasher.update(&buf[0..n]);
+                read += n as u64;
+            },
+            Err(e) => return Err(e.to_string())
+        }
+    }
+
+    if read != len {
+        return Err("transcript ends in the middle of a record".into());
+    }
+
+    let mut digest = [0; 32];
+    digest.copy_from_slice(hasher.finalize().as_bytes());
+
+    Ok(Digest256(digest))
+}
+
+/// Decodes and discards a record of a legacy transcript, the only way
+/// to find out where it ends.
+fn skip_legacy<R: Read>(r: &mut TranscriptReader<R>, ty: RecordType) -> Result<(), String> {
+    match ty {
+        RecordType::Commitment | RecordType::IHash => r.read::<Digest256>(ty).map(|_| ()),
+        RecordType::PublicKey => r.read::<PublicKey>(ty).map(|_| ()),
+        RecordType::PublicKeyNizks => r.read::<PublicKeyNizks>(ty).map(|_| ()),
+        RecordType::Stage1 => r.read::<Stage1Contents>(ty).map(|_| ()),
+        RecordType::Stage2 => r.read::<Stage2Contents>(ty).map(|_| ()),
+        RecordType::Stage3 => r.read::<Stage3Contents>(ty).map(|_| ()),
+        RecordType::Rejected => r.read::<Rejection>(ty).map(|_| ())
+    }
+}
+
+impl TranscriptIndex {
+    /// Reads through the transcript once, recording the location and
+    /// digest of every record.
+    pub fn build<R: Read + Seek>(r: &mut R) -> Result<TranscriptIndex, String> {
+        let transcript_len = try!(r.seek(SeekFrom::End(0)).map_err(|e| e.to_string()));
+        try!(r.seek(SeekFrom::Start(0)).map_err(|e| e.to_string()));
+
+        let mut reader = try!(TranscriptReader::open(r));
+        let header = reader.header().clone();
+        let mut labeller = Labeller::new();
+        let mut entries = vec![];
+
+        if header.is_legacy() {
+            for ty in legacy_layout(header.players) {
+                let offset = try!(position(reader.get_mut()));
+                try!(skip_legacy(&mut reader, ty));
+                let len = try!(position(reader.get_mut())) - offset;
+
+                try!(reader.get_mut().seek(SeekFrom::Start(offset)).map_err(|e| e.to_string()));
+                let digest = try!(hash_payload(reader.get_mut(), len));
+
+                let (stage, player) = labeller.label(ty);
+                entries.push(IndexEntry {
+                    record: ty,
+                    stage: stage,
+                    player: player,
+                    offset: offset,
+                    len: len,
+                    digest: digest
+                });
+            }
+        } else {
+            while let Some((ty, len)) = try!(reader.next_record()) {
+                let offset = try!(position(reader.get_mut()));
+
+                let (stage, player) = if ty == RecordType::Rejected {
+                    let rejection: Rejection = try!(decode_from(&mut reader.get_mut().take(len), Bounded(len))
+                                                    .map_err(|e| format!("could not decode {:?}: {}", ty, e)));
+                    try!(reader.get_mut().seek(SeekFrom::Start(offset)).map_err(|e| e.to_string()));
+
+                    labeller.reject(&rejection)
+                } else {
+                    labeller.label(ty)
+                };
+
+                let digest = try!(hash_payload(reader.get_mut(), len));
+
+                entries.push(IndexEntry {
+                    record: ty,
+                    stage: stage,
+                    player: player,
+                    offset: offset,
+                    len: len,
+                    digest: digest
+                });
+            }
+        }
+
+        Ok(TranscriptIndex {
+            header: header,
+            transcript_len: transcript_len,
+            entries: entries
+        })
+    }
+
+    pub fn load(path: &str) -> Option<TranscriptIndex> {
+        let mut f = match File::open(path) {
+            Ok(f) => f,
+            Err(_) => return None
+        };
+
+        decode_from(&mut f, Infinite).ok()
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let mut f = try!(File::create(path).map_err(|e| e.to_string()));
+
+        encode_into(self, &mut f, Infinite).map_err(|e| e.to_string())
+    }
+
+    /// Finds the record of type `ty` contributed by `player` in `stage`.
+    pub fn find(&self, stage: usize, player: usize, ty: RecordType) -> Option<&IndexEntry> {
+        self.entries.iter().find(|e| e.stage == stage && e.player == player && e.record == ty)
+    }
+
+    /// Decodes a single record from the transcript.
+    pub fn read<R: Read + Seek, T: Decodable>(&self, r: &mut R, entry: &IndexEntry) -> Result<T, String> {
+        try!(r.seek(SeekFrom::Start(entry.offset)).map_err(|e| e.to_string()));
+
+        decode_from(&mut r.by_ref().take(entry.len), Bounded(entry.len))
+            .map_err(|e| format!("could not decode {:?}: {}", entry.record, e))
+    }
+
+    /// Copies the payload of a single record to `w`. The result can be
+    /// decoded on its own with bincode.
+    pub fn extract<R: Read + Seek, W: Write>(&self, r: &mut R, entry: &IndexEntry, w: &mut W) -> Result<(), String> {
+        use std::io;
+
+        try!(r.seek(SeekFrom::Start(entry.offset)).map_err(|e| e.to_string()));
+
+        let copied = try!(io::copy(&mut r.by_ref().take(entry.len), w).map_err(|e| e.to_string()));
+        if copied != entry.len {
+            return Err("transcript ends in the middle of a record".into());
+        }
+
+        w.flush().map_err(|e| e.to_string())
+    }
+}
+
+#[test]
+fn index_transcript() {
+    use std::io::Cursor;
+    use super::TranscriptWriter;
+
+    let comms = vec![
+        Digest256::from(&"commitment 1").unwrap(),
+        Digest256::from(&"commitment 2").unwrap()
+    ];
+    let ihash = Digest256::from(&"ihash").unwrap();
+    let mut stage3 = vec![];
+
+    let mut w = TranscriptWriter::new(vec![], &Header::new(Digest256::from(&"cs").unwrap(), 2)).unwrap();
+    for comm in &comms {
+        w.write(RecordType::Commitment, comm).unwrap();
+    }
+    for i in 0..2 {
+        // Stand-in for the stage contents, which this test doesn't need.
+        stage3.push(vec![i as u8; 100 + i]);
+        w.write(RecordType::Stage3, &stage3[i]).unwrap();
+        w.write(RecordType::IHash, &ihash).unwrap();
+    }
+    let mut transcript = Cursor::new(w.into_inner());
+
+    let index = TranscriptIndex::build(&mut transcript).unwrap();
+    assert_eq!(index.entries.len(), 6);
+    assert_eq!(index.transcript_len, transcript.get_ref().len() as u64);
+
+    let entry = index.find(0, 1, RecordType::Commitment).unwrap();
+    assert!(index.read::<_, Digest256>(&mut transcript, entry).unwrap() == comms[1]);
+    assert!(entry.digest == Digest256::from(&comms[1]).unwrap());
+
+    let entry = index.find(3, 1, RecordType::Stage3).unwrap().clone();
+    assert!(entry.digest == Digest256::from(&stage3[1]).unwrap());
+    assert!(index.read::<_, Vec<u8>>(&mut transcript, &entry).unwrap() == stage3[1]);
+
+    let mut extracted = vec![];
+    index.extract(&mut transcript, &entry, &mut extracted).unwrap();
+    let decoded: Vec<u8> = decode_from(&mut &extracted[..], Infinite).unwrap();
+    assert!(decoded == stage3[1]);
+
+    assert!(index.find(3, 1, RecordType::IHash).is_some());
+    assert!(index.find(3, 2, RecordType::IHash).is_none());
+}
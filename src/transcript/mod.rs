@@ -0,0 +1,403 @@
+//! The transcript file written by the coordinator.
+//!
+//! A transcript begins with a header identifying the format version,
+//! the curve, the constraint system and the number of players. It is
+//! followed by a sequence of records, each consisting of a one byte
+//! record type, the length of the payload as a big-endian `u64`, and
+//! the payload itself (a bincode-encoded protocol message).
+//!
+//! The Sprout ceremony used a legacy layout that is a bare concatenation
+//! of bincode-encoded messages, beginning with the number of players.
+//! `TranscriptReader` recognizes and reads both.
+
+use std::io::{self, Read, Write};
+
+use byteorder::{BigEndian, ByteOrder, ReadBytesExt, WriteBytesExt};
+use rustc_serialize::{Encodable, Decodable};
+use bincode::SizeLimit::{Bounded, Infinite};
+use bincode::rustc_serialize::{encode_into, decode_from, encoded_size};
+
+use consts::*;
+use protocol::*;
+
+#[cfg(feature = "snark")]
+use snark::CS;
+
+mod index;
+pub use self::index::*;
+
+/// The version written by `TranscriptWriter`.
+pub const TRANSCRIPT_VERSION: u32 = 1;
+
+/// Version reported for transcripts in the legacy Sprout layout.
+pub const LEGACY_VERSION: u32 = 0;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, RustcEncodable, RustcDecodable)]
+pub enum Curve {
+    Bn254
+}
+
+/// The type of a record in the transcript.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, RustcEncodable, RustcDecodable)]
+pub enum RecordType {
+    Commitment,
+    PublicKey,
+    PublicKeyNizks,
+    Stage1,
+    Stage2,
+    Stage3,
+    IHash,
+    /// A contribution the coordinator rejected, see `Rejection`.
+    Rejected
+}
+
+impl RecordType {
+    pub fn tag(&self) -> u8 {
+        match *self {
+            RecordType::Commitment => 0,
+            RecordType::PublicKey => 1,
+            RecordType::PublicKeyNizks => 2,
+            RecordType::Stage1 => 3,
+            RecordType::Stage2 => 4,
+            RecordType::Stage3 => 5,
+            RecordType::IHash => 6,
+            RecordType::Rejected => 7
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            RecordType::Commitment => "commitment",
+            RecordType::PublicKey => "pubkey",
+            RecordType::PublicKeyNizks => "nizks",
+            RecordType::Stage1 => "stage1",
+            RecordType::Stage2 => "stage2",
+            RecordType::Stage3 => "stage3",
+            RecordType::IHash => "ihash",
+            RecordType::Rejected => "rejected"
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<RecordType> {
+        (0..8).filter_map(RecordType::from_tag).find(|ty| ty.name() == name)
+    }
+
+    pub fn from_tag(tag: u8) -> Option<RecordType> {
+        match tag {
+            0 => Some(RecordType::Commitment),
+            1 => Some(RecordType::PublicKey),
+            2 => Some(RecordType::PublicKeyNizks),
+            3 => Some(RecordType::Stage1),
+            4 => Some(RecordType::Stage2),
+            5 => Some(RecordType::Stage3),
+            6 => Some(RecordType::IHash),
+            7 => Some(RecordType::Rejected),
+            _ => None
+        }
+    }
+}
+
+/// The messages of a rejected contribution, bincode encoded as the
+/// player sent them, each with the type of record it would have been
+/// written as.
+pub type Evidence = Vec<(RecordType, Vec<u8>)>;
+
+/// Recorded in place of a player's turn when the coordinator rejected
+/// their contribution. The player takes no further part in the ceremony,
+/// and the next player builds upon the last accepted contribution.
+#[derive(Clone, PartialEq, Eq, Debug, RustcEncodable, RustcDecodable)]
+pub struct Rejection {
+    /// The (zero-indexed) player whose contribution was rejected.
+    pub player: usize,
+    pub stage: usize,
+    pub reason: String,
+    /// What the player sent, so that the verifier can check for itself
+    /// that the contribution is invalid. Empty if the contribution was
+    /// refused before it had been received.
+    pub evidence: Evidence,
+    /// The player who had built upon the contribution before it was
+    /// found to be invalid, and took their turn again.
+    pub retried: Option<usize>
+}
+
+#[derive(Clone, PartialEq, Eq, RustcEncodable, RustcDecodable)]
+pub struct Header {
+    pub version: u32,
+    pub curve: Curve,
+    /// Identifies the constraint system, see `cs_hash`. Unknown for
+    /// legacy transcripts.
+    pub cs_hash: Option<Digest256>,
+    pub players: usize
+}
+
+impl Header {
+    pub fn new(cs_hash: Digest256, players: usize) -> Header {
+        Header {
+            version: TRANSCRIPT_VERSION,
+            curve: Curve::Bn254,
+            cs_hash: Some(cs_hash),
+            players: players
+        }
+    }
+
+    pub fn is_legacy(&self) -> bool {
+        self.version == LEGACY_VERSION
+    }
+}
+
+/// Hash of the constraints of the R1CS, in libsnark's serialization,
+/// which determine the QAP the stage contents depend upon.
+#[cfg(feature = "snark")]
+pub fn cs_hash(cs: &CS) -> Digest256 {
+    Digest256::from_reader(&mut &cs.r1cs().to_bytes()[..])
+}
+
+pub struct TranscriptWriter<W: Write> {
+    w: W
+}
+
+impl<W: Write> TranscriptWriter<W> {
+    pub fn new(mut w: W, header: &Header) -> Result<Self, String> {
+        try!(w.write_all(&TRANSCRIPT_MAGIC).map_err(|e| e.to_string()));
+        try!(w.write_u32::<BigEndian>(header.version).map_err(|e| e.to_string()));
+        try!(encode_into(&header.curve, &mut w, Infinite).map_err(|e| e.to_string()));
+        try!(encode_into(&header.cs_hash, &mut w, Infinite).map_err(|e| e.to_string()));
+        try!(encode_into(&header.players, &mut w, Infinite).map_err(|e| e.to_string()));
+
+        Ok(TranscriptWriter {
+            w: w
+        })
+    }
+
+    /// Continues a transcript whose header has already been written.
+    /// `w` must be positioned at the end of the last complete record.
+    pub fn append(w: W) -> Self {
+        TranscriptWriter {
+            w: w
+        }
+    }
+
+    pub fn write<T: Encodable>(&mut self, ty: RecordType, obj: &T) -> Result<(), String> {
+        try!(self.w.write_u8(ty.tag()).map_err(|e| e.to_string()));
+        try!(self.w.write_u64::<BigEndian>(encoded_size(obj)).map_err(|e| e.to_string()));
+
+        encode_into(obj, &mut self.w, Infinite).map_err(|e| e.to_string())
+    }
+
+    pub fn flush(&mut self) -> Result<(), String> {
+        self.w.flush().map_err(|e| e.to_string())
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.w
+    }
+
+    pub fn into_inner(self) -> W {
+        self.w
+    }
+}
+
+pub struct TranscriptReader<R: Read> {
+    r: R,
+    header: Header,
+    /// A record header read by `peek_record`.
+    peeked: Option<(RecordType, u64)>,
+    /// Set when a read failed because the transcript ended.
+    ended: bool
+}
+
+impl<R: Read> TranscriptReader<R> {
+    /// Reads the header of the transcript, detecting the legacy layout
+    /// by the absence of the magic bytes.
+    pub fn open(mut r: R) -> Result<Self, String> {
+        let mut magic = [0; 8];
+        try!(r.read_exact(&mut magic).map_err(|e| format!("could not read transcript header: {}", e)));
+
+        let header = if magic == TRANSCRIPT_MAGIC {
+            let version = try!(r.read_u32::<BigEndian>().map_err(|e| e.to_string()));
+
+            if version != TRANSCRIPT_VERSION {
+                return Err(format!("unsupported transcript version {}", version));
+            }
+
+            Header {
+                version: version,
+                curve: try!(decode_from(&mut r, Infinite).map_err(|e| e.to_string())),
+                cs_hash: try!(decode_from(&mut r, Infinite).map_err(|e| e.to_string())),
+                players: try!(decode_from(&mut r, Infinite).map_err(|e| e.to_string()))
+            }
+        } else {
+            // The legacy layout begins with the number of players,
+            // encoded by bincode as a big-endian u64.
+            Header {
+                version: LEGACY_VERSION,
+                curve: Curve::Bn254,
+                cs_hash: None,
+                players: BigEndian::read_u64(&magic) as usize
+            }
+        };
+
+        Ok(TranscriptReader {
+            r: r,
+            header: header,
+            peeked: None,
+            ended: false
+        })
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// Reads the type and length of the next record. Not available
+    /// for legacy transcripts, which do not delimit their records.
+    pub fn next_record(&mut self) -> Result<Option<(RecordType, u64)>, String> {
+        if self.header.is_legacy() {
+            return Err("legacy transcripts have no record headers".into());
+        }
+
+        if let Some(record) = self.peeked.take() {
+            return Ok(Some(record));
+        }
+
+        let tag = match self.r.read_u8() {
+            Ok(tag) => tag,
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                self.ended = true;
+                return Ok(None);
+            },
+            Err(e) => return Err(e.to_string())
+        };
+
+        let ty = try!(RecordType::from_tag(tag).ok_or_else(|| format!("unknown record type {}", tag)));
+        let len = match self.r.read_u64::<BigEndian>() {
+            Ok(len) => len,
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                self.ended = true;
+                return Err(format!("{:?} record is cut short", ty));
+            },
+            Err(e) => return Err(e.to_string())
+        };
+
+        Ok(Some((ty, len)))
+    }
+
+    /// Returns the type of the next record without consuming it.
+    pub fn peek_record(&mut self) -> Result<Option<RecordType>, String> {
+        if self.peeked.is_none() {
+            self.peeked = try!(self.next_record());
+        }
+
+        Ok(self.peeked.map(|(ty, _)| ty))
+    }
+
+    /// Reads the next record, which must be of type `ty`.
+    pub fn read<T: Decodable>(&mut self, ty: RecordType) -> Result<T, String> {
+        if self.header.is_legacy() {
+            return decode_from(&mut self.r, Infinite)
+                   .map_err(|e| format!("could not decode {:?}: {}", ty, e));
+        }
+
+        let (found, len) = match try!(self.next_record()) {
+            Some(record) => record,
+            None => return Err(format!("expected {:?} record, found end of transcript", ty))
+        };
+
+        if found != ty {
+            return Err(format!("expected {:?} record, found {:?}", ty, found));
+        }
+
+        let mut payload = (&mut self.r).take(len);
+        let obj = match decode_from(&mut payload, Bounded(len)) {
+            Ok(obj) => obj,
+            Err(e) => {
+                // Tell a record that was cut short from one that is
+                // there in full but does not decode.
+                if io::copy(&mut payload, &mut io::sink()).is_ok() && payload.limit() != 0 {
+                    self.ended = true;
+                    return Err(format!("{:?} record is cut short", ty));
+                }
+
+                return Err(format!("could not decode {:?}: {}", ty, e));
+            }
+        };
+
+        if payload.limit() != 0 {
+            return Err(format!("{:?} record is longer than its contents", ty));
+        }
+
+        Ok(obj)
+    }
+
+    /// Whether a read failed because the transcript ended, at or within
+    /// the last record, rather than because a record was invalid.
+    pub fn ended(&self) -> bool {
+        self.ended
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.r
+    }
+
+    pub fn into_inner(self) -> R {
+        self.r
+    }
+}
+
+#[test]
+fn transcript_roundtrip() {
+    let comm = Digest256::from(&"commitment").unwrap();
+    let ihash = Digest256::from(&"ihash").unwrap();
+    let cs_hash = Digest256::from(&"cs").unwrap();
+
+    let mut w = TranscriptWriter::new(vec![], &Header::new(cs_hash, 1)).unwrap();
+    w.write(RecordType::Commitment, &comm).unwrap();
+    w.write(RecordType::IHash, &ihash).unwrap();
+    let transcript = w.into_inner();
+
+    let mut r = TranscriptReader::open(&transcript[..]).unwrap();
+    assert!(*r.header() == Header::new(cs_hash, 1));
+    assert!(!r.header().is_legacy());
+    assert!(r.read::<Digest256>(RecordType::Commitment).unwrap() == comm);
+
+    // The record type is checked.
+    assert!(r.read::<Digest256>(RecordType::Commitment).is_err());
+
+    let mut r = TranscriptReader::open(&transcript[..]).unwrap();
+    assert_eq!(r.peek_record().unwrap(), Some(RecordType::Commitment));
+    assert_eq!(r.next_record().unwrap(), Some((RecordType::Commitment, 32)));
+
+    // A transcript that ends within its last record
+    let mut r = TranscriptReader::open(&transcript[..transcript.len() - 1]).unwrap();
+    assert!(r.read::<Digest256>(RecordType::Commitment).is_ok());
+    assert!(r.read::<Digest256>(RecordType::IHash).is_err());
+    assert!(r.ended());
+
+    // A record that is there in full, but does not decode
+    let mut transcript = transcript;
+    let len = transcript.len();
+    transcript[len - 32] = 2;
+    let mut r = TranscriptReader::open(&transcript[..]).unwrap();
+    assert!(r.read::<Digest256>(RecordType::Commitment).is_ok());
+    assert!(r.read::<bool>(RecordType::IHash).is_err());
+    assert!(!r.ended());
+}
+
+#[test]
+fn transcript_legacy() {
+    let comm1 = Digest256::from(&"commitment 1").unwrap();
+    let comm2 = Digest256::from(&"commitment 2").unwrap();
+
+    let mut transcript = vec![];
+    encode_into(&2usize, &mut transcript, Infinite).unwrap();
+    encode_into(&comm1, &mut transcript, Infinite).unwrap();
+    encode_into(&comm2, &mut transcript, Infinite).unwrap();
+
+    let mut r = TranscriptReader::open(&transcript[..]).unwrap();
+    assert!(r.header().is_legacy());
+    assert_eq!(r.header().players, 2);
+    assert!(r.header().cs_hash.is_none());
+    assert!(r.read::<Digest256>(RecordType::Commitment).unwrap() == comm1);
+    assert!(r.read::<Digest256>(RecordType::Commitment).unwrap() == comm2);
+    assert!(r.read::<Digest256>(RecordType::Commitment).is_err());
+}
@@ -0,0 +1,149 @@
+//! A transport through files in a shared directory, for participants
+//! who can reach the coordinator only through a synchronized or network
+//! file system.
+//!
+//! Each connection is a subdirectory `conn-N` holding a file for each
+//! direction, to which the writer appends and which the reader polls
+//! for new data.
+
+use std::cell::Cell;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::{Stream, Listener, Connector};
+
+const TO_COORDINATOR: &'static str = "to-coordinator";
+const FROM_COORDINATOR: &'static str = "from-coordinator";
+
+/// How often a reader looks for new data.
+const POLL_INTERVAL: u64 = 100;
+
+pub struct FileStream {
+    incoming: File,
+    outgoing: File,
+    timeout: Cell<Option<Duration>>
+}
+
+impl FileStream {
+    fn open(dir: &Path, incoming: &str, outgoing: &str) -> io::Result<FileStream> {
+        let open = |name: &str| OpenOptions::new().read(true).append(true).create(true).open(dir.join(name));
+
+        Ok(FileStream {
+            incoming: try!(open(incoming)),
+            outgoing: try!(open(outgoing)),
+            timeout: Cell::new(None)
+        })
+    }
+}
+
+impl Read for FileStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let deadline = self.timeout.get().map(|timeout| Instant::now() + timeout);
+
+        loop {
+            let n = try!(self.incoming.read(buf));
+            if n > 0 || buf.is_empty() {
+                return Ok(n);
+            }
+
+            if deadline.map(|deadline| Instant::now() >= deadline).unwrap_or(false) {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "read timed out"));
+            }
+
+            thread::sleep(Duration::from_millis(POLL_INTERVAL));
+        }
+    }
+}
+
+impl Write for FileStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.outgoing.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.outgoing.sync_data()
+    }
+}
+
+impl Stream for FileStream {
+    fn set_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.timeout.set(timeout);
+
+        Ok(())
+    }
+}
+
+fn connection_dir(dir: &Path, n: usize) -> PathBuf {
+    dir.join(format!("conn-{}", n))
+}
+
+pub struct DirListener {
+    dir: PathBuf,
+    next: Cell<usize>
+}
+
+impl DirListener {
+    pub fn new<P: AsRef<Path>>(dir: P) -> io::Result<DirListener> {
+        try!(fs::create_dir_all(&dir));
+
+        // Connections left over from an earlier run are not resumed.
+        let mut next = 0;
+        while connection_dir(dir.as_ref(), next).is_dir() {
+            next += 1;
+        }
+
+        Ok(DirListener {
+            dir: dir.as_ref().into(),
+            next: Cell::new(next)
+        })
+    }
+}
+
+impl Listener for DirListener {
+    fn accept(&self) -> io::Result<(Box<Stream>, String)> {
+        let n = self.next.get();
+        let dir = connection_dir(&self.dir, n);
+
+        while !dir.is_dir() {
+            thread::sleep(Duration::from_millis(POLL_INTERVAL));
+        }
+        self.next.set(n + 1);
+
+        let stream = try!(FileStream::open(&dir, TO_COORDINATOR, FROM_COORDINATOR));
+
+        Ok((Box::new(stream), dir.display().to_string()))
+    }
+}
+
+pub struct DirConnector {
+    dir: PathBuf
+}
+
+impl DirConnector {
+    pub fn new<P: AsRef<Path>>(dir: P) -> DirConnector {
+        DirConnector {
+            dir: dir.as_ref().into()
+        }
+    }
+}
+
+impl Connector for DirConnector {
+    fn connect(&self) -> io::Result<Box<Stream>> {
+        // Every connection gets a fresh directory; creating it is what
+        // claims it.
+        for n in 0.. {
+            let dir = connection_dir(&self.dir, n);
+
+            match fs::create_dir(&dir) {
+                Ok(_) => return Ok(Box::new(try!(FileStream::open(&dir, FROM_COORDINATOR, TO_COORDINATOR)))),
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e)
+            }
+        }
+
+        unreachable!()
+    }
+}
@@ -0,0 +1,183 @@
+//! A transport between threads of the same process.
+
+use std::cell::Cell;
+use std::cmp;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex, Condvar};
+use std::sync::mpsc::{channel, Sender, Receiver};
+use std::time::{Duration, Instant};
+
+use super::{Stream, Listener, Connector};
+
+/// The bytes in flight in one direction, and whether either end has
+/// hung up.
+struct Pipe {
+    state: Mutex<(VecDeque<u8>, bool)>,
+    readable: Condvar
+}
+
+impl Pipe {
+    fn new() -> Arc<Pipe> {
+        Arc::new(Pipe {
+            state: Mutex::new((VecDeque::new(), false)),
+            readable: Condvar::new()
+        })
+    }
+
+    fn close(&self) {
+        self.state.lock().unwrap().1 = true;
+        self.readable.notify_all();
+    }
+}
+
+pub struct MemoryStream {
+    incoming: Arc<Pipe>,
+    outgoing: Arc<Pipe>,
+    timeout: Cell<Option<Duration>>
+}
+
+impl MemoryStream {
+    fn pair() -> (MemoryStream, MemoryStream) {
+        let (a, b) = (Pipe::new(), Pipe::new());
+
+        (MemoryStream {
+            incoming: a.clone(),
+            outgoing: b.clone(),
+            timeout: Cell::new(None)
+        },
+        MemoryStream {
+            incoming: b,
+            outgoing: a,
+            timeout: Cell::new(None)
+        })
+    }
+}
+
+impl Drop for MemoryStream {
+    fn drop(&mut self) {
+        self.incoming.close();
+        self.outgoing.close();
+    }
+}
+
+impl Read for MemoryStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let deadline = self.timeout.get().map(|timeout| Instant::now() + timeout);
+        let mut state = self.incoming.state.lock().unwrap();
+
+        while state.0.is_empty() && !state.1 && !buf.is_empty() {
+            state = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(io::Error::new(io::ErrorKind::TimedOut, "read timed out"));
+                    }
+
+                    self.incoming.readable.wait_timeout(state, deadline - now).unwrap().0
+                },
+                None => self.incoming.readable.wait(state).unwrap()
+            };
+        }
+
+        let n = cmp::min(buf.len(), state.0.len());
+        for (dst, src) in buf.iter_mut().zip(state.0.drain(0..n)) {
+            *dst = src;
+        }
+
+        Ok(n)
+    }
+}
+
+impl Write for MemoryStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.outgoing.state.lock().unwrap();
+
+        if state.1 {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "the other end hung up"));
+        }
+
+        state.0.extend(buf.iter().cloned());
+        self.outgoing.readable.notify_all();
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Stream for MemoryStream {
+    fn set_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.timeout.set(timeout);
+
+        Ok(())
+    }
+}
+
+pub struct MemoryListener {
+    connections: Receiver<MemoryStream>
+}
+
+impl Listener for MemoryListener {
+    fn accept(&self) -> io::Result<(Box<Stream>, String)> {
+        match self.connections.recv() {
+            Ok(stream) => Ok((Box::new(stream), "memory".into())),
+            Err(_) => Err(io::Error::new(io::ErrorKind::NotConnected, "every connector has been dropped"))
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct MemoryConnector {
+    connections: Sender<MemoryStream>
+}
+
+impl Connector for MemoryConnector {
+    fn connect(&self) -> io::Result<Box<Stream>> {
+        let (ours, theirs) = MemoryStream::pair();
+
+        match self.connections.send(theirs) {
+            Ok(_) => Ok(Box::new(ours)),
+            Err(_) => Err(io::Error::new(io::ErrorKind::ConnectionRefused, "the listener has been dropped"))
+        }
+    }
+}
+
+/// Creates an in-memory transport, whose connector can be cloned for
+/// any number of clients.
+pub fn memory() -> (MemoryListener, MemoryConnector) {
+    let (tx, rx) = channel();
+
+    (MemoryListener { connections: rx }, MemoryConnector { connections: tx })
+}
+
+#[test]
+fn memory_transport() {
+    use std::thread;
+
+    let (listener, connector) = memory();
+
+    let server = thread::spawn(move || {
+        let (mut s, _) = listener.accept().unwrap();
+        let mut buf = [0; 5];
+        s.read_exact(&mut buf).unwrap();
+        s.write_all(&buf).unwrap();
+
+        // The client hangs up after reading the echo.
+        assert_eq!(s.read(&mut buf).unwrap(), 0);
+    });
+
+    let mut s = connector.connect().unwrap();
+    s.write_all(b"hello").unwrap();
+    let mut buf = [0; 5];
+    s.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"hello");
+
+    s.set_timeout(Some(Duration::from_millis(10))).unwrap();
+    assert_eq!(s.read(&mut buf).unwrap_err().kind(), io::ErrorKind::TimedOut);
+
+    drop(s);
+    server.join().unwrap();
+}
@@ -0,0 +1,139 @@
+//! The transports over which the coordinator and the players' network
+//! clients communicate.
+//!
+//! The session logic only needs a bidirectional byte stream with a
+//! timeout, so it is written against the `Stream`, `Listener` and
+//! `Connector` traits. Addresses select the transport: `unix:PATH` for a
+//! Unix socket, `dir:PATH` for the file-based transport, and anything
+//! else is a TCP address. The in-memory transport, created by
+//! `memory()`, connects parts of the same process, which is how the
+//! ceremony is tested end to end.
+
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::Duration;
+
+mod memory;
+mod file;
+pub use self::memory::*;
+pub use self::file::*;
+
+/// A connection between the coordinator and a network client.
+pub trait Stream: Read + Write + Send {
+    /// Sets the timeout of reads and writes, or removes it if `None`.
+    fn set_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+/// The coordinator's end of a transport.
+pub trait Listener: Send {
+    /// Waits for the next connection, and describes where it came from.
+    fn accept(&self) -> io::Result<(Box<Stream>, String)>;
+}
+
+/// The network client's end of a transport.
+pub trait Connector: Send {
+    fn connect(&self) -> io::Result<Box<Stream>>;
+}
+
+impl Stream for TcpStream {
+    fn set_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        try!(self.set_read_timeout(timeout));
+        self.set_write_timeout(timeout)
+    }
+}
+
+impl Listener for TcpListener {
+    fn accept(&self) -> io::Result<(Box<Stream>, String)> {
+        let (stream, addr) = try!(TcpListener::accept(self));
+
+        Ok((Box::new(stream), addr.to_string()))
+    }
+}
+
+pub struct TcpConnector(pub String);
+
+impl Connector for TcpConnector {
+    fn connect(&self) -> io::Result<Box<Stream>> {
+        Ok(Box::new(try!(TcpStream::connect(&self.0[..]))))
+    }
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::io;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use super::{Stream, Listener, Connector};
+
+    impl Stream for UnixStream {
+        fn set_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+            try!(self.set_read_timeout(timeout));
+            self.set_write_timeout(timeout)
+        }
+    }
+
+    impl Listener for UnixListener {
+        fn accept(&self) -> io::Result<(Box<Stream>, String)> {
+            let (stream, _) = try!(UnixListener::accept(self));
+
+            Ok((Box::new(stream), "unix socket".into()))
+        }
+    }
+
+    pub struct UnixConnector(pub PathBuf);
+
+    impl Connector for UnixConnector {
+        fn connect(&self) -> io::Result<Box<Stream>> {
+            Ok(Box::new(try!(UnixStream::connect(&self.0))))
+        }
+    }
+}
+
+#[cfg(unix)]
+pub use self::unix::UnixConnector;
+
+/// Listens on `addr`, see the module documentation for its format.
+pub fn listen(addr: &str) -> io::Result<Box<Listener>> {
+    if addr.starts_with("unix:") {
+        listen_unix(&addr[5..])
+    } else if addr.starts_with("dir:") {
+        Ok(Box::new(try!(DirListener::new(&addr[4..]))))
+    } else {
+        Ok(Box::new(try!(TcpListener::bind(addr))))
+    }
+}
+
+/// Connects to `addr`, see the module documentation for its format.
+pub fn connector(addr: &str) -> io::Result<Box<Connector>> {
+    if addr.starts_with("unix:") {
+        connector_unix(&addr[5..])
+    } else if addr.starts_with("dir:") {
+        Ok(Box::new(DirConnector::new(&addr[4..])))
+    } else {
+        Ok(Box::new(TcpConnector(addr.into())))
+    }
+}
+
+#[cfg(unix)]
+fn listen_unix(path: &str) -> io::Result<Box<Listener>> {
+    use std::os::unix::net::UnixListener;
+
+    Ok(Box::new(try!(UnixListener::bind(path))))
+}
+
+#[cfg(not(unix))]
+fn listen_unix(_: &str) -> io::Result<Box<Listener>> {
+    Err(io::Error::new(io::ErrorKind::Other, "unix sockets are not supported on this platform"))
+}
+
+#[cfg(unix)]
+fn connector_unix(path: &str) -> io::Result<Box<Connector>> {
+    Ok(Box::new(UnixConnector(path.into())))
+}
+
+#[cfg(not(unix))]
+fn connector_unix(_: &str) -> io::Result<Box<Connector>> {
+    Err(io::Error::new(io::ErrorKind::Other, "unix sockets are not supported on this platform"))
+}
@@ -0,0 +1,212 @@
+//! What the compute and network machines show the participant: the
+//! disc being worked on, the discs done so far, the progress of long
+//! computations, and a checklist of the hashes already written down.
+
+use std::io::{self, Write};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use protocol::{Digest256, Observer};
+
+const DISCS: [&'static str; 6] = ["A", "B", "C", "D", "E", "F"];
+const BAR_WIDTH: usize = 40;
+
+struct State {
+    step: String,
+    done: Vec<String>,
+    hashes: Vec<(String, Digest256)>
+}
+
+/// The progress of a computation, as reported by its observer.
+struct Work {
+    /// Elements processed so far, by vector.
+    vectors: HashMap<String, usize>,
+    /// The vector most recently worked on.
+    current: String
+}
+
+impl Work {
+    fn done(&self) -> usize {
+        self.vectors.values().fold(0, |sum, done| sum + done)
+    }
+}
+
+pub struct Ui {
+    state: Mutex<State>,
+    /// Whether the screen is cleared and redrawn. Otherwise, for
+    /// unattended runs whose output is logged, messages are printed one
+    /// after another.
+    redraw: bool
+}
+
+impl Ui {
+    pub fn new(redraw: bool) -> Ui {
+        Ui {
+            state: Mutex::new(State {
+                step: String::new(),
+                done: vec![],
+                hashes: vec![]
+            }),
+            redraw: redraw
+        }
+    }
+
+    pub fn set_step(&self, step: &str) {
+        self.state.lock().unwrap().step = step.into();
+
+        if !self.redraw {
+            println!("== {}", step);
+        }
+    }
+
+    pub fn disc_done(&self, disc: &str) {
+        let mut state = self.state.lock().unwrap();
+
+        if !state.done.iter().any(|d| d == disc) {
+            state.done.push(disc.into());
+        }
+    }
+
+    pub fn record_hash(&self, disc: &str, h: &Digest256) {
+        self.state.lock().unwrap().hashes.push((disc.into(), *h));
+    }
+
+    /// Clears the screen and draws the state of the ceremony at the top.
+    pub fn draw(&self) {
+        if !self.redraw {
+            return;
+        }
+
+        let state = self.state.lock().unwrap();
+
+        print!("{}[2J", 27 as char);
+        print!("{}[1;1H", 27 as char);
+        println!("[MPC] Do not exit this process or shut the system off.");
+        println!("");
+
+        let discs: Vec<String> = DISCS.iter().map(|disc| {
+            format!("[{}] {}", if state.done.iter().any(|d| d == disc) { "x" } else { " " }, disc)
+        }).collect();
+        println!("Discs: {}", discs.join("  "));
+
+        if !state.step.is_empty() {
+            println!("Now:   {}", state.step);
+        }
+
+        if !state.hashes.is_empty() {
+            println!("");
+            println!("Hashes written down:");
+            for &(ref disc, ref h) in &state.hashes {
+                println!("  [x] disc '{}': {}", disc, h.to_string());
+            }
+        }
+
+        println!("");
+    }
+
+    /// Runs `f`, which works through `total` group elements, reporting
+    /// its progress to the observer it is given, and shows that progress
+    /// meanwhile.
+    pub fn work<T, F: FnOnce(&Observer) -> T>(&self, step: &str, total: usize, f: F) -> T {
+        self.set_step(step);
+        self.draw();
+
+        let start = Instant::now();
+        let finished = Arc::new(AtomicBool::new(false));
+        let work = Arc::new(Mutex::new(Work {
+            vectors: HashMap::new(),
+            current: String::new()
+        }));
+        let redraw = self.redraw;
+
+        let ticker = {
+            let finished = finished.clone();
+            let work = work.clone();
+
+            thread::spawn(move || {
+                let mut reported = 0;
+
+                while !finished.load(Ordering::SeqCst) {
+                    let (done, current) = {
+                        let work = work.lock().unwrap();
+                        (work.done(), work.current.clone())
+                    };
+
+                    if redraw {
+                        print!("\r{} {}   ", progress_line(done, total, start.elapsed()), current);
+                        let _ = io::stdout().flush();
+                    } else if total > 0 && done * 10 / total > reported {
+                        reported = done * 10 / total;
+                        println!("{} {}", progress_line(done, total, start.elapsed()), current);
+                    }
+
+                    thread::sleep(Duration::from_secs(1));
+                }
+            })
+        };
+
+        let result = f(&|name: &str, done: usize, _: usize| {
+            let mut work = work.lock().unwrap();
+
+            work.vectors.insert(name.to_string(), done);
+            work.current = name.to_string();
+        });
+
+        finished.store(true, Ordering::SeqCst);
+        let _ = ticker.join();
+
+        if redraw {
+            println!("\r{} {}   ", progress_line(total, total, start.elapsed()), work.lock().unwrap().current);
+        } else {
+            println!("Done in {}.", format_duration(start.elapsed()));
+        }
+
+        result
+    }
+}
+
+fn progress_line(done: usize, total: usize, elapsed: Duration) -> String {
+    let fraction = if total == 0 { 1.0 } else { (done as f64 / total as f64).min(1.0) };
+    let filled = (fraction * BAR_WIDTH as f64) as usize;
+
+    let secs = elapsed.as_secs();
+    let rate = if secs == 0 { 0 } else { done as u64 / secs };
+
+    let remaining = if done == 0 {
+        "estimating time left".to_string()
+    } else if fraction >= 1.0 {
+        "finishing".to_string()
+    } else {
+        let left = elapsed.as_secs() as f64 * (1.0 - fraction) / fraction;
+        format!("about {} left", format_duration(Duration::from_secs(left as u64)))
+    };
+
+    format!("[{}{}] {:3}%  {} elapsed, {}, {} elements/s",
+            (0..filled).map(|_| "#").collect::<String>(),
+            (filled..BAR_WIDTH).map(|_| "-").collect::<String>(),
+            (fraction * 100.0) as usize,
+            format_duration(elapsed),
+            remaining,
+            rate)
+}
+
+fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+
+    format!("{}h{:02}m{:02}s", secs / 3600, (secs / 60) % 60, secs % 60)
+}
+
+#[test]
+fn progress_lines() {
+    let line = progress_line(50, 100, Duration::from_secs(600));
+
+    assert!(line.starts_with("[####################--------------------]  50%"));
+    assert!(line.contains("0h10m00s elapsed, about 0h10m00s left"));
+
+    let line = progress_line(6000, 12000, Duration::from_secs(60));
+
+    assert!(line.ends_with("100 elements/s"));
+}
@@ -1,169 +1,135 @@
 #![allow(non_snake_case, dead_code)]
 
-extern crate bn;
-extern crate rand;
 extern crate snark;
-extern crate crossbeam;
-extern crate rustc_serialize;
-extern crate blake2_rfc;
 extern crate bincode;
-extern crate byteorder;
+extern crate mpc;
 
-#[macro_use]
-mod protocol;
-
-mod consts;
-use self::consts::*;
+use mpc::Meter;
+use mpc::config::Config;
+use mpc::verify::*;
+use mpc::protocol::powersoftau::Accumulator;
 
 use std::fs::File;
-use protocol::*;
+use std::io::{self, Write};
+use std::process;
 use snark::*;
-
 use bincode::SizeLimit::Infinite;
-use bincode::rustc_serialize::{encode_into, decode_from};
+use bincode::rustc_serialize::decode_from;
 
-pub const THREADS: usize = 128;
+const DEFAULT_THREADS: usize = 128;
+const DEFAULT_TRANSCRIPT_PATH: &'static str = "transcript";
 
-fn main() {
-    let mut f = File::open("transcript").unwrap();
+const USAGE: &'static str = "\
+Usage: verifier [options]
 
-    let cs = {
-        if USE_DUMMY_CS {
-            CS::dummy()
-        } else {
-            CS::from_file()
+Options:
+  --config PATH      read options from a file of `key = value` lines
+  --transcript PATH  the transcript to verify (default transcript)
+  --accumulator PATH the powers of tau accumulator stage 1 started from, if any
+  --threads N        threads used for verification (default 128)
+  --resume           continue from the checkpoint left by an earlier run
+  --json             print the report as JSON
+  --dummy-cs         use the small dummy constraint system of a rehearsal";
+
+fn main() {
+    let options = match Config::from_args(std::env::args().skip(1), &["resume", "json", "dummy-cs", "help"], &["transcript", "accumulator", "threads"]) {
+        Ok(options) => options,
+        Err(e) => {
+            println!("{}\n\n{}", e, USAGE);
+            process::exit(1);
         }
     };
 
-    let num_players: usize = decode_from(&mut f, Infinite).unwrap();
-    println!("Number of players: {}", num_players);
-
-    let mut commitments = vec![];
-    let mut pubkeys = vec![];
-    for i in 0..num_players {
-        let comm: Digest256 = decode_from(&mut f, Infinite).unwrap();
-        commitments.push(comm);
-        println!("Player {} commitment: {}", i+1, comm.to_string());
+    if options.flag("help") {
+        println!("{}", USAGE);
+        return;
     }
 
-    // Hash of all the commitments.
-    let hash_of_commitments = Digest512::from(&commitments).unwrap();
+    let resume = options.flag("resume");
+    let json = options.flag("json");
+    let transcript_path = options.get("transcript").unwrap_or(DEFAULT_TRANSCRIPT_PATH).to_string();
+    let checkpoint_path = format!("{}.checkpoint", transcript_path);
 
-    // Hash of the last message
-    let mut last_message_hash = Digest256::from(&commitments).unwrap();
-
-    let mut stage1 = Stage1Contents::new(&cs);
-
-    for i in 0..num_players {
-        let expected_ihash = {
-            let h = digest256_from_parts!(
-                hash_of_commitments,
-                stage1,
-                last_message_hash
-            );
-            println!("Player {} hash of disk A: {}", i+1, h.to_string());
-            h
-        };
-        let pubkey: PublicKey = decode_from(&mut f, Infinite).unwrap();
-
-        if pubkey.hash() != commitments[i] {
-            panic!("Invalid commitment from player {}", i);
+    match options.get_or("threads", DEFAULT_THREADS) {
+        Ok(threads) => mpc::set_threads(threads),
+        Err(e) => {
+            println!("{}\n\n{}", e, USAGE);
+            process::exit(1);
         }
+    }
 
-        let nizks: PublicKeyNizks = decode_from(&mut f, Infinite).unwrap();
-
-        if !nizks.is_valid(&pubkey, &hash_of_commitments) {
-            panic!("Invalid nizks from player {}", i);
+    let mut f = match File::open(&transcript_path) {
+        Ok(f) => f,
+        Err(e) => {
+            println!("could not open transcript {}: {}\n\n{}", transcript_path, e, USAGE);
+            process::exit(1);
         }
+    };
 
-        let new_stage: Stage1Contents = decode_from(&mut f, Infinite).unwrap();
-        if !new_stage.verify_transform(&stage1, &pubkey) {
-            panic!("Invalid stage1 transformation from player {}", i);
+    let cs = {
+        if options.flag("dummy-cs") {
+            CS::dummy()
+        } else {
+            CS::from_file()
         }
+    };
 
-        let ihash: Digest256 = decode_from(&mut f, Infinite).unwrap();
-        assert!(ihash == expected_ihash);
-
-        {
-            last_message_hash = digest256_from_parts!(
-                pubkey,
-                nizks,
-                new_stage,
-                ihash
-            );
-            println!("Player {} hash of disk B: {}", i+1, last_message_hash.to_string());
+    let accumulator = options.get("accumulator").map(|path| {
+        let acc: Result<Accumulator, String> = File::open(path)
+            .map_err(|e| e.to_string())
+            .and_then(|mut f| decode_from(&mut f, Infinite).map_err(|e| e.to_string()));
+
+        match acc {
+            Ok(acc) => acc,
+            Err(e) => {
+                println!("could not read accumulator {}: {}\n\n{}", path, e, USAGE);
+                process::exit(1);
+            }
         }
-
-        stage1 = new_stage;
-        pubkeys.push(pubkey);
+    });
+
+    let checkpoint = if resume { Checkpoint::load(&checkpoint_path) } else { None };
+
+    // Progress goes to stderr so that stdout only contains the report.
+    let mut stderr = io::stderr();
+
+    match checkpoint {
+        Some(ref checkpoint) => {
+            let _ = writeln!(stderr, "Resuming from checkpoint at stage {}, player {}",
+                             checkpoint.stage(), checkpoint.next_player() + 1);
+        },
+        None if resume => {
+            let _ = writeln!(stderr, "No usable checkpoint found, starting from the beginning.");
+        },
+        None => {}
     }
 
-    let mut stage2 = Stage2Contents::new(&cs, &stage1);
-
-    for i in 0..num_players {
-        let expected_ihash = {
-            let h = digest256_from_parts!(
-                stage2,
-                last_message_hash
-            );
-            println!("Player {} hash of disk C: {}", i+1, h.to_string());
-
-            h
-        };
-
-        let new_stage: Stage2Contents = decode_from(&mut f, Infinite).unwrap();
-        if !new_stage.verify_transform(&stage2, &pubkeys[i]) {
-            panic!("Invalid stage2 transformation from player {}", i);
+    let meter = Meter::new();
+    let observer = |name: &str, done: usize, total: usize| {
+        if let Some((percent, rate)) = meter.update(name, done, total) {
+            let _ = writeln!(io::stderr(), "Checking {}: {}% of {} elements ({:.0} elements/s)",
+                             name, percent, total, rate);
         }
+    };
 
-        let ihash: Digest256 = decode_from(&mut f, Infinite).unwrap();
-        assert!(ihash == expected_ihash);
-
-        {
-            last_message_hash = digest256_from_parts!(
-                new_stage,
-                ihash
-            );
+    let powers = accumulator.as_ref().map(|acc| acc.powers());
+    let (report, kp) = verify(&mut f, &cs, powers, checkpoint, &observer, |checkpoint| {
+        let _ = writeln!(io::stderr(), "Verified stage {} of player {}",
+                         checkpoint.stage(), checkpoint.next_player());
 
-            println!("Player {} hash of disk D: {}", i+1, last_message_hash.to_string());
+        if let Err(e) = checkpoint.save(&checkpoint_path) {
+            let _ = writeln!(io::stderr(), "Warning: could not write checkpoint: {}", e);
         }
+    });
 
-        stage2 = new_stage;
+    if json {
+        println!("{}", report.to_json());
+    } else {
+        println!("{}", report);
     }
 
-    let mut stage3 = Stage3Contents::new(&cs, &stage2);
-
-    for i in 0..num_players {
-        let expected_ihash = {
-            let h = digest256_from_parts!(
-                stage3,
-                last_message_hash
-            );
-            println!("Player {} hash of disk E: {}", i+1, h.to_string());
-
-            h
-        };
-
-        let new_stage: Stage3Contents = decode_from(&mut f, Infinite).unwrap();
-        if !new_stage.verify_transform(&stage3, &pubkeys[i]) {
-            panic!("Invalid stage3 transformation from player {}", i);
-        }
-
-        let ihash: Digest256 = decode_from(&mut f, Infinite).unwrap();
-
-        assert!(expected_ihash == ihash);
-
-        {
-            last_message_hash = digest256_from_parts!(
-                new_stage,
-                ihash
-            );
-            println!("Player {} hash of disk F: {}", i+1, last_message_hash.to_string());
-        }
-
-        stage3 = new_stage;
+    match kp {
+        Some(kp) => kp.write_to_disk(),
+        None => process::exit(1)
     }
-
-    let kp = keypair(&cs, &stage1, &stage2, &stage3);
-    kp.write_to_disk();
 }
@@ -0,0 +1,1299 @@
+//! Verification of a ceremony transcript.
+//!
+//! `verify` walks the transcript written by the coordinator and checks,
+//! for every player and every stage, the commitment to their `PublicKey`,
+//! the `PublicKeyNizks`, the transformation of the stage contents and the
+//! hash chain of the discs (A through F) exchanged with the compute node.
+//! Players whose contribution the coordinator rejected are skipped from
+//! then on, once the evidence recorded with the rejection has been
+//! checked.
+//! Rather than stopping with a panic, it produces a `VerificationReport`
+//! that can be published alongside the ceremony.
+
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+
+use protocol::*;
+use transcript::*;
+use snark::*;
+
+use rustc_serialize::Decodable;
+use rustc_serialize::json;
+use bincode::SizeLimit::{Bounded, Infinite};
+use bincode::rustc_serialize::{encode_into, decode_from};
+
+/// Outcome of a single check.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, RustcEncodable, RustcDecodable)]
+pub enum Check {
+    Passed,
+    Failed,
+    /// The check could not be performed, usually because an earlier
+    /// part of the transcript was invalid.
+    Skipped
+}
+
+impl Check {
+    fn from(passed: bool) -> Check {
+        if passed { Check::Passed } else { Check::Failed }
+    }
+
+    pub fn passed(&self) -> bool {
+        *self == Check::Passed
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Check::Passed => write!(f, "ok"),
+            Check::Failed => write!(f, "FAILED"),
+            Check::Skipped => write!(f, "skipped")
+        }
+    }
+}
+
+/// The result of verifying one player's contribution to one stage.
+#[derive(Clone, RustcEncodable, RustcDecodable)]
+pub struct StageReport {
+    pub stage: usize,
+    /// Hash of the disc given to the compute node (A, C or E).
+    pub disc_in: String,
+    /// Hash of the disc returned by the compute node (B, D or F).
+    pub disc_out: Option<String>,
+    /// `verify_transform` of the new stage contents.
+    pub transform: Check,
+    /// The disc hash chain: the player's `ihash` matches the hash of
+    /// the disc they were given.
+    pub disc_hash: Check
+}
+
+impl StageReport {
+    /// Names of the discs exchanged in this stage.
+    pub fn disc_names(&self) -> (&'static str, &'static str) {
+        match self.stage {
+            1 => ("A", "B"),
+            2 => ("C", "D"),
+            _ => ("E", "F")
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.transform.passed() && self.disc_hash.passed()
+    }
+}
+
+/// The result of verifying everything a player contributed.
+#[derive(Clone, RustcEncodable, RustcDecodable)]
+pub struct PlayerReport {
+    pub player: usize,
+    pub commitment: String,
+    /// The `PublicKey` matches the commitment.
+    pub commitment_check: Check,
+    /// The `PublicKeyNizks` are valid for the `PublicKey`.
+    pub nizks: Check,
+    pub stages: Vec<StageReport>,
+    /// Set if the coordinator rejected one of the player's contributions.
+    pub rejected: Option<Rejection>,
+    /// The evidence recorded with the rejection shows the contribution
+    /// to be invalid. `Failed` if it shows a valid contribution, and
+    /// `Skipped` if there is none.
+    pub rejection_evidence: Check
+}
+
+impl PlayerReport {
+    /// Whether everything the player contributed is valid. A player who
+    /// was rejected took no part in that stage or the ones after it.
+    pub fn is_valid(&self) -> bool {
+        let completed = match self.rejected {
+            Some(ref rejection) => rejection.stage - 1,
+            None => 3
+        };
+
+        (completed == 0 || (self.commitment_check.passed() && self.nizks.passed())) &&
+        self.stages.len() == completed &&
+        self.stages.iter().all(|s| s.is_valid())
+    }
+}
+
+#[derive(Clone, RustcEncodable, RustcDecodable)]
+pub struct VerificationReport {
+    pub num_players: usize,
+    pub players: Vec<PlayerReport>,
+    /// Set if the transcript could not be read in its entirety.
+    pub error: Option<String>,
+    /// Whether the whole transcript was read and checked.
+    pub complete: bool
+}
+
+impl VerificationReport {
+    fn new() -> VerificationReport {
+        VerificationReport {
+            num_players: 0,
+            players: vec![],
+            error: None,
+            complete: false
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.complete &&
+        self.error.is_none() &&
+        self.players.len() == self.num_players &&
+        self.players.iter().all(|p| p.is_valid()) &&
+        (1..4).all(|stage| self.contributions(stage) > 0)
+    }
+
+    /// The number of contributions to `stage` that were not rejected.
+    pub fn contributions(&self, stage: usize) -> usize {
+        self.players.iter().filter(|p| p.stages.iter().any(|s| s.stage == stage)).count()
+    }
+
+    /// Whether the transcript is valid, and the evidence of every
+    /// rejection shows that the contribution was invalid, so that no
+    /// player was turned away on the coordinator's word alone.
+    pub fn is_fully_valid(&self) -> bool {
+        self.is_valid() &&
+        self.players.iter().all(|p| p.rejected.is_none() || p.rejection_evidence.passed())
+    }
+
+    pub fn to_json(&self) -> String {
+        format!("{}", json::as_pretty_json(self))
+    }
+}
+
+impl fmt::Display for VerificationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(writeln!(f, "Number of players: {}", self.num_players));
+
+        for p in &self.players {
+            try!(writeln!(f, "Player {} commitment: {}", p.player, p.commitment));
+        }
+
+        for stage in 1..4 {
+            for p in &self.players {
+                for s in p.stages.iter().filter(|s| s.stage == stage) {
+                    let (disc_in, disc_out) = s.disc_names();
+
+                    try!(writeln!(f, "Player {} hash of disk {}: {}", p.player, disc_in, s.disc_in));
+                    if let Some(ref h) = s.disc_out {
+                        try!(writeln!(f, "Player {} hash of disk {}: {}", p.player, disc_out, h));
+                    }
+                    if stage == 1 {
+                        try!(writeln!(f, "Player {} public key matches commitment: {}, nizks: {}",
+                                      p.player, p.commitment_check, p.nizks));
+                    }
+                    try!(writeln!(f, "Player {} stage{} transformation: {}, disc hash chain: {}",
+                                  p.player, stage, s.transform, s.disc_hash));
+                }
+                if let Some(ref rejection) = p.rejected {
+                    if rejection.stage == stage {
+                        try!(writeln!(f, "Player {} was rejected in stage{}: {}, evidence: {}",
+                                      p.player, stage, rejection.reason, p.rejection_evidence));
+                    }
+                }
+            }
+        }
+
+        if let Some(ref e) = self.error {
+            try!(writeln!(f, "Error: {}", e));
+        }
+
+        if self.is_fully_valid() {
+            write!(f, "Transcript is valid.")
+        } else if self.is_valid() {
+            write!(f, "Transcript is valid, but not every rejection is backed by evidence.")
+        } else {
+            write!(f, "Transcript is NOT valid.")
+        }
+    }
+}
+
+/// The stage contents accumulated so far. Earlier stages are kept
+/// around because the final keypair is built from all three.
+#[derive(Clone, RustcEncodable, RustcDecodable)]
+enum Stage {
+    One(Stage1Contents),
+    Two(Stage1Contents, Stage2Contents),
+    Three(Stage1Contents, Stage2Contents, Stage3Contents)
+}
+
+impl Stage {
+    fn number(&self) -> usize {
+        match *self {
+            Stage::One(..) => 1,
+            Stage::Two(..) => 2,
+            Stage::Three(..) => 3
+        }
+    }
+}
+
+/// Everything needed to continue verification from `offset` in the
+/// transcript, starting with player `next_player` of the current stage.
+#[derive(Clone, RustcEncodable, RustcDecodable)]
+pub struct Checkpoint {
+    offset: u64,
+    next_player: usize,
+    commitments: Vec<Digest256>,
+    /// Public keys of the players, or `None` for those rejected in the
+    /// first stage.
+    pubkeys: Vec<Option<PublicKey>>,
+    last_message_hash: Digest256,
+    stage: Stage,
+    report: VerificationReport
+}
+
+impl Checkpoint {
+    pub fn load(path: &str) -> Option<Checkpoint> {
+        let mut f = match File::open(path) {
+            Ok(f) => f,
+            Err(_) => return None
+        };
+
+        decode_from(&mut f, Infinite).ok()
+    }
+
+    /// Writes the checkpoint to a temporary file first so that a crash
+    /// while saving never leaves us with a truncated checkpoint.
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let tmp_path = format!("{}.tmp", path);
+
+        {
+            let mut f = try!(File::create(&tmp_path).map_err(|e| e.to_string()));
+            try!(encode_into(self, &mut f, Infinite).map_err(|e| e.to_string()));
+            try!(f.sync_all().map_err(|e| e.to_string()));
+        }
+
+        fs::rename(&tmp_path, path).map_err(|e| e.to_string())
+    }
+
+    /// The stage currently being verified.
+    pub fn stage(&self) -> usize {
+        self.stage.number()
+    }
+
+    /// The (zero-indexed) player whose contribution is verified next.
+    pub fn next_player(&self) -> usize {
+        self.next_player
+    }
+
+    /// The report for every player verified so far.
+    pub fn report(&self) -> &VerificationReport {
+        &self.report
+    }
+
+    /// Offset in the transcript just past the last verified player.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn commitments(&self) -> &[Digest256] {
+        &self.commitments
+    }
+
+    /// Public keys of the players verified so far, `None` for those
+    /// rejected in the first stage.
+    pub fn pubkeys(&self) -> &[Option<PublicKey>] {
+        &self.pubkeys
+    }
+
+    /// The hash of the last message, which chains into the next disc.
+    pub fn last_message_hash(&self) -> &Digest256 {
+        &self.last_message_hash
+    }
+
+    /// The stage contents after the last verified player, for each
+    /// stage that has begun.
+    pub fn into_stages(self) -> (Stage1Contents, Option<Stage2Contents>, Option<Stage3Contents>) {
+        match self.stage {
+            Stage::One(stage1) => (stage1, None, None),
+            Stage::Two(stage1, stage2) => (stage1, Some(stage2), None),
+            Stage::Three(stage1, stage2, stage3) => (stage1, Some(stage2), Some(stage3))
+        }
+    }
+}
+
+fn position<R: Read + Seek>(f: &mut TranscriptReader<R>) -> Result<u64, String> {
+    f.get_mut().seek(SeekFrom::Current(0)).map_err(|e| e.to_string())
+}
+
+/// Reads the commitments at the start of the transcript and constructs
+/// the initial state of stage 1. A ceremony built upon a powers of tau
+/// accumulator records the stage 1 it started from after the
+/// commitments, which must be a truncation of `accumulator`.
+fn start<R: Read + Seek>(
+    f: &mut TranscriptReader<R>,
+    cs: &CS,
+    accumulator: Option<&Stage1Contents>
+) -> Result<Checkpoint, String>
+{
+    let num_players = f.header().players;
+
+    let mut commitments = vec![];
+    let mut report = VerificationReport::new();
+    report.num_players = num_players;
+
+    for i in 0..num_players {
+        let comm: Digest256 = try!(f.read(RecordType::Commitment));
+        commitments.push(comm);
+        report.players.push(PlayerReport {
+            player: i + 1,
+            commitment: comm.to_string(),
+            commitment_check: Check::Skipped,
+            nizks: Check::Skipped,
+            stages: vec![],
+            rejected: None,
+            rejection_evidence: Check::Skipped
+        });
+    }
+
+    let last_message_hash = Digest256::from(&commitments).unwrap();
+
+    let next = if f.header().is_legacy() { None } else { try!(f.peek_record()) };
+    let fresh = Stage1Contents::new(cs);
+    let initial = match (next, accumulator) {
+        (Some(RecordType::Stage1), Some(accumulator)) => {
+            let initial: Stage1Contents = try!(f.read(RecordType::Stage1));
+
+            if !initial.is_well_formed(&fresh) || !initial.is_truncation_of(accumulator) {
+                return Err("stage 1 does not start from a truncation of the accumulator".into());
+            }
+
+            initial
+        },
+        (Some(RecordType::Stage1), None) => {
+            return Err("stage 1 starts from an accumulator, which is needed to verify it".into());
+        },
+        (_, Some(_)) => {
+            return Err("stage 1 does not start from an accumulator".into());
+        },
+        (_, None) => fresh
+    };
+
+    Ok(Checkpoint {
+        offset: try!(position(f)),
+        next_player: 0,
+        commitments: commitments,
+        pubkeys: vec![],
+        last_message_hash: last_message_hash,
+        stage: Stage::One(initial),
+        report: report
+    })
+}
+
+fn verify_stage1_player<R: Read>(
+    f: &mut TranscriptReader<R>,
+    player: &mut PlayerReport,
+    hash_of_commitments: &Digest512,
+    commitment: &Digest256,
+    stage1: &Stage1Contents,
+    last_message_hash: &Digest256,
+    observer: &Observer
+) -> Result<(PublicKey, Stage1Contents, Digest256), String>
+{
+    let expected_ihash = digest256_from_parts!(
+        hash_of_commitments,
+        stage1,
+        last_message_hash
+    );
+
+    player.stages.push(StageReport {
+        stage: 1,
+        disc_in: expected_ihash.to_string(),
+        disc_out: None,
+        transform: Check::Skipped,
+        disc_hash: Check::Skipped
+    });
+
+    let pubkey: PublicKey = try!(f.read(RecordType::PublicKey));
+    player.commitment_check = Check::from(pubkey.hash() == *commitment);
+
+    let nizks: PublicKeyNizks = try!(f.read(RecordType::PublicKeyNizks));
+    player.nizks = Check::from(nizks.is_valid(&pubkey, hash_of_commitments));
+
+    let new_stage: Stage1Contents = try!(f.read(RecordType::Stage1));
+    let transform = Check::from(new_stage.verify_transform_observed(stage1, &pubkey, observer));
+
+    let ihash: Digest256 = try!(f.read(RecordType::IHash));
+
+    let last_message_hash = digest256_from_parts!(
+        pubkey,
+        nizks,
+        new_stage,
+        ihash
+    );
+
+    {
+        let report = player.stages.last_mut().unwrap();
+        report.disc_out = Some(last_message_hash.to_string());
+        report.transform = transform;
+        report.disc_hash = Check::from(ihash == expected_ihash);
+    }
+
+    Ok((pubkey, new_stage, last_message_hash))
+}
+
+fn verify_stage2_player<R: Read>(
+    f: &mut TranscriptReader<R>,
+    player: &mut PlayerReport,
+    pubkey: &PublicKey,
+    stage2: &Stage2Contents,
+    last_message_hash: &Digest256,
+    observer: &Observer
+) -> Result<(Stage2Contents, Digest256), String>
+{
+    let expected_ihash = digest256_from_parts!(
+        stage2,
+        last_message_hash
+    );
+
+    player.stages.push(StageReport {
+        stage: 2,
+        disc_in: expected_ihash.to_string(),
+        disc_out: None,
+        transform: Check::Skipped,
+        disc_hash: Check::Skipped
+    });
+
+    let new_stage: Stage2Contents = try!(f.read(RecordType::Stage2));
+    let transform = Check::from(new_stage.verify_transform_observed(stage2, pubkey, observer));
+
+    let ihash: Digest256 = try!(f.read(RecordType::IHash));
+
+    let last_message_hash = digest256_from_parts!(
+        new_stage,
+        ihash
+    );
+
+    {
+        let report = player.stages.last_mut().unwrap();
+        report.disc_out = Some(last_message_hash.to_string());
+        report.transform = transform;
+        report.disc_hash = Check::from(ihash == expected_ihash);
+    }
+
+    Ok((new_stage, last_message_hash))
+}
+
+fn verify_stage3_player<R: Read>(
+    f: &mut TranscriptReader<R>,
+    player: &mut PlayerReport,
+    pubkey: &PublicKey,
+    stage3: &Stage3Contents,
+    last_message_hash: &Digest256,
+    observer: &Observer
+) -> Result<(Stage3Contents, Digest256), String>
+{
+    let expected_ihash = digest256_from_parts!(
+        stage3,
+        last_message_hash
+    );
+
+    player.stages.push(StageReport {
+        stage: 3,
+        disc_in: expected_ihash.to_string(),
+        disc_out: None,
+        transform: Check::Skipped,
+        disc_hash: Check::Skipped
+    });
+
+    let new_stage: Stage3Contents = try!(f.read(RecordType::Stage3));
+    let transform = Check::from(new_stage.verify_transform_observed(stage3, pubkey, observer));
+
+    let ihash: Digest256 = try!(f.read(RecordType::IHash));
+
+    let last_message_hash = digest256_from_parts!(
+        new_stage,
+        ihash
+    );
+
+    {
+        let report = player.stages.last_mut().unwrap();
+        report.disc_out = Some(last_message_hash.to_string());
+        report.transform = transform;
+        report.disc_hash = Check::from(ihash == expected_ihash);
+    }
+
+    Ok((new_stage, last_message_hash))
+}
+
+/// Reads the record of a rejected contribution, if that is what the
+/// transcript contains next.
+fn read_rejection<R: Read>(f: &mut TranscriptReader<R>) -> Result<Option<Rejection>, String> {
+    if f.header().is_legacy() {
+        return Ok(None);
+    }
+
+    match try!(f.peek_record()) {
+        Some(RecordType::Rejected) => f.read(RecordType::Rejected).map(Some),
+        _ => Ok(None)
+    }
+}
+
+/// Decodes a message recorded as evidence, as strictly as the
+/// coordinator does.
+fn decode_evidence<T: Decodable>(data: &[u8]) -> Option<T> {
+    let mut r = data;
+
+    match decode_from(&mut r, Bounded(data.len() as u64)) {
+        Ok(obj) => if r.is_empty() { Some(obj) } else { None },
+        Err(_) => None
+    }
+}
+
+/// Checks the evidence recorded with a rejection: one of the messages
+/// must not decode, or the contribution must fail the checks of the
+/// coordinator, given the contents of `stage` it should have
+/// transformed and the player's public key, if it is known already.
+fn check_rejection(
+    rejection: &Rejection,
+    stage: &Stage,
+    commitment: &Digest256,
+    pubkey: Option<&PublicKey>,
+    hash_of_commitments: &Digest512
+) -> Check
+{
+    if rejection.evidence.is_empty() {
+        return Check::Skipped;
+    }
+
+    let mut pubkey = pubkey.cloned();
+
+    for &(ty, ref data) in &rejection.evidence {
+        let invalid = match ty {
+            RecordType::PublicKey => match decode_evidence::<PublicKey>(data) {
+                Some(key) => {
+                    let invalid = key.hash() != *commitment;
+                    pubkey = Some(key);
+
+                    invalid
+                },
+                None => true
+            },
+            RecordType::PublicKeyNizks => match (decode_evidence::<PublicKeyNizks>(data), pubkey.as_ref()) {
+                (Some(nizks), Some(key)) => !nizks.is_valid(key, hash_of_commitments),
+                (Some(_), None) => return Check::Skipped,
+                (None, _) => true
+            },
+            RecordType::Stage1 => match (decode_evidence::<Stage1Contents>(data), stage, pubkey.as_ref()) {
+                (Some(new), &Stage::One(ref prev), Some(key)) => !new.verify_transform(prev, key),
+                (None, _, _) => true,
+                _ => return Check::Skipped
+            },
+            RecordType::Stage2 => match (decode_evidence::<Stage2Contents>(data), stage, pubkey.as_ref()) {
+                (Some(new), &Stage::Two(_, ref prev), Some(key)) => !new.verify_transform(prev, key),
+                (None, _, _) => true,
+                _ => return Check::Skipped
+            },
+            RecordType::Stage3 => match (decode_evidence::<Stage3Contents>(data), stage, pubkey.as_ref()) {
+                (Some(new), &Stage::Three(_, _, ref prev), Some(key)) => !new.verify_transform(prev, key),
+                (None, _, _) => true,
+                _ => return Check::Skipped
+            },
+            RecordType::IHash => decode_evidence::<Digest256>(data).is_none(),
+            RecordType::Commitment | RecordType::Rejected => return Check::Skipped
+        };
+
+        if invalid {
+            return Check::Passed;
+        }
+    }
+
+    // Everything the player sent checks out.
+    Check::Failed
+}
+
+/// Verifies the transcript in `f`, optionally continuing from a
+/// `Checkpoint` produced by an earlier run. `on_player` is called with a
+/// new checkpoint after every player of every stage has been verified,
+/// which the caller may save to disk to resume later. The progress
+/// through each vector of a contribution is reported to `observer`, as
+/// `stage S, player P: VECTOR`.
+///
+/// A ceremony built upon a powers of tau accumulator is verified against
+/// the powers of that accumulator, `accumulator`, whose own transcript
+/// must have been verified separately.
+///
+/// Verification stops at the first player whose contribution is invalid,
+/// since every later contribution builds upon it. The keypair is only
+/// returned if the entire transcript is valid.
+pub fn verify<R: Read + Seek, F: FnMut(&Checkpoint)>(
+    f: &mut R,
+    cs: &CS,
+    accumulator: Option<&Stage1Contents>,
+    resume: Option<Checkpoint>,
+    observer: &Observer,
+    mut on_player: F
+) -> (VerificationReport, Option<Keypair>)
+{
+    let mut f = match TranscriptReader::open(f) {
+        Ok(f) => f,
+        Err(e) => {
+            let mut report = VerificationReport::new();
+            report.error = Some(e);
+
+            return (report, None);
+        }
+    };
+
+    verify_records(&mut f, cs, accumulator, resume, observer, on_player)
+}
+
+/// `verify`, for a transcript whose header has been read.
+fn verify_records<R: Read + Seek, F: FnMut(&Checkpoint)>(
+    f: &mut TranscriptReader<R>,
+    cs: &CS,
+    accumulator: Option<&Stage1Contents>,
+    resume: Option<Checkpoint>,
+    observer: &Observer,
+    mut on_player: F
+) -> (VerificationReport, Option<Keypair>)
+{
+    if let Some(expected) = f.header().cs_hash {
+        if expected != cs_hash(cs) {
+            let mut report = VerificationReport::new();
+            report.error = Some("transcript was produced for a different constraint system".into());
+
+            return (report, None);
+        }
+    }
+
+    let mut state = match resume {
+        Some(checkpoint) => {
+            if let Err(e) = f.get_mut().seek(SeekFrom::Start(checkpoint.offset)) {
+                let mut report = checkpoint.report;
+                report.error = Some(format!("could not seek to checkpoint: {}", e));
+
+                return (report, None);
+            }
+
+            checkpoint
+        },
+        None => {
+            match start(f, cs, accumulator) {
+                Ok(state) => state,
+                Err(e) => {
+                    let mut report = VerificationReport::new();
+                    report.error = Some(e);
+
+                    return (report, None);
+                }
+            }
+        }
+    };
+
+    let num_players = state.commitments.len();
+
+    // Hash of all the commitments.
+    let hash_of_commitments = Digest512::from(&state.commitments).unwrap();
+
+    loop {
+        if state.next_player == num_players {
+            if let Stage::Three(..) = state.stage {
+                break;
+            }
+
+            state.stage = match state.stage {
+                Stage::One(stage1) => {
+                    let stage2 = Stage2Contents::new(cs, &stage1);
+                    Stage::Two(stage1, stage2)
+                },
+                Stage::Two(stage1, stage2) => {
+                    let stage3 = Stage3Contents::new(cs, &stage2);
+                    Stage::Three(stage1, stage2, stage3)
+                },
+                Stage::Three(..) => unreachable!()
+            };
+            state.next_player = 0;
+        }
+
+        let i = state.next_player;
+
+        if state.report.players[i].rejected.is_some() {
+            // Rejected in an earlier stage, so the player has no more
+            // records in the transcript.
+            state.next_player += 1;
+            continue;
+        }
+
+        let rejection = match read_rejection(f) {
+            Ok(rejection) => rejection,
+            Err(e) => {
+                state.report.error = Some(format!("stage {}, player {}: {}", state.stage.number(), i + 1, e));
+
+                return (state.report, None);
+            }
+        };
+
+        if let Some(rejection) = rejection {
+            if rejection.player != i || rejection.stage != state.stage.number() {
+                state.report.error = Some(format!("stage {}, player {}: found the rejection of stage {}, player {}",
+                                                  state.stage.number(), i + 1, rejection.stage, rejection.player + 1));
+
+                return (state.report, None);
+            }
+
+            state.report.players[i].rejection_evidence = check_rejection(
+                &rejection,
+                &state.stage,
+                &state.commitments[i],
+                state.pubkeys.get(i).and_then(|pubkey| pubkey.as_ref()),
+                &hash_of_commitments
+            );
+
+            // The next player builds upon the same stage contents and
+            // message hash as this one did.
+            if rejection.stage == 1 {
+                state.pubkeys.push(None);
+            }
+            state.report.players[i].rejected = Some(rejection);
+        } else {
+            let stage = state.stage.number();
+            let observer = &|name: &str, done: usize, total: usize| {
+                observer.progress(&format!("stage {}, player {}: {}", stage, i + 1, name), done, total)
+            };
+
+            let result = match state.stage {
+                Stage::One(ref mut stage1) => {
+                    verify_stage1_player(
+                        f,
+                        &mut state.report.players[i],
+                        &hash_of_commitments,
+                        &state.commitments[i],
+                        stage1,
+                        &state.last_message_hash,
+                        observer
+                    ).map(|(pubkey, new_stage, h)| {
+                        *stage1 = new_stage;
+                        (Some(pubkey), h)
+                    })
+                },
+                Stage::Two(_, ref mut stage2) => {
+                    verify_stage2_player(
+                        f,
+                        &mut state.report.players[i],
+                        state.pubkeys[i].as_ref().expect("rejected players are skipped"),
+                        stage2,
+                        &state.last_message_hash,
+                        observer
+                    ).map(|(new_stage, h)| {
+                        *stage2 = new_stage;
+                        (None, h)
+                    })
+                },
+                Stage::Three(_, _, ref mut stage3) => {
+                    verify_stage3_player(
+                        f,
+                        &mut state.report.players[i],
+                        state.pubkeys[i].as_ref().expect("rejected players are skipped"),
+                        stage3,
+                        &state.last_message_hash,
+                        observer
+                    ).map(|(new_stage, h)| {
+                        *stage3 = new_stage;
+                        (None, h)
+                    })
+                }
+            };
+
+            match result {
+                Ok((pubkey, h)) => {
+                    if let Some(pubkey) = pubkey {
+                        state.pubkeys.push(Some(pubkey));
+                    }
+                    state.last_message_hash = h;
+                },
+                Err(e) => {
+                    state.report.error = Some(format!("stage {}, player {}: {}", state.stage.number(), i + 1, e));
+
+                    return (state.report, None);
+                }
+            }
+
+            if !state.report.players[i].stages.iter().all(|s| s.is_valid()) ||
+               (state.stage.number() == 1 && !(state.report.players[i].commitment_check.passed() &&
+                                               state.report.players[i].nizks.passed()))
+            {
+                // Later contributions build upon this one, so there is
+                // nothing more to check.
+                return (state.report, None);
+            }
+        }
+
+        state.next_player += 1;
+        state.offset = match position(f) {
+            Ok(offset) => offset,
+            Err(e) => {
+                state.report.error = Some(e);
+
+                return (state.report, None);
+            }
+        };
+
+        on_player(&state);
+    }
+
+    state.report.complete = true;
+
+    let kp = match state.stage {
+        Stage::Three(ref stage1, ref stage2, ref stage3) => {
+            if state.report.is_valid() {
+                Some(keypair(cs, stage1, stage2, stage3))
+            } else {
+                None
+            }
+        },
+        _ => unreachable!()
+    };
+
+    (state.report, kp)
+}
+
+/// How far an unfinished ceremony got, according to its transcript.
+pub enum Progress {
+    /// Not every player has committed yet. Contains the commitments
+    /// received so far and the offset just past the last of them.
+    Commitments(Vec<Digest256>, u64),
+    /// Every player has committed, and the contributions up to the
+    /// checkpoint are valid.
+    Started(Checkpoint),
+    /// The transcript is complete and valid.
+    Complete
+}
+
+/// Replays a transcript that may have been cut short, such as one left
+/// behind by a coordinator that crashed, so that the ceremony can be
+/// continued. A contribution the transcript ends within is ignored; an
+/// invalid contribution, or a record that does not decode, is an error.
+/// A ceremony built upon an accumulator is replayed with its powers,
+/// `accumulator`, as in `verify`.
+pub fn replay<R: Read + Seek>(f: &mut R, cs: &CS, accumulator: Option<&Stage1Contents>) -> Result<Progress, String> {
+    let (commitments, offset) = {
+        let mut reader = try!(TranscriptReader::open(&mut *f));
+
+        if reader.header().is_legacy() {
+            return Err("cannot continue a transcript in the legacy layout".into());
+        }
+        if reader.header().cs_hash != Some(cs_hash(cs)) {
+            return Err("transcript was produced for a different constraint system".into());
+        }
+
+        let num_players = reader.header().players;
+        let mut commitments = vec![];
+        let mut offset = try!(position(&mut reader));
+
+        while commitments.len() < num_players {
+            match reader.read::<Digest256>(RecordType::Commitment) {
+                Ok(comm) => {
+                    commitments.push(comm);
+                    offset = try!(position(&mut reader));
+                },
+                Err(_) if reader.ended() => return Ok(Progress::Commitments(commitments, offset)),
+                Err(e) => return Err(format!("commitment {}: {}", commitments.len() + 1, e))
+            }
+        }
+
+        (commitments, offset)
+    };
+
+    try!(f.seek(SeekFrom::Start(0)).map_err(|e| e.to_string()));
+
+    let mut reader = try!(TranscriptReader::open(&mut *f));
+    let initial = match start(&mut reader, cs, accumulator) {
+        Ok(initial) => initial,
+        // The stage 1 a ceremony starts from is written once every
+        // player has committed, so the transcript may end before it.
+        Err(_) if reader.ended() => return Ok(Progress::Commitments(commitments, offset)),
+        Err(e) => return Err(e)
+    };
+
+    let mut last = None;
+    let (report, _) = verify_records(&mut reader, cs, accumulator, Some(initial.clone()), &quiet, |checkpoint| {
+        last = Some(checkpoint.clone());
+    });
+
+    if report.is_valid() {
+        Ok(Progress::Complete)
+    } else if report.error.is_some() && reader.ended() {
+        // The transcript ends within the contribution after the last
+        // checkpoint.
+        Ok(Progress::Started(last.unwrap_or(initial)))
+    } else {
+        Err(format!("transcript is not valid:\n{}", report))
+    }
+}
+
+/// Writes the transcript of a ceremony in which the coordinator rejects
+/// the contributions of the (player, stage) pairs in `rejected`, with
+/// stage 1 starting from `initial` if it is given.
+#[cfg(test)]
+fn dummy_transcript(cs: &CS, players: usize, rejected: &[(usize, usize)], initial: Option<&Stage1Contents>) -> Vec<u8> {
+    use bincode::rustc_serialize::encode;
+
+    let rng = &mut ::rand::thread_rng();
+
+    let privkeys: Vec<_> = (0..players).map(|_| PrivateKey::new(rng)).collect();
+    let pubkeys: Vec<_> = privkeys.iter().map(|p| p.pubkey(rng)).collect();
+    let commitments: Vec<_> = pubkeys.iter().map(|p| p.hash()).collect();
+
+    let mut transcript = TranscriptWriter::new(vec![], &Header::new(cs_hash(cs), players)).unwrap();
+    for comm in &commitments {
+        transcript.write(RecordType::Commitment, comm).unwrap();
+    }
+    if let Some(initial) = initial {
+        transcript.write(RecordType::Stage1, initial).unwrap();
+    }
+
+    let mut dropped = vec![];
+    // Rejected players are recorded with a contribution transformed by
+    // some other key than their own.
+    let mut turn = |transcript: &mut TranscriptWriter<Vec<u8>>, player: usize, stage: usize, evidence: &Fn() -> Evidence| -> bool {
+        if dropped.contains(&player) {
+            return false;
+        }
+
+        if rejected.contains(&(player, stage)) {
+            dropped.push(player);
+            transcript.write(RecordType::Rejected, &Rejection {
+                player: player,
+                stage: stage,
+                reason: "test".into(),
+                evidence: evidence(),
+                retried: None
+            }).unwrap();
+
+            return false;
+        }
+
+        true
+    };
+
+    let hash_of_commitments = Digest512::from(&commitments).unwrap();
+    let mut last_message_hash = Digest256::from(&commitments).unwrap();
+
+    let mut stage1 = initial.cloned().unwrap_or_else(|| Stage1Contents::new(cs));
+    for (i, (privkey, pubkey)) in privkeys.iter().zip(pubkeys.iter()).enumerate() {
+        if !turn(&mut transcript, i, 1, &|| {
+            let mut invalid = stage1.clone();
+            invalid.transform(&PrivateKey::new(&mut ::rand::thread_rng()));
+
+            vec![(RecordType::PublicKey, encode(pubkey, Infinite).unwrap()),
+                 (RecordType::Stage1, encode(&invalid, Infinite).unwrap())]
+        }) {
+            continue;
+        }
+
+        let nizks = pubkey.nizks(rng, privkey, &hash_of_commitments);
+        let ihash = digest256_from_parts!(hash_of_commitments, stage1, last_message_hash);
+        stage1.transform(privkey);
+
+        transcript.write(RecordType::PublicKey, pubkey).unwrap();
+        transcript.write(RecordType::PublicKeyNizks, &nizks).unwrap();
+        transcript.write(RecordType::Stage1, &stage1).unwrap();
+        transcript.write(RecordType::IHash, &ihash).unwrap();
+
+        last_message_hash = digest256_from_parts!(pubkey, nizks, stage1, ihash);
+    }
+
+    let mut stage2 = Stage2Contents::new(cs, &stage1);
+    for (i, privkey) in privkeys.iter().enumerate() {
+        if !turn(&mut transcript, i, 2, &|| {
+            let mut invalid = stage2.clone();
+            invalid.transform(&PrivateKey::new(&mut ::rand::thread_rng()));
+
+            vec![(RecordType::Stage2, encode(&invalid, Infinite).unwrap())]
+        }) {
+            continue;
+        }
+
+        let ihash = digest256_from_parts!(stage2, last_message_hash);
+        stage2.transform(privkey);
+
+        transcript.write(RecordType::Stage2, &stage2).unwrap();
+        transcript.write(RecordType::IHash, &ihash).unwrap();
+
+        last_message_hash = digest256_from_parts!(stage2, ihash);
+    }
+
+    let mut stage3 = Stage3Contents::new(cs, &stage2);
+    for (i, privkey) in privkeys.iter().enumerate() {
+        if !turn(&mut transcript, i, 3, &|| {
+            let mut invalid = stage3.clone();
+            invalid.transform(&PrivateKey::new(&mut ::rand::thread_rng()));
+
+            vec![(RecordType::Stage3, encode(&invalid, Infinite).unwrap())]
+        }) {
+            continue;
+        }
+
+        let ihash = digest256_from_parts!(stage3, last_message_hash);
+        stage3.transform(privkey);
+
+        transcript.write(RecordType::Stage3, &stage3).unwrap();
+        transcript.write(RecordType::IHash, &ihash).unwrap();
+
+        last_message_hash = digest256_from_parts!(stage3, ihash);
+    }
+
+    transcript.into_inner()
+}
+
+#[test]
+fn verify_dummy_transcript() {
+    use std::io::Cursor;
+    use bincode::rustc_serialize::{encode, decode};
+
+    let cs = CS::dummy();
+    let transcript = dummy_transcript(&cs, 3, &[], None);
+
+    let mut checkpoints = vec![];
+    let (report, kp) = verify(&mut Cursor::new(&transcript[..]), &cs, None, None, &quiet, |c| {
+        checkpoints.push(encode(c, Infinite).unwrap());
+    });
+
+    assert!(report.is_valid());
+    assert!(kp.is_some());
+    assert_eq!(report.players.len(), 3);
+    assert_eq!(checkpoints.len(), 9);
+
+    // Resume from the first player of stage 2.
+    let checkpoint: Checkpoint = decode(&checkpoints[3]).unwrap();
+    assert_eq!(checkpoint.stage(), 2);
+    assert_eq!(checkpoint.next_player(), 1);
+
+    let (report, kp) = verify(&mut Cursor::new(&transcript[..]), &cs, None, Some(checkpoint), &quiet, |_| {});
+
+    assert!(report.is_valid());
+    assert!(kp.is_some());
+    assert!(report.players.iter().all(|p| p.stages.len() == 3));
+
+    // A truncated transcript is reported, not panicked on.
+    let (report, kp) = verify(&mut Cursor::new(&transcript[0..transcript.len() - 1]), &cs, None, None, &quiet, |_| {});
+
+    assert!(!report.is_valid());
+    assert!(report.error.is_some());
+    assert!(kp.is_none());
+}
+
+#[test]
+fn replay_partial_transcript() {
+    use std::io::Cursor;
+    use transcript::TranscriptIndex;
+
+    let cs = CS::dummy();
+    let transcript = dummy_transcript(&cs, 2, &[], None);
+    let index = TranscriptIndex::build(&mut Cursor::new(&transcript[..])).unwrap();
+
+    match replay(&mut Cursor::new(&transcript[..]), &cs, None).unwrap() {
+        Progress::Complete => {},
+        _ => panic!("expected a complete transcript")
+    }
+
+    // Cut short in the middle of the second commitment.
+    let second = index.find(0, 1, RecordType::Commitment).unwrap().clone();
+    match replay(&mut Cursor::new(&transcript[0..(second.offset as usize + 1)]), &cs, None).unwrap() {
+        Progress::Commitments(commitments, offset) => {
+            assert_eq!(commitments.len(), 1);
+            assert_eq!(offset, second.offset - 9);
+        },
+        _ => panic!("expected to be collecting commitments")
+    }
+
+    // Cut short in the middle of the second player's stage2.
+    let stage2 = index.find(2, 1, RecordType::Stage2).unwrap().clone();
+    match replay(&mut Cursor::new(&transcript[0..(stage2.offset as usize + 10)]), &cs, None).unwrap() {
+        Progress::Started(checkpoint) => {
+            assert_eq!(checkpoint.stage(), 2);
+            assert_eq!(checkpoint.next_player(), 1);
+            assert_eq!(checkpoint.offset(), stage2.offset - 9);
+            assert_eq!(checkpoint.commitments().len(), 2);
+
+            let (_, stage2, stage3) = checkpoint.into_stages();
+            assert!(stage2.is_some());
+            assert!(stage3.is_none());
+        },
+        _ => panic!("expected the ceremony to have started")
+    }
+
+    // A record in the middle that is corrupt, rather than cut short,
+    // must not be mistaken for the end of the transcript.
+    let mut corrupt = transcript.clone();
+    corrupt[stage2.offset as usize - 9] = 0xff;
+    assert!(replay(&mut Cursor::new(&corrupt[..]), &cs, None).is_err());
+
+    let mut corrupt = transcript.clone();
+    corrupt[stage2.offset as usize] ^= 0xff;
+    assert!(replay(&mut Cursor::new(&corrupt[..]), &cs, None).is_err());
+}
+
+#[test]
+fn replay_from_accumulator() {
+    use std::io::Cursor;
+    use protocol::powersoftau;
+    use transcript::TranscriptIndex;
+
+    let cs = CS::dummy();
+    let rng = &mut ::rand::thread_rng();
+
+    let mut k = 0;
+    while (1 << k) < cs.d {
+        k += 1;
+    }
+    let mut acc = powersoftau::Accumulator::new(k);
+    acc.transform(&powersoftau::PrivateKey::new(rng));
+
+    let transcript = dummy_transcript(&cs, 2, &[], Some(&acc.truncate(&cs).unwrap()));
+    let index = TranscriptIndex::build(&mut Cursor::new(&transcript[..])).unwrap();
+
+    match replay(&mut Cursor::new(&transcript[..]), &cs, Some(acc.powers())).unwrap() {
+        Progress::Complete => {},
+        _ => panic!("expected a complete transcript")
+    }
+
+    // Without the accumulator, stage 1 cannot be replayed.
+    assert!(replay(&mut Cursor::new(&transcript[..]), &cs, None).is_err());
+
+    // Cut short in the middle of the stage 1 the ceremony starts from,
+    // which is written once every player has committed.
+    let initial = index.find(0, 0, RecordType::Stage1).unwrap().clone();
+    match replay(&mut Cursor::new(&transcript[0..(initial.offset as usize + 10)]), &cs, Some(acc.powers())).unwrap() {
+        Progress::Commitments(commitments, offset) => {
+            assert_eq!(commitments.len(), 2);
+            assert_eq!(offset, initial.offset - 9);
+        },
+        _ => panic!("expected every player to have committed")
+    }
+
+    // Cut short in the middle of the first player's stage 1.
+    let stage1 = index.find(1, 0, RecordType::Stage1).unwrap().clone();
+    match replay(&mut Cursor::new(&transcript[0..(stage1.offset as usize + 10)]), &cs, Some(acc.powers())).unwrap() {
+        Progress::Started(checkpoint) => {
+            assert_eq!(checkpoint.stage(), 1);
+            assert_eq!(checkpoint.next_player(), 0);
+            assert_eq!(checkpoint.offset(), initial.offset + initial.len);
+        },
+        _ => panic!("expected the ceremony to have started")
+    }
+}
+
+#[test]
+fn verify_rejected_players() {
+    use std::io::Cursor;
+    use transcript::TranscriptIndex;
+
+    let cs = CS::dummy();
+
+    // The second player is rejected in stage 1, the third in stage 3.
+    let transcript = dummy_transcript(&cs, 4, &[(1, 1), (2, 3)], None);
+
+    let (report, kp) = verify(&mut Cursor::new(&transcript[..]), &cs, None, None, &quiet, |_| {});
+    assert!(report.is_valid());
+    assert!(kp.is_some());
+    assert_eq!(report.players[1].stages.len(), 0);
+    assert_eq!(report.players[2].stages.len(), 2);
+    assert_eq!(report.players[2].rejected.as_ref().unwrap().stage, 3);
+    assert_eq!(report.contributions(1), 3);
+    assert_eq!(report.contributions(3), 2);
+    assert!(report.players[1].rejection_evidence.passed());
+    assert!(report.players[2].rejection_evidence.passed());
+    assert!(report.is_fully_valid());
+
+    let index = TranscriptIndex::build(&mut Cursor::new(&transcript[..])).unwrap();
+    assert!(index.find(1, 1, RecordType::Rejected).is_some());
+    assert!(index.find(2, 1, RecordType::Stage2).is_none());
+    assert!(index.find(2, 3, RecordType::Stage2).is_some());
+    assert!(index.find(3, 2, RecordType::Rejected).is_some());
+    assert!(index.find(3, 3, RecordType::Stage3).is_some());
+
+    // Without a single accepted contribution to a stage, the ceremony
+    // is worthless.
+    let transcript = dummy_transcript(&cs, 2, &[(0, 2), (1, 2)], None);
+
+    let (report, kp) = verify(&mut Cursor::new(&transcript[..]), &cs, None, None, &quiet, |_| {});
+    assert!(report.complete);
+    assert!(!report.is_valid());
+    assert!(kp.is_none());
+}
+
+#[test]
+fn verify_from_accumulator() {
+    use std::io::Cursor;
+    use protocol::powersoftau;
+
+    let cs = CS::dummy();
+    let rng = &mut ::rand::thread_rng();
+
+    let mut k = 0;
+    while (1 << k) < cs.d {
+        k += 1;
+    }
+    let mut acc = powersoftau::Accumulator::new(k);
+    acc.transform(&powersoftau::PrivateKey::new(rng));
+
+    let transcript = dummy_transcript(&cs, 2, &[], Some(&acc.truncate(&cs).unwrap()));
+
+    let (report, kp) = verify(&mut Cursor::new(&transcript[..]), &cs, Some(acc.powers()), None, &quiet, |_| {});
+    assert!(report.is_valid());
+    assert!(kp.is_some());
+
+    // Stage 1 of such a transcript cannot be verified without the
+    // accumulator, nor one started afresh with it.
+    let (report, kp) = verify(&mut Cursor::new(&transcript[..]), &cs, None, None, &quiet, |_| {});
+    assert!(!report.is_valid());
+    assert!(kp.is_none());
+
+    let fresh = dummy_transcript(&cs, 2, &[], None);
+    let (report, kp) = verify(&mut Cursor::new(&fresh[..]), &cs, Some(acc.powers()), None, &quiet, |_| {});
+    assert!(!report.is_valid());
+    assert!(kp.is_none());
+
+    // The powers stage 1 started from were not those of the accumulator.
+    let mut tampered = acc.clone();
+    tampered.transform(&powersoftau::PrivateKey::new(rng));
+    let transcript = dummy_transcript(&cs, 2, &[], Some(&tampered.truncate(&cs).unwrap()));
+
+    let (report, kp) = verify(&mut Cursor::new(&transcript[..]), &cs, Some(acc.powers()), None, &quiet, |_| {});
+    assert!(!report.is_valid());
+    assert!(report.error.is_some());
+    assert!(kp.is_none());
+}
+
+#[test]
+fn rejection_evidence() {
+    use bincode::rustc_serialize::encode;
+
+    let cs = CS::dummy();
+    let rng = &mut ::rand::thread_rng();
+
+    let privkey = PrivateKey::new(rng);
+    let pubkey = privkey.pubkey(rng);
+    let hash_of_commitments = Digest512::from(&vec![pubkey.hash()]).unwrap();
+    let nizks = pubkey.nizks(rng, &privkey, &hash_of_commitments);
+
+    let stage1 = Stage1Contents::new(&cs);
+    let mut valid = stage1.clone();
+    valid.transform(&privkey);
+    let mut invalid = stage1.clone();
+    invalid.transform(&PrivateKey::new(rng));
+
+    let prev = Stage::One(stage1);
+    let check = |evidence: Evidence| check_rejection(
+        &Rejection { player: 0, stage: 1, reason: "test".into(), evidence: evidence, retried: None },
+        &prev,
+        &pubkey.hash(),
+        None,
+        &hash_of_commitments
+    );
+    let record = |ty, obj: &Stage1Contents| (ty, encode(obj, Infinite).unwrap());
+    let key = (RecordType::PublicKey, encode(&pubkey, Infinite).unwrap());
+    let key_nizks = (RecordType::PublicKeyNizks, encode(&nizks, Infinite).unwrap());
+
+    assert_eq!(check(vec![]), Check::Skipped);
+    assert_eq!(check(vec![key.clone(), key_nizks.clone(), record(RecordType::Stage1, &invalid)]), Check::Passed);
+    assert_eq!(check(vec![key.clone(), (RecordType::PublicKeyNizks, vec![0xff; 64])]), Check::Passed);
+    let other = PrivateKey::new(rng).pubkey(rng);
+    assert_eq!(check(vec![(RecordType::PublicKey, encode(&other, Infinite).unwrap())]), Check::Passed);
+
+    // The coordinator rejected a valid contribution.
+    assert_eq!(check(vec![key.clone(), key_nizks.clone(), record(RecordType::Stage1, &valid)]), Check::Failed);
+}
@@ -0,0 +1,592 @@
+//! Framing of the messages exchanged by the coordinator and the players'
+//! network clients.
+//!
+//! Every message is sent in a frame consisting of a one byte message
+//! type, the sender's sequence number and the length of the payload as
+//! big-endian `u64`s, the payload itself (a bincode-encoded protocol
+//! message), and a BLAKE2s checksum of all of the above. Sequence numbers
+//! count the messages exchanged in either direction, so each side knows
+//! the number of the next message it should receive, and a frame that is
+//! out of order, of the wrong type or corrupted is refused rather than
+//! misinterpreted.
+//!
+//! Messages are carried by transfers, so that the stage contents, which
+//! are hundreds of megabytes for the Sprout circuit, need not be sent
+//! again from the start when a connection drops. A transfer begins with
+//! a `Transfer` frame announcing the type, length and BLAKE2s hash of the
+//! encoded message, to which the receiver replies with an `Ack` of the
+//! number of bytes it already holds from an earlier attempt. The message
+//! then follows in `Chunk` frames, each checksummed and acknowledged in
+//! turn; the final `Ack` acknowledges the whole message.
+
+use std::io::{self, Read, Write};
+
+use blake2_rfc::blake2s::{Blake2s, blake2s};
+use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
+use rustc_serialize::{Encodable, Decodable};
+use bincode::SizeLimit::{Bounded, Infinite};
+use bincode::rustc_serialize::{encode_into, decode_from, encoded_size};
+
+const HEADER_LEN: usize = 17;
+const CHECKSUM_LEN: usize = 32;
+
+/// The amount of a message sent in each `Chunk` frame.
+pub const CHUNK_SIZE: usize = 1 << 20;
+
+/// How many times a message may arrive in full but not match its hash
+/// before it is refused.
+pub const MAX_MISMATCHES: usize = 3;
+
+/// The type of a message, which the receiver checks against the message
+/// it expects next.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MessageType {
+    Commitment,
+    /// The hash of every player's commitment, sent with the stage 1
+    /// request.
+    CommitmentsHash,
+    /// The hash of the previous message in the transcript, sent with
+    /// every stage request.
+    PrevMessageHash,
+    PublicKey,
+    PublicKeyNizks,
+    Stage1,
+    Stage2,
+    Stage3,
+    IHash,
+    /// Sent after each of the player's turns: whether they must take it
+    /// again, upon the contents that follow, because the contribution
+    /// they built upon was rejected.
+    Retry,
+    /// Announces a message sent in chunks, see `Outgoing`.
+    Transfer,
+    Chunk,
+    /// Acknowledges receipt of the bytes of a message so far.
+    Ack
+}
+
+impl MessageType {
+    pub fn tag(&self) -> u8 {
+        match *self {
+            MessageType::Commitment => 0,
+            MessageType::CommitmentsHash => 1,
+            MessageType::PrevMessageHash => 2,
+            MessageType::PublicKey => 3,
+            MessageType::PublicKeyNizks => 4,
+            MessageType::Stage1 => 5,
+            MessageType::Stage2 => 6,
+            MessageType::Stage3 => 7,
+            MessageType::IHash => 8,
+            MessageType::Transfer => 9,
+            MessageType::Chunk => 10,
+            MessageType::Ack => 11,
+            MessageType::Retry => 12
+        }
+    }
+
+    pub fn from_tag(tag: u8) -> Option<MessageType> {
+        match tag {
+            0 => Some(MessageType::Commitment),
+            1 => Some(MessageType::CommitmentsHash),
+            2 => Some(MessageType::PrevMessageHash),
+            3 => Some(MessageType::PublicKey),
+            4 => Some(MessageType::PublicKeyNizks),
+            5 => Some(MessageType::Stage1),
+            6 => Some(MessageType::Stage2),
+            7 => Some(MessageType::Stage3),
+            8 => Some(MessageType::IHash),
+            9 => Some(MessageType::Transfer),
+            10 => Some(MessageType::Chunk),
+            11 => Some(MessageType::Ack),
+            12 => Some(MessageType::Retry),
+            _ => None
+        }
+    }
+}
+
+/// Passes through everything written or read, hashing it on the way.
+struct Checksummed<S> {
+    s: S,
+    hasher: Blake2s
+}
+
+impl<S> Checksummed<S> {
+    fn new(s: S) -> Checksummed<S> {
+        Checksummed {
+            s: s,
+            hasher: Blake2s::new(CHECKSUM_LEN)
+        }
+    }
+
+    fn checksum(self) -> (S, [u8; CHECKSUM_LEN]) {
+        let mut checksum = [0; CHECKSUM_LEN];
+        checksum.copy_from_slice(self.hasher.finalize().as_bytes());
+
+        (self.s, checksum)
+    }
+}
+
+impl<S: Read> Read for Checksummed<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = try!(self.s.read(buf));
+        self.hasher.update(&buf[0..n]);
+
+        Ok(n)
+    }
+}
+
+impl<S: Write> Write for Checksummed<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = try!(self.s.write(buf));
+        self.hasher.update(&buf[0..n]);
+
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.s.flush()
+    }
+}
+
+/// Writes `obj` in a frame of type `ty` with sequence number `seq`.
+pub fn write_frame<W: Write, T: Encodable>(w: &mut W, ty: MessageType, seq: u64, obj: &T) -> Result<(), String> {
+    let mut w = Checksummed::new(w);
+
+    try!(w.write_u8(ty.tag()).map_err(|e| e.to_string()));
+    try!(w.write_u64::<BigEndian>(seq).map_err(|e| e.to_string()));
+    try!(w.write_u64::<BigEndian>(encoded_size(obj)).map_err(|e| e.to_string()));
+    try!(encode_into(obj, &mut w, Infinite).map_err(|e| e.to_string()));
+
+    let (w, checksum) = w.checksum();
+    w.write_all(&checksum).map_err(|e| e.to_string())
+}
+
+/// Reads a frame, which must be of type `ty` and have sequence number
+/// `seq`, and decodes its payload. Payloads longer than `limit` bytes are
+/// refused before they are read.
+pub fn read_frame<R: Read, T: Decodable>(r: &mut R, ty: MessageType, seq: u64, limit: u64) -> Result<T, String> {
+    let mut r = Checksummed::new(r);
+
+    let mut header = [0; HEADER_LEN];
+    try!(r.read_exact(&mut header).map_err(|e| e.to_string()));
+
+    let found = try!(MessageType::from_tag(header[0]).ok_or_else(|| format!("unknown message type {}", header[0])));
+    let found_seq = BigEndian::read_u64(&header[1..9]);
+    let len = BigEndian::read_u64(&header[9..17]);
+
+    if found != ty {
+        return Err(format!("expected {:?} message, received {:?}", ty, found));
+    }
+    if found_seq != seq {
+        return Err(format!("expected message {}, received message {}", seq, found_seq));
+    }
+    if len > limit {
+        return Err(format!("{:?} message of {} bytes exceeds the limit of {} bytes", ty, len, limit));
+    }
+
+    let obj = {
+        let mut payload = (&mut r).take(len);
+        let obj = try!(decode_from(&mut payload, Bounded(len))
+                       .map_err(|e| format!("could not decode {:?} message: {}", ty, e)));
+
+        if payload.limit() != 0 {
+            return Err(format!("{:?} message is longer than its contents", ty));
+        }
+
+        obj
+    };
+
+    let (r, expected) = r.checksum();
+    let mut checksum = [0; CHECKSUM_LEN];
+    try!(r.read_exact(&mut checksum).map_err(|e| e.to_string()));
+
+    if checksum != expected {
+        return Err(format!("{:?} message has an invalid checksum", ty));
+    }
+
+    Ok(obj)
+}
+
+/// Acknowledges receipt of the first `received` bytes of message `seq`.
+pub fn write_ack<W: Write>(w: &mut W, seq: u64, received: u64) -> Result<(), String> {
+    try!(write_frame(w, MessageType::Ack, seq, &received));
+
+    w.flush().map_err(|e| e.to_string())
+}
+
+/// Waits for the other side to acknowledge message `seq`, returning the
+/// number of bytes of it they hold.
+pub fn read_ack<R: Read>(r: &mut R, seq: u64) -> Result<u64, String> {
+    read_frame(r, MessageType::Ack, seq, 8)
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+struct Transfer {
+    ty: u8,
+    len: u64,
+    hash: [u8; CHECKSUM_LEN]
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+struct Chunk {
+    offset: u64,
+    data: Vec<u8>
+}
+
+fn hash(data: &[u8]) -> [u8; CHECKSUM_LEN] {
+    let mut hash = [0; CHECKSUM_LEN];
+    hash.copy_from_slice(blake2s(CHECKSUM_LEN, &[], data).as_bytes());
+
+    hash
+}
+
+/// A message to be sent in chunks. It is encoded once, and `send` can be
+/// called again after a connection failure to resume the transfer.
+pub struct Outgoing {
+    ty: MessageType,
+    data: Vec<u8>,
+    hash: [u8; CHECKSUM_LEN]
+}
+
+impl Outgoing {
+    pub fn new<T: Encodable>(ty: MessageType, obj: &T) -> Result<Outgoing, String> {
+        let mut data = Vec::with_capacity(encoded_size(obj) as usize);
+        try!(encode_into(obj, &mut data, Infinite).map_err(|e| e.to_string()));
+
+        Ok(Outgoing {
+            ty: ty,
+            hash: hash(&data),
+            data: data
+        })
+    }
+
+    pub fn len(&self) -> u64 {
+        self.data.len() as u64
+    }
+
+    /// Sends the message as message `seq`, starting from wherever the
+    /// receiver got to before. `progress` is called with the number of
+    /// bytes the receiver has acknowledged.
+    pub fn send<S: Read + Write, F: FnMut(u64, u64)>(&self, s: &mut S, seq: u64, mut progress: F) -> Result<(), String> {
+        let transfer = Transfer {
+            ty: self.ty.tag(),
+            len: self.len(),
+            hash: self.hash
+        };
+        try!(write_frame(s, MessageType::Transfer, seq, &transfer));
+        try!(s.flush().map_err(|e| e.to_string()));
+
+        let mut offset = try!(read_ack(s, seq));
+        progress(offset, self.len());
+
+        while offset < self.len() {
+            let end = ::std::cmp::min(offset as usize + CHUNK_SIZE, self.data.len());
+            let chunk = Chunk {
+                offset: offset,
+                data: self.data[(offset as usize)..end].to_vec()
+            };
+            try!(write_frame(s, MessageType::Chunk, seq, &chunk));
+            try!(s.flush().map_err(|e| e.to_string()));
+
+            let received = try!(read_ack(s, seq));
+            if received != end as u64 {
+                return Err(format!("expected acknowledgement of {} bytes, received {}", end, received));
+            }
+
+            offset = received;
+            progress(offset, self.len());
+        }
+
+        Ok(())
+    }
+}
+
+/// A message being received in chunks. What has been received is kept
+/// across calls to `receive`, so that a transfer interrupted by a
+/// connection failure can be resumed.
+pub struct Incoming {
+    ty: MessageType,
+    limit: u64,
+    /// The sequence number and description of the transfer in progress.
+    transfer: Option<(u64, Transfer)>,
+    data: Vec<u8>,
+    /// How many times the message has not matched its hash.
+    mismatches: usize,
+    /// Why the message was refused, if it can never be accepted.
+    refused: Option<String>
+}
+
+impl Incoming {
+    /// Expects a message of type `ty`, refusing it if it is longer than
+    /// `limit` bytes.
+    pub fn new(ty: MessageType, limit: u64) -> Incoming {
+        Incoming {
+            ty: ty,
+            limit: limit,
+            transfer: None,
+            data: vec![],
+            mismatches: 0,
+            refused: None
+        }
+    }
+
+    /// Receives message `seq`, or the rest of it. `progress` is called
+    /// with the number of bytes received so far.
+    pub fn receive<S: Read + Write, F: FnMut(u64, u64)>(&mut self, s: &mut S, seq: u64, mut progress: F) -> Result<(), String> {
+        let transfer: Transfer = try!(read_frame(s, MessageType::Transfer, seq, 1024));
+
+        if transfer.ty != self.ty.tag() {
+            return Err(format!("expected {:?} message, received {:?}", self.ty,
+                               MessageType::from_tag(transfer.ty)));
+        }
+        if transfer.len > self.limit {
+            let reason = format!("{:?} message of {} bytes exceeds the limit of {} bytes",
+                                 self.ty, transfer.len, self.limit);
+            self.refused = Some(reason.clone());
+
+            return Err(reason);
+        }
+
+        let resumed = match self.transfer {
+            Some((resumed_seq, ref resumed)) => {
+                resumed_seq == seq && resumed.len == transfer.len && resumed.hash == transfer.hash
+            },
+            None => false
+        };
+        if !resumed {
+            self.data = Vec::with_capacity(transfer.len as usize);
+        }
+        let len = transfer.len;
+        self.transfer = Some((seq, transfer));
+
+        try!(write_ack(s, seq, self.data.len() as u64));
+        progress(self.data.len() as u64, len);
+
+        while (self.data.len() as u64) < len {
+            let chunk: Chunk = try!(read_frame(s, MessageType::Chunk, seq, CHUNK_SIZE as u64 + 16));
+
+            if chunk.offset != self.data.len() as u64 {
+                return Err(format!("expected chunk at offset {}, received offset {}", self.data.len(), chunk.offset));
+            }
+            if chunk.data.is_empty() || self.data.len() + chunk.data.len() > len as usize {
+                return Err("chunk does not fit the message".into());
+            }
+
+            self.data.extend_from_slice(&chunk.data);
+
+            if self.data.len() as u64 == len && hash(&self.data) != self.transfer.as_ref().unwrap().1.hash {
+                // Start over if the sender tries again, unless it has
+                // already failed too often.
+                self.transfer = None;
+                self.data = vec![];
+                self.mismatches += 1;
+
+                let reason = format!("{:?} message does not match its hash", self.ty);
+                if self.mismatches >= MAX_MISMATCHES {
+                    self.refused = Some(format!("{} after {} attempts", reason, self.mismatches));
+                }
+
+                return Err(reason);
+            }
+
+            try!(write_ack(s, seq, self.data.len() as u64));
+            progress(self.data.len() as u64, len);
+        }
+
+        Ok(())
+    }
+
+    /// Set if `receive` failed because the sender announced a message
+    /// that is too large, or sent messages that did not match their hash
+    /// `MAX_MISMATCHES` times, which trying again will not change.
+    pub fn refused(&self) -> Option<&str> {
+        self.refused.as_ref().map(|reason| &reason[..])
+    }
+
+    /// The message as received so far.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Decodes the message once it has been received.
+    pub fn decode<T: Decodable>(&self) -> Result<T, String> {
+        let len = self.data.len() as u64;
+
+        match self.transfer {
+            Some((_, ref transfer)) if transfer.len == len => {},
+            _ => return Err(format!("{:?} message has not been received", self.ty))
+        }
+
+        let mut r = &self.data[..];
+        let obj = try!(decode_from(&mut r, Bounded(len))
+                       .map_err(|e| format!("could not decode {:?} message: {}", self.ty, e)));
+
+        if !r.is_empty() {
+            return Err(format!("{:?} message is longer than its contents", self.ty));
+        }
+
+        Ok(obj)
+    }
+}
+
+/// Decides when to report the progress of a transfer: when it starts,
+/// and after every tenth of it. Messages that fit in a single chunk are
+/// not worth reporting.
+pub struct Progress {
+    reported: Option<u64>
+}
+
+impl Progress {
+    pub fn new() -> Progress {
+        Progress {
+            reported: None
+        }
+    }
+
+    /// Returns the percentage to report, if any.
+    pub fn update(&mut self, done: u64, total: u64) -> Option<u64> {
+        if total <= CHUNK_SIZE as u64 {
+            return None;
+        }
+
+        let percent = done * 100 / total;
+
+        match self.reported {
+            Some(reported) if percent < reported + 10 && done != total => None,
+            Some(reported) if percent == reported => None,
+            _ => {
+                self.reported = Some(percent);
+
+                Some(percent)
+            }
+        }
+    }
+}
+
+#[test]
+fn frame_roundtrip() {
+    let msg = vec![1u64, 2, 3];
+
+    let mut buf = vec![];
+    write_frame(&mut buf, MessageType::IHash, 7, &msg).unwrap();
+    write_ack(&mut buf, 7, 32).unwrap();
+
+    {
+        let mut r = &buf[..];
+        assert_eq!(read_frame::<_, Vec<u64>>(&mut r, MessageType::IHash, 7, 1024).unwrap(), msg);
+        assert_eq!(read_ack(&mut r, 7).unwrap(), 32);
+        assert!(r.is_empty());
+    }
+
+    // The type, sequence number and length are checked.
+    assert!(read_frame::<_, Vec<u64>>(&mut &buf[..], MessageType::Stage1, 7, 1024).is_err());
+    assert!(read_frame::<_, Vec<u64>>(&mut &buf[..], MessageType::IHash, 8, 1024).is_err());
+    assert!(read_frame::<_, Vec<u64>>(&mut &buf[..], MessageType::IHash, 7, 16).is_err());
+
+    // So is the checksum.
+    let mut corrupted = buf.clone();
+    corrupted[HEADER_LEN + 10] ^= 1;
+    assert!(read_frame::<_, Vec<u64>>(&mut &corrupted[..], MessageType::IHash, 7, 1024).is_err());
+}
+
+#[test]
+fn resumed_transfer() {
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    let msg: Vec<u8> = (0..(2 * CHUNK_SIZE + 100)).map(|i| (i % 251) as u8).collect();
+    let outgoing = Outgoing::new(MessageType::Stage1, &msg).unwrap();
+    let len = outgoing.len();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let sender = thread::spawn(move || {
+        // The first connection drops after the first chunk has been
+        // acknowledged.
+        let (mut s, _) = listener.accept().unwrap();
+        let conn = s.try_clone().unwrap();
+        let mut acked = vec![];
+        let _ = outgoing.send(&mut s, 3, |done, _| {
+            acked.push(done);
+            if done > 0 {
+                let _ = conn.shutdown(::std::net::Shutdown::Both);
+            }
+        });
+        assert_eq!(acked, vec![0, CHUNK_SIZE as u64]);
+        drop(s);
+
+        let (mut s, _) = listener.accept().unwrap();
+        let mut acked = vec![];
+        outgoing.send(&mut s, 3, |done, _| acked.push(done)).unwrap();
+        assert_eq!(acked, vec![CHUNK_SIZE as u64, 2 * CHUNK_SIZE as u64, len]);
+    });
+
+    let mut incoming = Incoming::new(MessageType::Stage1, len);
+
+    let mut s = TcpStream::connect(addr).unwrap();
+    assert!(incoming.receive(&mut s, 3, |_, _| {}).is_err());
+    assert!(incoming.refused().is_none());
+
+    let mut s = TcpStream::connect(addr).unwrap();
+    incoming.receive(&mut s, 3, |_, _| {}).unwrap();
+
+    sender.join().unwrap();
+
+    assert!(incoming.decode::<Vec<u8>>().unwrap() == msg);
+
+    // Messages larger than the limit are refused.
+    let mut buf = vec![];
+    write_frame(&mut buf, MessageType::Transfer, 0, &Transfer { ty: MessageType::Stage1.tag(), len: 100, hash: [0; 32] }).unwrap();
+    let mut s = ::std::io::Cursor::new(buf);
+    let mut incoming = Incoming::new(MessageType::Stage1, 99);
+    assert!(incoming.receive(&mut s, 0, |_, _| {}).is_err());
+    assert!(incoming.refused().is_some());
+}
+
+#[test]
+fn mismatched_transfers() {
+    use std::io::Cursor;
+
+    struct Scripted {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>
+    }
+
+    impl Read for Scripted {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Scripted {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // The sender announces a hash the data it sends does not match, each
+    // time it tries.
+    let data = vec![1u8, 2, 3];
+    let mut buf = vec![];
+    for _ in 0..MAX_MISMATCHES {
+        write_frame(&mut buf, MessageType::Transfer, 5, &Transfer { ty: MessageType::Stage2.tag(), len: 3, hash: [0; 32] }).unwrap();
+        write_frame(&mut buf, MessageType::Chunk, 5, &Chunk { offset: 0, data: data.clone() }).unwrap();
+    }
+    let mut s = Scripted { input: Cursor::new(buf), output: vec![] };
+
+    let mut incoming = Incoming::new(MessageType::Stage2, 1024);
+    for _ in 1..MAX_MISMATCHES {
+        assert!(incoming.receive(&mut s, 5, |_, _| {}).is_err());
+        assert!(incoming.refused().is_none());
+    }
+
+    // The last attempt is refused, so that the player can be rejected
+    // rather than asked again.
+    assert!(incoming.receive(&mut s, 5, |_, _| {}).is_err());
+    assert!(incoming.refused().unwrap().contains("does not match its hash"));
+}